@@ -0,0 +1,35 @@
+extern crate futures;
+extern crate tokio;
+extern crate mqtt_codec;
+
+use futures::{future, stream, FutureExt, StreamExt, TryStreamExt};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+
+use mqtt_codec::codec::MqttCodec;
+
+fn main(){
+    let runtime = Runtime::new().unwrap();
+
+    let server = TcpListener::bind("0.0.0.0:1883").then(|listener| {
+        let listener = listener.expect("failed to bind 0.0.0.0:1883");
+        stream::poll_fn(move |cx| listener.poll_accept(cx).map(Some)).for_each(|accepted| {
+            match accepted {
+                Ok((socket, addr)) => {
+                    let (sink, packets) = MqttCodec::new().framed(socket).split();
+                    let echo = packets
+                        .inspect_ok(|packet| println!("packet {:?}", packet))
+                        .forward(sink)
+                        .map(move |result| if let Err(err) = result {
+                            println!("{}: {}", addr, err);
+                        });
+                    tokio::spawn(echo);
+                },
+                Err(err) => println!("accept failed: {}", err),
+            }
+            future::ready(())
+        })
+    });
+
+    runtime.block_on(server)
+}
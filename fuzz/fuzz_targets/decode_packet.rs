@@ -0,0 +1,33 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate bytes;
+extern crate mqtt_codec;
+
+use bytes::BytesMut;
+use mqtt_codec::{Decodable, ProtocolVersion};
+use mqtt_codec::blocking::read_packet_with_config;
+use mqtt_codec::packet::{DecoderConfig, PacketDecoder, StreamingDecoder, ValuePacket, ValuePacketRef};
+
+fuzz_target!(|data: &[u8]| {
+    for protocol_version in &[ProtocolVersion::V31, ProtocolVersion::V311, ProtocolVersion::V5] {
+        let config = DecoderConfig { protocol_version: *protocol_version, ..DecoderConfig::default() };
+        let _ = ValuePacket::decode_with(&mut BytesMut::from(data), &config.context());
+
+        let mut bytes = BytesMut::from(data);
+        let mut decoder = PacketDecoder::with_config(config.clone());
+        while let Ok(Some(_)) = decoder.decode(&mut bytes) {}
+
+        let mut bytes = BytesMut::from(data);
+        let mut decoder = StreamingDecoder::with_config(config.clone());
+        while let Ok(Some(_)) = decoder.decode(&mut bytes) {}
+
+        let mut offset = 0;
+        while let Ok(Some((_, length))) = ValuePacketRef::decode_with(&data[offset..], &config.context()) {
+            offset += length;
+        }
+
+        let mut reader = data;
+        while read_packet_with_config(&mut reader, config.clone()).is_ok() {}
+    }
+});
@@ -0,0 +1,220 @@
+use std::io::{self, IoSlice, Read, Write};
+use bytes::{Buf, BytesMut};
+
+use EncodeContext;
+use packet::{PacketDecoder, DecoderConfig, ValuePacket, VectoredPacket};
+
+error_chain!{
+    types {
+        BlockingError, ErrorKind, ResultExt, BlockingResult;
+    }
+
+    links {
+        DecodeError(::packet::DecodeError, ::packet::DecodeErrorKind);
+        ValuePacketError(::packet::ValuePacketError, ::packet::ValuePacketErrorKind);
+    }
+
+    foreign_links {
+        Io(::std::io::Error);
+    }
+}
+
+/// Reads exactly one packet from `reader`, using the default `DecoderConfig`.
+///
+/// The fixed header is read a byte at a time and then the body it announces in
+/// one go, so nothing past the end of the packet is consumed. End of stream
+/// inside a packet is reported as an `UnexpectedEof` I/O error.
+pub fn read_packet<R: Read + ?Sized>(reader: &mut R) -> Result<ValuePacket, BlockingError> {
+    read_packet_with_config(reader, DecoderConfig::default())
+}
+
+/// Like `read_packet`, enforcing the limits of `config`.
+pub fn read_packet_with_config<R: Read + ?Sized>(reader: &mut R, config: DecoderConfig) -> Result<ValuePacket, BlockingError> {
+    let mut decoder = PacketDecoder::with_config(config);
+    let mut buf = BytesMut::new();
+    loop {
+        let needed = decoder.bytes_needed(&buf)?;
+        if needed == 0 {
+            break;
+        }
+        let start = buf.len();
+        buf.resize(start + needed, 0);
+        reader.read_exact(&mut buf[start..])?;
+    }
+
+    match decoder.decode(&mut buf)? {
+        Some(packet) => {
+            trace!("read packet {:?}", packet);
+            Ok(packet)
+        },
+        None => unreachable!("bytes_needed reported a whole frame"),
+    }
+}
+
+/// Writes the encoded `packet` to `writer` in full, for the protocol version
+/// the packet was built or decoded for. The writer is not flushed.
+///
+/// The headers and a PUBLISH payload are passed to `write_vectored` as separate
+/// buffers, so the payload is not copied.
+pub fn write_packet<W: Write + ?Sized>(writer: &mut W, packet: &ValuePacket) -> Result<(), BlockingError> {
+    write_vectored(writer, packet, packet.encode_vectored()?)
+}
+
+/// Like `write_packet`, refusing a packet that does not fit `ctx`.
+pub fn write_packet_with<W: Write + ?Sized>(writer: &mut W, packet: &ValuePacket, ctx: &EncodeContext) -> Result<(), BlockingError> {
+    write_vectored(writer, packet, packet.encode_vectored_with(ctx)?)
+}
+
+fn write_vectored<W: Write + ?Sized>(writer: &mut W, packet: &ValuePacket, mut buf: VectoredPacket) -> Result<(), BlockingError> {
+    while buf.has_remaining() {
+        let result = {
+            let mut slices = [IoSlice::new(&[]); 2];
+            let n = buf.chunks_vectored(&mut slices);
+            writer.write_vectored(&slices[..n])
+        };
+        match result {
+            Ok(0) => bail!(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole packet")),
+            Ok(n) => buf.advance(n),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err.into()),
+        }
+    }
+    trace!("wrote packet {:?}", packet);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+    use {Encodable, ProtocolVersion, PubAckReasonCode};
+    use packet::{PingReq, Properties, PubAck, Publish, Subscribe, ValuePacketErrorKind};
+    use qos::QualityOfService;
+
+    #[test]
+    fn test_read_write_packet() {
+        let publish = ValuePacket::PublishPacket(Publish::builder().topic_name("a/b").payload(vec![0u8; 200]).build().unwrap());
+        let subscribe = ValuePacket::SubscribePacket(Subscribe::builder().packet_identifier(1).subscribe("a/#", QualityOfService::Level1).build().unwrap());
+        let pingreq = ValuePacket::PingReqPacket(PingReq::builder().build().unwrap());
+
+        let mut vec = Vec::new();
+        write_packet(&mut vec, &publish).unwrap();
+        write_packet(&mut vec, &subscribe).unwrap();
+        write_packet(&mut vec, &pingreq).unwrap();
+
+        let mut reader = &vec[..];
+        match read_packet(&mut reader).unwrap() {
+            ValuePacket::PublishPacket(publish) => assert_eq!(publish.payload().len(), 200),
+            other => panic!("unexpected {:?}", other),
+        }
+        match read_packet(&mut reader).unwrap() {
+            ValuePacket::SubscribePacket(subscribe) => assert_eq!(subscribe.packet_identifier(), 1),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(reader, &[0xC0, 0x00]);
+        match read_packet(&mut reader).unwrap() {
+            ValuePacket::PingReqPacket(_) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match read_packet(&mut reader) {
+            Err(BlockingError(ErrorKind::Io(ref err), _)) if err.kind() == io::ErrorKind::UnexpectedEof => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    struct ShortWriter {
+        written: Vec<u8>,
+        vectored_calls: usize,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = ::std::cmp::min(buf.len(), 7);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            if bufs.len() > 1 {
+                self.vectored_calls += 1;
+            }
+            let mut n = 0;
+            for buf in bufs {
+                let written = self.write(&buf[..::std::cmp::min(buf.len(), 7 - n)])?;
+                n += written;
+                if n == 7 {
+                    break;
+                }
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_packet_vectored() {
+        let publish = Publish::builder().topic_name("a/b").payload(vec![0xA5u8; 100]).build().unwrap();
+        let encoded = publish.encode().unwrap();
+        let mut writer = ShortWriter { written: Vec::new(), vectored_calls: 0 };
+        write_packet(&mut writer, &ValuePacket::PublishPacket(publish)).unwrap();
+        assert_eq!(writer.written, encoded);
+        assert!(writer.vectored_calls > 0);
+
+        let mut full = [0u8; 4];
+        let err = write_packet(&mut &mut full[..], &ValuePacket::PublishPacket(Publish::builder().topic_name("a/b").build().unwrap())).unwrap_err();
+        match *err.kind() {
+            ErrorKind::Io(ref err) => assert_eq!(err.kind(), io::ErrorKind::WriteZero),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_write_packet_v5() {
+        let puback = ValuePacket::PubAckPacket(PubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1)
+                                                   .reason_code(PubAckReasonCode::NoMatchingSubscribers).build().unwrap());
+        let subscribe = ValuePacket::SubscribePacket(Subscribe::builder().protocol_version(ProtocolVersion::V5).packet_identifier(2)
+                                                         .subscribe("a/#", QualityOfService::Level1).build().unwrap());
+
+        let mut vec = Vec::new();
+        write_packet(&mut vec, &puback).unwrap();
+        write_packet_with(&mut vec, &subscribe, &EncodeContext::new(ProtocolVersion::V5)).unwrap();
+
+        let config = DecoderConfig {
+            protocol_version: ProtocolVersion::V5,
+            ..DecoderConfig::default()
+        };
+        let mut reader = &vec[..];
+        match read_packet_with_config(&mut reader, config.clone()).unwrap() {
+            ValuePacket::PubAckPacket(puback) => assert_eq!(puback.reason_code(), PubAckReasonCode::NoMatchingSubscribers),
+            other => panic!("unexpected {:?}", other),
+        }
+        match read_packet_with_config(&mut reader, config).unwrap() {
+            ValuePacket::SubscribePacket(subscribe) => assert_eq!(subscribe.properties(), Some(&Properties::new())),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(reader.is_empty());
+
+        match write_packet_with(&mut Vec::new(), &puback, &EncodeContext::default()) {
+            Err(BlockingError(ErrorKind::ValuePacketError(ValuePacketErrorKind::ProtocolVersionMismatch(_, _)), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_packet_limits() {
+        let config = DecoderConfig {
+            max_packet_size: 16,
+            ..DecoderConfig::default()
+        };
+        let mut reader = &[0x30, 0x7F, 0x00][..];
+        match read_packet_with_config(&mut reader, config) {
+            Err(BlockingError(ErrorKind::DecodeError(_), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(reader, &[0x00]);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{fmt, str};
+use std::borrow::Borrow;
+use std::ops::Deref;
+use bytes::{Bytes, BytesMut, Buf, BufMut};
+
+use {Encodable, Decodable, PacketError, ErrorKind, DecodeContext, EncodeContext, check_string};
+
+/// UTF-8 string stored in a reference counted `Bytes` buffer.
+///
+/// Decoding slices the string out of the input buffer instead of copying it,
+/// and cloning only bumps a reference count. The contents are validated once,
+/// on construction.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ByteStr(Bytes);
+
+impl ByteStr {
+    pub fn from_utf8(bytes: Bytes) -> Result<ByteStr, str::Utf8Error> {
+        str::from_utf8(&bytes)?;
+        Ok(ByteStr(bytes))
+    }
+
+    pub fn from_static(string: &'static str) -> ByteStr {
+        ByteStr(Bytes::from_static(string.as_bytes()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.0) }
+    }
+
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+}
+
+impl Deref for ByteStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for ByteStr {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for ByteStr {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<String> for ByteStr {
+    fn from(string: String) -> ByteStr {
+        ByteStr(Bytes::from(string))
+    }
+}
+
+impl<'a> From<&'a str> for ByteStr {
+    fn from(string: &'a str) -> ByteStr {
+        ByteStr(Bytes::copy_from_slice(string.as_bytes()))
+    }
+}
+
+impl PartialEq<str> for ByteStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for ByteStr {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for ByteStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for ByteStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<'a> Decodable<'a> for ByteStr {
+    type Error = PacketError;
+
+    fn decode_with(bytes: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        if bytes.len() < 2 {
+            bail!(ErrorKind::NoEnoughBytesToDecode)
+        }
+        let size = (&bytes[..]).get_u16() as usize;
+        if bytes.len() < size + 2 {
+            bail!(ErrorKind::NoEnoughBytesToDecode)
+        }
+
+        bytes.advance(2);
+        let string = ByteStr::from_utf8(bytes.split_to(size).freeze())?;
+        check_string(&string, ctx)?;
+        Ok(string)
+    }
+}
+
+impl Encodable for ByteStr {
+    type Error = PacketError;
+
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+        let length = self.encode_length()? - 2;
+        dst.put_u16(length as u16);
+        dst.put_slice(&self.0);
+        Ok(())
+    }
+
+    fn encode_length(&self) -> Result<u32, Self::Error> {
+        if self.0.len() > u16::MAX as usize {
+            bail!(ErrorKind::StringTooLong(self.0.len()))
+        }
+        Ok(2 + self.0.len() as u32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_byte_str_decode_shares_input() {
+        let mut bytes = BytesMut::from(&[0x00, 0x03, b'a', b'/', b'b', 0xFF][..]);
+        let start = bytes.as_ptr() as usize;
+        let topic = ByteStr::decode(&mut bytes).unwrap();
+        assert_eq!(topic, "a/b");
+        assert_eq!(topic.as_bytes().as_ptr() as usize, start + 2);
+        assert_eq!(&bytes[..], &[0xFF]);
+        assert_eq!(topic.encode().unwrap(), vec![0x00, 0x03, b'a', b'/', b'b']);
+
+        let mut invalid = BytesMut::from(&[0x00, 0x02, 0xC3, 0x28][..]);
+        assert!(ByteStr::decode(&mut invalid).is_err());
+        let mut short = BytesMut::from(&[0x00, 0x04, b'a'][..]);
+        assert!(ByteStr::decode(&mut short).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use std::io::{self, IoSlice};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use {Encodable, EncodeContext, ProtocolVersion};
+use packet::{PacketDecoder, DecoderConfig, StreamingDecoder, StreamingItem, ValuePacket, ValuePacketError, VectoredPacket};
+
+error_chain!{
+    types {
+        CodecError, ErrorKind, ResultExt, CodecResult;
+    }
+
+    links {
+        DecodeError(::packet::DecodeError, ::packet::DecodeErrorKind);
+        ValuePacketError(::packet::ValuePacketError, ::packet::ValuePacketErrorKind);
+    }
+
+    foreign_links {
+        Io(::std::io::Error);
+    }
+}
+
+impl From<CodecError> for io::Error {
+    fn from(err: CodecError) -> io::Error {
+        let kind = match *err.kind() {
+            ErrorKind::Io(ref err) => err.kind(),
+            ErrorKind::DecodeError(_) => io::ErrorKind::InvalidData,
+            ErrorKind::ValuePacketError(_) => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::Other,
+        };
+        match err {
+            CodecError(ErrorKind::Io(err), _) => err,
+            err => io::Error::new(kind, err.to_string()),
+        }
+    }
+}
+
+/// `tokio-util` `Decoder`/`Encoder` pair framing `ValuePacket`s over a byte
+/// stream.
+///
+/// Decoding is delegated to `PacketDecoder`, so the limits of the given
+/// `DecoderConfig` are enforced before a frame is buffered. Errors keep their
+/// `DecodeError` kind; transports that need an `io::Error` can convert with
+/// `From`, which maps malformed input to `InvalidData` and packets that fail to
+/// encode to `InvalidInput`.
+#[derive(Debug, Default)]
+pub struct MqttCodec {
+    decoder: PacketDecoder,
+}
+
+impl MqttCodec {
+    pub fn new() -> MqttCodec {
+        MqttCodec::with_config(DecoderConfig::default())
+    }
+
+    pub fn with_config(config: DecoderConfig) -> MqttCodec {
+        MqttCodec {
+            decoder: PacketDecoder::with_config(config),
+        }
+    }
+
+    pub fn config(&self) -> &DecoderConfig {
+        self.decoder.config()
+    }
+
+    pub fn set_protocol_version(&mut self, protocol_version: ProtocolVersion) {
+        self.decoder.set_protocol_version(protocol_version);
+    }
+
+    /// Wraps `io` into a `Stream` + `Sink` of packets using this codec.
+    pub fn framed<T: AsyncRead + AsyncWrite>(self, io: T) -> Framed<T, MqttCodec> {
+        Framed::new(io, self)
+    }
+}
+
+impl Decoder for MqttCodec {
+    type Item = ValuePacket;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decoder.decode(src) {
+            Ok(Some(packet)) => {
+                trace!("decoded packet {:?}", packet);
+                Ok(Some(packet))
+            },
+            Ok(None) => Ok(None),
+            Err(err) => {
+                debug!("failed to decode packet: {}", err);
+                Err(err.into())
+            },
+        }
+    }
+}
+
+impl Decoder for StreamingDecoder {
+    type Item = StreamingItem;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match StreamingDecoder::decode(self, src) {
+            Ok(item) => Ok(item),
+            Err(err) => {
+                debug!("failed to decode packet: {}", err);
+                Err(err.into())
+            },
+        }
+    }
+}
+
+impl<'a> Encoder<&'a ValuePacket> for MqttCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: &'a ValuePacket, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let result = item.encode_length().and_then(|length| {
+            dst.reserve(length as usize);
+            item.encode_to_with(dst, &EncodeContext::new(self.config().protocol_version))
+        });
+        match result {
+            Ok(()) => {
+                trace!("encoded packet {:?}", item);
+                Ok(())
+            },
+            Err(err) => {
+                debug!("failed to encode packet {:?}: {}", item, err);
+                Err(err.into())
+            },
+        }
+    }
+}
+
+impl Encoder<ValuePacket> for MqttCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: ValuePacket, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(&item, dst)
+    }
+}
+
+/// Reads exactly one packet from `reader`, using the default `DecoderConfig`.
+///
+/// Only the bytes of that packet are read, so the reader can be handed to
+/// another consumer afterwards. End of stream before a whole packet has been
+/// read is reported as an `UnexpectedEof` I/O error.
+pub fn read_packet<'a, R>(reader: &'a mut R) -> ReadPacket<'a, R>
+    where R: AsyncRead + Unpin + ?Sized
+{
+    read_packet_with_config(reader, DecoderConfig::default())
+}
+
+/// Like `read_packet`, enforcing the limits of `config`.
+pub fn read_packet_with_config<'a, R>(reader: &'a mut R, config: DecoderConfig) -> ReadPacket<'a, R>
+    where R: AsyncRead + Unpin + ?Sized
+{
+    ReadPacket {
+        reader,
+        decoder: PacketDecoder::with_config(config),
+        buf: BytesMut::new(),
+    }
+}
+
+/// Writes the encoded `packet` to `writer` in full, for the protocol version
+/// the packet was built or decoded for. The writer is not flushed.
+///
+/// The headers and a PUBLISH payload are passed to `poll_write_vectored` as
+/// separate buffers, so the payload is not copied.
+pub fn write_packet<'a, W>(writer: &'a mut W, packet: &ValuePacket) -> WritePacket<'a, W>
+    where W: AsyncWrite + Unpin + ?Sized
+{
+    write_encoded(writer, packet, packet.encode_vectored())
+}
+
+/// Like `write_packet`, refusing a packet that does not fit `ctx`.
+pub fn write_packet_with<'a, W>(writer: &'a mut W, packet: &ValuePacket, ctx: &EncodeContext) -> WritePacket<'a, W>
+    where W: AsyncWrite + Unpin + ?Sized
+{
+    write_encoded(writer, packet, packet.encode_vectored_with(ctx))
+}
+
+fn write_encoded<'a, W>(writer: &'a mut W, packet: &ValuePacket, buf: Result<VectoredPacket, ValuePacketError>) -> WritePacket<'a, W>
+    where W: AsyncWrite + Unpin + ?Sized
+{
+    let buf = buf.map_err(CodecError::from);
+    if let Err(ref err) = buf {
+        debug!("failed to encode packet {:?}: {}", packet, err);
+    }
+    WritePacket {
+        writer,
+        buf: Some(buf),
+    }
+}
+
+/// Future returned by `read_packet`.
+#[derive(Debug)]
+pub struct ReadPacket<'a, R: ?Sized + 'a> {
+    reader: &'a mut R,
+    decoder: PacketDecoder,
+    buf: BytesMut,
+}
+
+impl<'a, R> Future for ReadPacket<'a, R>
+    where R: AsyncRead + Unpin + ?Sized
+{
+    type Output = Result<ValuePacket, CodecError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let me = &mut *self;
+        loop {
+            let needed = me.decoder.bytes_needed(&me.buf)?;
+            if needed == 0 {
+                break;
+            }
+
+            let start = me.buf.len();
+            me.buf.resize(start + needed, 0);
+            let (result, n) = {
+                let mut read_buf = ReadBuf::new(&mut me.buf[start..]);
+                let result = Pin::new(&mut *me.reader).poll_read(cx, &mut read_buf);
+                (result, read_buf.filled().len())
+            };
+            me.buf.truncate(start + n);
+
+            match result {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+                Poll::Ready(Ok(())) if n == 0 => {
+                    let err = io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended inside a packet");
+                    return Poll::Ready(Err(err.into()));
+                },
+                Poll::Ready(Ok(())) => {},
+            }
+        }
+
+        match me.decoder.decode(&mut me.buf)? {
+            Some(packet) => {
+                trace!("read packet {:?}", packet);
+                Poll::Ready(Ok(packet))
+            },
+            None => unreachable!("bytes_needed reported a whole frame"),
+        }
+    }
+}
+
+/// Future returned by `write_packet`.
+#[derive(Debug)]
+pub struct WritePacket<'a, W: ?Sized + 'a> {
+    writer: &'a mut W,
+    buf: Option<Result<VectoredPacket, CodecError>>,
+}
+
+impl<'a, W> Future for WritePacket<'a, W>
+    where W: AsyncWrite + Unpin + ?Sized
+{
+    type Output = Result<(), CodecError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let me = &mut *self;
+        let mut buf = match me.buf.take() {
+            Some(Ok(buf)) => buf,
+            Some(Err(err)) => return Poll::Ready(Err(err)),
+            None => panic!("WritePacket polled after completion"),
+        };
+
+        while buf.has_remaining() {
+            let result = {
+                let mut slices = [IoSlice::new(&[]); 2];
+                let n = buf.chunks_vectored(&mut slices);
+                Pin::new(&mut *me.writer).poll_write_vectored(cx, &slices[..n])
+            };
+            match result {
+                Poll::Pending => {
+                    me.buf = Some(Ok(buf));
+                    return Poll::Pending;
+                },
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+                Poll::Ready(Ok(0)) => {
+                    let err = io::Error::new(io::ErrorKind::WriteZero, "failed to write whole packet");
+                    return Poll::Ready(Err(err.into()));
+                },
+                Poll::Ready(Ok(n)) => buf.advance(n),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::executor::block_on;
+    use PubAckReasonCode;
+    use packet::{Disconnect, PingReq, PubAck, Publish, DecodeError, DecodeErrorKind};
+
+    #[test]
+    fn test_codec_encode_decode() {
+        let mut codec = MqttCodec::new();
+        let mut bytes = BytesMut::new();
+        let publish = Publish::builder().topic_name("a/b").payload(vec![1, 2, 3]).build().unwrap();
+        codec.encode(ValuePacket::PublishPacket(publish), &mut bytes).unwrap();
+        codec.encode(&ValuePacket::PingReqPacket(PingReq::builder().build().unwrap()), &mut bytes).unwrap();
+
+        let mut partial = bytes.split_to(3);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        partial.unsplit(bytes);
+        let mut bytes = partial;
+
+        match codec.decode(&mut bytes).unwrap() {
+            Some(ValuePacket::PublishPacket(publish)) => assert_eq!(publish.payload(), &[1, 2, 3]),
+            other => panic!("unexpected {:?}", other),
+        }
+        match codec.decode(&mut bytes).unwrap() {
+            Some(ValuePacket::PingReqPacket(_)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(codec.decode(&mut bytes).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_codec_v5() {
+        let mut codec = MqttCodec::new();
+        codec.set_protocol_version(ProtocolVersion::V5);
+        let mut bytes = BytesMut::new();
+        let puback = PubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1)
+                            .reason_code(PubAckReasonCode::NoMatchingSubscribers).build().unwrap();
+        codec.encode(ValuePacket::PubAckPacket(puback), &mut bytes).unwrap();
+        match codec.decode(&mut bytes).unwrap() {
+            Some(ValuePacket::PubAckPacket(puback)) => assert_eq!(puback.reason_code(), PubAckReasonCode::NoMatchingSubscribers),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let disconnect = ValuePacket::DisconnecPacket(Disconnect::builder().protocol_version(ProtocolVersion::V5).build().unwrap());
+        assert!(MqttCodec::new().encode(&disconnect, &mut bytes).is_err());
+
+        let mut vec = Vec::new();
+        block_on(write_packet(&mut vec, &disconnect)).unwrap();
+        let err = block_on(write_packet_with(&mut vec, &disconnect, &EncodeContext::default())).unwrap_err();
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidInput);
+
+        let config = DecoderConfig {
+            protocol_version: ProtocolVersion::V5,
+            ..DecoderConfig::default()
+        };
+        let mut reader = &vec[..];
+        match block_on(read_packet_with_config(&mut reader, config)).unwrap() {
+            ValuePacket::DisconnecPacket(disconnect) => assert!(disconnect.properties().is_some()),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_codec_error_mapping() {
+        let mut codec = MqttCodec::with_config(DecoderConfig {
+            max_packet_size: 16,
+            ..DecoderConfig::default()
+        });
+
+        let mut bytes = BytesMut::from(&[0x30, 0x20][..]);
+        let err = codec.decode(&mut bytes).unwrap_err();
+        match *err.kind() {
+            ErrorKind::DecodeError(DecodeErrorKind::PacketTooLarge(34, 16)) => {},
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidData);
+
+        let err = CodecError::from(DecodeError::from_kind(DecodeErrorKind::MalformedPacket("x".into())));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidData);
+
+        let err = CodecError::from(io::Error::new(io::ErrorKind::UnexpectedEof, "eof"));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_write_packet() {
+        let publish = ValuePacket::PublishPacket(Publish::builder().topic_name("a/b").payload(vec![0u8; 200]).build().unwrap());
+        let pingreq = ValuePacket::PingReqPacket(PingReq::builder().build().unwrap());
+
+        let mut vec = Vec::new();
+        block_on(write_packet(&mut vec, &publish)).unwrap();
+        block_on(write_packet(&mut vec, &pingreq)).unwrap();
+
+        let mut reader = &vec[..];
+        match block_on(read_packet(&mut reader)).unwrap() {
+            ValuePacket::PublishPacket(publish) => assert_eq!(publish.payload().len(), 200),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(reader, &[0xC0, 0x00]);
+        match block_on(read_packet(&mut reader)).unwrap() {
+            ValuePacket::PingReqPacket(_) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let err = block_on(read_packet(&mut reader)).unwrap_err();
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut reader = &vec[..10];
+        let err = block_on(read_packet(&mut reader)).unwrap_err();
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
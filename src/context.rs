@@ -0,0 +1,64 @@
+use ProtocolVersion;
+use packet::MAX_REMAINING_LENGTH;
+
+/// Settings every `Decodable` impl receives, from `ValuePacket` down to the
+/// field codecs.
+///
+/// `protocol_version` selects the packet layouts; CONNECT names its own level
+/// and ignores it. `max_string_length` bounds every UTF-8 string and binary
+/// field as it is read. `strict` rejects input the specification forbids but
+/// that is harmless to decode, such as U+0000 inside a UTF-8 string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeContext {
+    pub protocol_version: ProtocolVersion,
+    pub max_string_length: usize,
+    pub strict: bool,
+}
+
+impl DecodeContext {
+    pub fn new(protocol_version: ProtocolVersion) -> DecodeContext {
+        DecodeContext {
+            protocol_version,
+            ..DecodeContext::default()
+        }
+    }
+}
+
+impl Default for DecodeContext {
+    fn default() -> DecodeContext {
+        DecodeContext {
+            protocol_version: ProtocolVersion::V311,
+            max_string_length: u16::MAX as usize,
+            strict: false,
+        }
+    }
+}
+
+/// Settings every `Encodable` impl receives.
+///
+/// Packets carry their own layout, so `protocol_version` only has to agree
+/// with it: `ValuePacket` refuses to encode a packet built for another
+/// version, and one whose encoding exceeds `max_packet_size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeContext {
+    pub protocol_version: ProtocolVersion,
+    pub max_packet_size: usize,
+}
+
+impl EncodeContext {
+    pub fn new(protocol_version: ProtocolVersion) -> EncodeContext {
+        EncodeContext {
+            protocol_version,
+            ..EncodeContext::default()
+        }
+    }
+}
+
+impl Default for EncodeContext {
+    fn default() -> EncodeContext {
+        EncodeContext {
+            protocol_version: ProtocolVersion::V311,
+            max_packet_size: 5 + MAX_REMAINING_LENGTH as usize,
+        }
+    }
+}
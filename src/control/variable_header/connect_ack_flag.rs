@@ -1,27 +1,25 @@
+use {DecodeContext, EncodeContext, ProtocolVersion};
 use Encodable; 
 use Decodable;
-use bytes::BytesMut;
+use bytes::{BytesMut, Buf, BufMut};
 
 error_chain!{
     types{
         ConnectAckFlagsError, ErrorKind, ResultExt, ConnectAckFlagsResult;
     }
 }
+/// Session present flag; MQTT 3.1 reserves the byte, so it reads as `false`.
 #[derive(Debug)]
 pub struct ConnectAckFlags(pub bool);
 
 impl<'a> Decodable<'a> for ConnectAckFlags {
     type Error = ConnectAckFlagsError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error>{
         let len = byte.len();
         if len >= 1{
-            let session_present = if byte[0] & 0x01 == 0x01 {
-                true
-            }else {
-                false
-            };
+            let session_present = byte[0] & 0x01 == 0x01 && ctx.protocol_version != ProtocolVersion::V31;
+            byte.advance(1);
             Ok(ConnectAckFlags(session_present))
         } else {
             bail!("no enough byte to decode connect_ack_flag");
@@ -31,16 +29,14 @@ impl<'a> Decodable<'a> for ConnectAckFlags {
 
 impl Encodable for ConnectAckFlags{
     type Error = ConnectAckFlagsError;
-    type Cond = ();
 
-    fn encode_with(&self, _: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let mut vec = vec![];
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
         if self.0 {
-            vec.push(1);
+            dst.put_u8(1);
         }else{
-            vec.push(0);
+            dst.put_u8(0);
         }
-        Ok(vec)
+        Ok(())
     }
 
 
@@ -59,7 +55,7 @@ mod tests{
     #[test]
     fn test_encode_decode_connect_ack_flag(){
         let vec = vec![0u8];
-        let mut bytes = BytesMut::from(vec);
+        let mut bytes = BytesMut::from(&vec[..]);
         let caf = ConnectAckFlags::decode(&mut bytes);
         let result = caf.unwrap().encode();
     }
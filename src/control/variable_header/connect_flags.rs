@@ -1,7 +1,9 @@
+use {DecodeContext, EncodeContext};
 use PacketError;
-use bytes::BytesMut;
+use bytes::{BytesMut, Buf, BufMut};
 use Decodable;
 use Encodable;
+use qos::QualityOfService;
 
 error_chain!{
     types {
@@ -9,15 +11,15 @@ error_chain!{
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ConnectFlags {
-    pub user_name_flag: bool,
-    pub password_flag: bool,
-    pub will_retain: bool,
-    pub will_QoS: u8,
-    pub will_flag: bool,
-    pub clean_session: bool,
-    pub reserved: bool,
+    pub(crate) user_name_flag: bool,
+    pub(crate) password_flag: bool,
+    pub(crate) will_retain: bool,
+    pub(crate) will_qos: u8,
+    pub(crate) will_flag: bool,
+    pub(crate) clean_session: bool,
+    pub(crate) reserved: bool,
 }
 
 impl ConnectFlags{
@@ -26,23 +28,51 @@ impl ConnectFlags{
             user_name_flag: false,
             password_flag: false,
             will_retain: false,
-            will_QoS: 0,
+            will_qos: 0,
             will_flag: false,
             clean_session: false,
             reserved: false,
         }
     }
+
+    pub fn user_name_flag(&self) -> bool {
+        self.user_name_flag
+    }
+
+    pub fn password_flag(&self) -> bool {
+        self.password_flag
+    }
+
+    pub fn will_retain(&self) -> bool {
+        self.will_retain
+    }
+
+    pub fn will_qos(&self) -> QualityOfService {
+        QualityOfService::from_u8(self.will_qos).unwrap_or(QualityOfService::Level0)
+    }
+
+    pub fn will_flag(&self) -> bool {
+        self.will_flag
+    }
+
+    pub fn clean_session(&self) -> bool {
+        self.clean_session
+    }
+
+    /// Bit 0, which MQTT requires to be zero.
+    pub fn reserved(&self) -> bool {
+        self.reserved
+    }
 }
 
 
 impl<'a> Decodable<'a> for ConnectFlags {
     type Error = ConnectFlagsError;
-    type Cond = ();
-    fn decode_with(bytes: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(bytes: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
         let len = bytes.len();
         if len >= 1 {
             let byte = bytes[0];
-            bytes.split_to(1);
+            bytes.advance(1);
 
             let user_name_flag = if byte >> 7 & 0x01 == 0x01 {
                     true
@@ -61,9 +91,12 @@ impl<'a> Decodable<'a> for ConnectFlags {
                 } else{
                     false
             };
-            let will_QoS = {
+            let will_qos = {
                 byte >> 3 & 0x03
             };
+            if will_qos > 2 {
+                bail!("connect flag will qos is 3");
+            }
             let will_flag = 
                 if byte >> 2 & 0x01 == 0x01 {
                     true
@@ -88,7 +121,7 @@ impl<'a> Decodable<'a> for ConnectFlags {
                     password_flag: password_flag,
                     will_flag: will_flag,
                     will_retain: will_retain,
-                    will_QoS: will_QoS,
+                    will_qos,
                     clean_session: clean_session,
                     reserved: reserved,
             };
@@ -104,8 +137,7 @@ impl<'a> Decodable<'a> for ConnectFlags {
 
 impl Encodable for ConnectFlags{
     type Error = ConnectFlagsError;
-    type Cond = ();
-    fn encode_with(&self, _cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
         let mut connect_flag = 0u8;
         if self.user_name_flag {
             connect_flag |= 0x01;
@@ -119,7 +151,7 @@ impl Encodable for ConnectFlags{
             connect_flag |= 0x01;
         };
         connect_flag = connect_flag << 2;
-        connect_flag |= self.will_QoS;
+        connect_flag |= self.will_qos;
         connect_flag = connect_flag << 1;
         if self.will_flag {
             connect_flag |= 0x01;
@@ -132,7 +164,8 @@ impl Encodable for ConnectFlags{
         if self.reserved {
             connect_flag |= 0x01;
         };
-        Ok(vec![connect_flag])
+        dst.put_u8(connect_flag);
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, ConnectFlagsError> {
@@ -1,6 +1,7 @@
+use {DecodeContext, EncodeContext};
 use Decodable;
 use Encodable;
-use bytes::BytesMut;
+use bytes::{BytesMut, BufMut};
 
 #[derive(Debug)]
 pub struct ConnectReturnCode(pub u8);
@@ -13,19 +14,18 @@ error_chain!{
 
 impl<'a> Decodable<'a> for ConnectReturnCode {
     type Error = ConnectReturnCodeError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        Ok(ConnectReturnCode(Decodable::decode(byte).chain_err(||"decode connect return code error")?))
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        Ok(ConnectReturnCode(Decodable::decode_with(byte, ctx).chain_err(||"decode connect return code error")?))
     }
 }
 
 impl Encodable for ConnectReturnCode{
     type Error = ConnectReturnCodeError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        Ok(vec![self.0])
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+        dst.put_u8(self.0);
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
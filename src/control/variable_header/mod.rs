@@ -7,7 +7,6 @@ pub use self::keep_alive::KeepAlive;
 pub use self::will_message::{VecBytesError, VecBytes, ErrorKind as VecBytesErrorKind};
 pub use self::connect_ack_flag::{ConnectAckFlags, ConnectAckFlagsError, ErrorKind as ConnectAckFlagsErrorKind};
 pub use self::connect_return_code::{ConnectReturnCode,ConnectReturnCodeError, ErrorKind as ConnectReturnCodeErrorKind};
-pub use self::topic_name::{TopicName, TopicNameError, ErrorKind as TopicNameErrorKind};
 pub use self::packet_identifier::{PacketIdentifier, PacketIdentifierError, ErrorKind as PacketIdentifierErrorKind};
 
 
@@ -18,5 +17,4 @@ mod will_message;
 mod keep_alive;
 mod connect_ack_flag;
 mod connect_return_code;
-mod topic_name;
 mod packet_identifier;
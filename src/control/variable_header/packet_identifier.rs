@@ -1,30 +1,41 @@
-use bytes::BytesMut;
-use {Encodable, Decodable};
+use bytes::{BytesMut, BufMut};
+use {Encodable, Decodable, DecodeContext, EncodeContext};
 
 error_chain!{
     types{
         PacketIdentifierError, ErrorKind, ResultExt, PacketIdentifierResult;
     }
+
+    errors{
+        ZeroPacketIdentifier
+    }
 }
 
 #[derive(Debug)]
 pub struct PacketIdentifier(pub u16);
 
+impl PacketIdentifier {
+    pub fn new(identifier: u16) -> Result<PacketIdentifier, PacketIdentifierError> {
+        if identifier == 0 {
+            bail!(ErrorKind::ZeroPacketIdentifier);
+        }
+        Ok(PacketIdentifier(identifier))
+    }
+}
+
 impl<'a> Decodable<'a> for PacketIdentifier{
     type Error = PacketIdentifierError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        Ok(PacketIdentifier(Decodable::decode(byte).chain_err(||"decode packet identifier avaiable")?))
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        Ok(PacketIdentifier(Decodable::decode_with(byte, ctx).chain_err(||"decode packet identifier avaiable")?))
     }
 }
 
 impl Encodable for PacketIdentifier {
     type Error = PacketIdentifierError;
-    type Cond = ();
 
-    fn encode_with(&self, _: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        self.0.encode().chain_err(||"encode packet identifier error")
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.0.encode_to_with(dst, ctx).chain_err(||"encode packet identifier error")
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -32,3 +43,13 @@ impl Encodable for PacketIdentifier {
     }
 }
 
+#[cfg(test)]
+mod test{
+    use super::*;
+
+    #[test]
+    fn test_zero_packet_identifier(){
+        assert!(PacketIdentifier::new(0).is_err());
+        assert_eq!(PacketIdentifier::new(7).unwrap().0, 7);
+    }
+}
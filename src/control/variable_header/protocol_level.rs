@@ -1,5 +1,6 @@
+use {DecodeContext, EncodeContext};
 use PacketError;
-use bytes::BytesMut;
+use bytes::{BytesMut, BufMut};
 use Decodable;
 use Encodable;
 
@@ -9,19 +10,17 @@ pub struct ProtocolLevel(pub u8);
 
 impl<'a> Decodable<'a> for ProtocolLevel{
     type Error = PacketError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        Ok(ProtocolLevel(Decodable::decode(byte)?))
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        Ok(ProtocolLevel(Decodable::decode_with(byte, ctx)?))
     }
 }
 
 impl Encodable for ProtocolLevel{
     type Error = PacketError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        self.0.encode()
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.0.encode_to_with(dst, ctx)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -1,39 +1,42 @@
+use {DecodeContext, EncodeContext};
 use PacketError;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut, Buf, BufMut};
 use Decodable;
 use Encodable;
-use bytes::BigEndian;
-use bytes::ByteOrder;
 
 error_chain!{
     types {
         VecBytesError, ErrorKind, ResultExt, VecBytesResult;
     }
+
+    errors {
+        BinaryTooLong(len: usize)
+    }
 }
 
 #[derive(Debug)]
-pub struct VecBytes(pub Vec<u8>);
+pub struct VecBytes(pub Bytes);
 
 impl<'a> Decodable<'a> for VecBytes{
     type Error = VecBytesError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error>{
         let len = byte.len();
         let mut size = 0u16;
         if len >= 2 {
-            size = BigEndian::read_u16(byte);
+            size = (&byte[..]).get_u16();
         }else {
             error!("will message header is not enough code to decode");
             bail!("will message header is not enough code to decode");
         }
 
-        let split_len = (size + 2 ) as usize;
+        if size as usize > ctx.max_string_length {
+            bail!("binary data of {} bytes is longer than allowed", size);
+        }
+        let split_len = size as usize + 2;
         if len >= split_len {
-            let result = VecBytes(byte[2..split_len].to_vec());
-            byte.split_to(split_len);
-
-            Ok(result)
+            byte.advance(2);
+            Ok(VecBytes(byte.split_to(split_len - 2).freeze()))
         }else {
             error!("will message header is not enough code to decode");
             // Err( ConnectPacketError::NoEnoughBytesToDecode )
@@ -44,15 +47,17 @@ impl<'a> Decodable<'a> for VecBytes{
 
 impl Encodable for VecBytes {
     type Error = VecBytesError;
-    type Cond = ();
-    fn encode_with(&self, _cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let mut result = vec![0u8; 2];
-        BigEndian::write_u16(&mut result, self.0.len() as u16);
-        result.extend(self.0.iter().cloned());
-        Ok(result)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+        let length = self.encode_length()? - 2;
+        dst.put_u16(length as u16);
+        dst.put_slice(&self.0);
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
+        if self.0.len() > u16::MAX as usize {
+            bail!(ErrorKind::BinaryTooLong(self.0.len()))
+        }
         Ok( 2 + ( self.0.len() as u32 ) )
     }
 }
@@ -5,54 +5,93 @@ extern crate log;
 extern crate bytes;
 #[macro_use]
 extern crate error_chain;
+#[cfg(feature = "codec")]
+extern crate tokio;
+#[cfg(feature = "codec")]
+extern crate tokio_util;
+#[cfg(all(test, feature = "codec"))]
+extern crate futures;
 
 pub mod packet;
+pub mod blocking;
+#[cfg(feature = "codec")]
+pub mod codec;
 mod control;
 mod qos;
 mod topic_name;
 mod return_code;
+mod reason_code;
 mod topic_filter;
-
-
-use bytes::BytesMut;
-use bytes::BigEndian;
-use bytes::ByteOrder;
+mod subscription_trie;
+mod byte_str;
+mod protocol_version;
+mod context;
+
+pub use qos::QualityOfService;
+pub use return_code::SubscribeReturnCode;
+pub use reason_code::{ PubAckReasonCode, PubRecReasonCode, PubRelReasonCode, PubCompReasonCode, SubAckReasonCode, UnSubAckReasonCode,
+                       DisconnectReasonCode, AuthReasonCode, ReasonCodeError, ErrorKind as ReasonCodeErrorKind };
+pub use topic_name::TopicName;
+pub use topic_filter::TopicFilter;
+pub use subscription_trie::SubscriptionTrie;
+pub use byte_str::ByteStr;
+pub use protocol_version::ProtocolVersion;
+pub use context::{DecodeContext, EncodeContext};
+pub use control::variable_header::ConnectFlags;
+
+use bytes::{BytesMut, Buf, BufMut};
 use std::fmt;
 use packet::FixedHeaderError;
 use std::error::Error;
 
+/// Decoding reads from the front of the buffer, consuming what it decodes.
+///
+/// The `DecodeContext` is handed down unchanged from the packet to each of its
+/// fields; `decode` uses the default 3.1.1 context.
 pub trait Decodable<'a>: Sized {
     type Error;
-    type Cond;
     fn decode(bytes: &mut BytesMut) -> Result<Self, Self::Error> {
-        Self::decode_with(bytes, None)
+        Self::decode_with(bytes, &DecodeContext::default())
     }
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error>;
 }
 
+/// Encoding writes straight into the destination buffer.
+///
+/// `encode_length` is exact, so callers writing into a growable buffer can
+/// reserve once up front; `encode` and `encode_with` do that for a `Vec<u8>`.
+/// Like `BufMut::put`, `encode_to` panics if `dst` runs out of room.
 pub trait Encodable{
     type Error;
-    type Cond;
 
     fn encode(&self) -> Result<Vec<u8>, Self::Error> {
-        Self::encode_with(&self, None)
+        self.encode_with(&EncodeContext::default())
+    }
+
+    fn encode_with(&self, ctx: &EncodeContext) -> Result<Vec<u8>, Self::Error> {
+        let mut vec = Vec::with_capacity(self.encode_length()? as usize);
+        self.encode_to_with(&mut vec, ctx)?;
+        Ok(vec)
+    }
+
+    fn encode_to<B: BufMut>(&self, dst: &mut B) -> Result<(), Self::Error> {
+        self.encode_to_with(dst, &EncodeContext::default())
     }
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>;
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error>;
 
     fn encode_length(&self) -> Result<u32, Self::Error>;
 }
 
 impl<'a> Decodable<'a> for String {
     type Error = PacketError;
-    type Cond = ();
 
-    fn decode_with(bytes: &mut BytesMut, _size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(bytes: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
         let len = bytes.len();
         let mut size = 0u16;
         if len >= 2 {
-            size = BigEndian::read_u16(bytes);
+            size = (&bytes[..]).get_u16();
         } else {
             error!("not enough bytes encode String header bytes");
             // return Err(PacketError::NoEnoughBytesToDecode);
@@ -67,19 +106,31 @@ impl<'a> Decodable<'a> for String {
             bail!(ErrorKind::NoEnoughBytesToDecode)
         }
 
-        Ok(String::from_utf8(bytes.split_to(2 + ( size as usize )).split_off(2).to_vec())?)
+        let string = String::from_utf8(bytes.split_to(2 + ( size as usize )).split_off(2).to_vec())?;
+        check_string(&string, ctx)?;
+        Ok(string)
     }
 }
 
+/// Applies the `DecodeContext` limits on UTF-8 strings to a decoded string.
+fn check_string(string: &str, ctx: &DecodeContext) -> Result<(), PacketError> {
+    if string.len() > ctx.max_string_length {
+        bail!(ErrorKind::StringTooLong(string.len()))
+    }
+    if ctx.strict && string.contains('\u{0}') {
+        bail!(ErrorKind::NullCharacter)
+    }
+    Ok(())
+}
+
 impl<'a> Decodable<'a> for u8 {
     type Error = PacketError;
-    type Cond = ();
 
-    fn decode_with(bytes: &mut BytesMut, _size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(bytes: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
         let len = bytes.len();
         if len >= 1 {
             let code = bytes[0];
-            bytes.split_to(1);
+            bytes.advance(1);
             Ok(code)
         } else {
             error!("u8 enough code to decode");
@@ -91,14 +142,12 @@ impl<'a> Decodable<'a> for u8 {
 
 impl<'a> Decodable<'a> for u16 {
     type Error = PacketError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _size: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error>{
         let len = byte.len();
         let mut result = 0u16;
         if len >= 2 {
-            result = BigEndian::read_u16(byte);
-            byte.split_to(2);
+            result = byte.get_u16();
             Ok(result)
         }else {
             error!("u16 not enough code to decode");
@@ -111,16 +160,17 @@ impl<'a> Decodable<'a> for u16 {
 
 impl Encodable for String {
     type Error = PacketError;
-    type Cond = ();
-    fn encode_with(&self, _cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let length = self.len() as u16;
-        let mut vec = vec![0u8, 0u8];
-        BigEndian::write_u16(&mut vec, length);
-        vec.extend(self.as_bytes());
-        Ok(vec)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        let length = self.encode_length()? - 2;
+        dst.put_u16(length as u16);
+        dst.put_slice(self.as_bytes());
+        Ok(())
     }
     
     fn encode_length(&self) -> Result<u32, Self::Error>{
+        if self.len() > u16::MAX as usize {
+            bail!(ErrorKind::StringTooLong(self.len()))
+        }
         Ok(2 + (self.len() as u32))
     }
 }
@@ -128,11 +178,9 @@ impl Encodable for String {
 
 impl Encodable for u16 {
     type Error = PacketError;
-    type Cond = ();
-    fn encode_with(&self, _cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let mut vec = vec![0u8; 2];
-        BigEndian::write_u16(&mut vec, *self);
-        Ok(vec)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        dst.put_u16(*self);
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -142,10 +190,10 @@ impl Encodable for u16 {
 
 impl Encodable for u8{
     type Error = PacketError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        Ok(vec![*self])
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+        dst.put_u8(*self);
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -162,14 +210,13 @@ error_chain!{
     errors{
         NoEnoughBytesToDecode
         InvalidEncode
-    }
-
-    links {
-        ConnectPacket(::packet::ConnectError, ::packet::ConnectErrorKind);
+        StringTooLong(len: usize)
+        NullCharacter
     }
 
     foreign_links {
         FromUtf8Error(::std::string::FromUtf8Error);
+        Utf8Error(::std::str::Utf8Error);
     }
 
 }
@@ -184,7 +231,7 @@ mod tests {
     fn check_string_decode() {
         let mut vec = vec![0x00, 0x04];
         vec.extend(String::from("mqtt").into_bytes().iter().clone());
-        let mut bytes = BytesMut::from(vec);
+        let mut bytes = BytesMut::from(&vec[..]);
 
         let result = String::decode(&mut bytes);
         // println!("{:?}", result);
@@ -194,7 +241,7 @@ mod tests {
     #[test]
     fn check_u8_decode() {
         let mut vec = vec![];
-        let mut bytes = BytesMut::from(vec);
+        let mut bytes = BytesMut::from(&vec[..]);
 
         let result = u8::decode(&mut bytes);
         // println!("{:?}", result);
@@ -207,11 +254,35 @@ mod tests {
     }
 
 
+    #[test]
+    fn check_string_decode_context(){
+        let vec = vec![0x00, 0x03, b'a', 0x00, b'b'];
+        assert_eq!(String::decode(&mut BytesMut::from(&vec[..])).unwrap(), "a\u{0}b");
+
+        let ctx = DecodeContext { strict: true, ..DecodeContext::default() };
+        match String::decode_with(&mut BytesMut::from(&vec[..]), &ctx) {
+            Err(PacketError(ErrorKind::NullCharacter, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let ctx = DecodeContext { max_string_length: 2, ..DecodeContext::default() };
+        match String::decode_with(&mut BytesMut::from(&vec[..]), &ctx) {
+            Err(PacketError(ErrorKind::StringTooLong(3), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn check_string_too_long(){
+        let target = String::from_utf8(vec![b'a'; 65536]).unwrap();
+        assert!(target.encode().is_err());
+    }
+
     #[test]
     fn check_u16_encode(){
         let number = 65535u16;
 
-        let mut bytes = BytesMut::from(number.encode().unwrap());
+        let mut bytes = BytesMut::from(&number.encode().unwrap()[..]);
         
         let encode: Result<u16, PacketError> = Decodable::decode(&mut bytes);
         // println!("{:?}", encode);
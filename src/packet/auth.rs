@@ -0,0 +1,240 @@
+use {Encodable, Decodable, AuthReasonCode, DecodeContext, EncodeContext};
+use packet::{FixedHeader, Properties, Property};
+use bytes::{BytesMut, Buf, BufMut};
+
+error_chain!{
+    types{
+        AuthError, ErrorKind, ResultExt, AuthResult;
+    }
+    errors{
+        InvalidProperty(identifier: u8)
+    }
+    links{
+        FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
+        ReasonCode(::reason_code::ReasonCodeError, ::reason_code::ErrorKind);
+    }
+}
+
+#[derive(Debug)]
+struct AuthFixedHeader{
+    packet_type: u8,
+    remaining_length: u32,
+}
+
+impl AuthFixedHeader{
+    fn new() -> AuthFixedHeader{
+        AuthFixedHeader{
+            packet_type: 15,
+            remaining_length: 0,
+        }
+    }
+}
+
+impl FixedHeader for AuthFixedHeader {
+    fn set_remaining_length(&mut self, len: u32) {
+        self.remaining_length = len;
+    }
+}
+
+impl<'a> Decodable<'a> for AuthFixedHeader{
+    type Error = AuthError;
+
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        match Self::get_fixheader(byte) {
+            Ok((packet_type, _, remaining_length, n)) => {
+                byte.advance(1 + n);
+                Ok(AuthFixedHeader {
+                    packet_type,
+                    remaining_length,
+                })
+            },
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Encodable for AuthFixedHeader{
+    type Error = AuthError;
+
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    }
+
+    fn encode_length(&self) -> Result<u32, Self::Error>{
+        Self::get_remaining_length_bytes(self.remaining_length).map_err(From::from)
+    }
+}
+
+/// MQTT 5 AUTH packet, exchanged during enhanced authentication. It has no
+/// 3.1.1 form, so its reason code and properties are always present.
+#[derive(Debug)]
+pub struct Auth{
+    fixed_header: AuthFixedHeader,
+    reason_code: AuthReasonCode,
+    properties: Properties,
+}
+
+impl Auth {
+    pub fn builder() -> AuthBuilder {
+        AuthBuilder::new()
+    }
+
+    pub fn reason_code(&self) -> AuthReasonCode {
+        self.reason_code
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    pub fn authentication_method(&self) -> Option<&str> {
+        match self.properties.get(0x15) {
+            Some(Property::AuthenticationMethod(method)) => Some(method),
+            _ => None,
+        }
+    }
+
+    pub fn authentication_data(&self) -> Option<&[u8]> {
+        match self.properties.get(0x16) {
+            Some(Property::AuthenticationData(data)) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Length of the reason code and properties. Both are left out while they
+    /// hold their defaults, success and no properties.
+    fn reason_length(&self) -> Result<u32, AuthError> {
+        if !self.properties.is_empty() {
+            Ok(1 + self.properties.encode_length()?)
+        } else if !self.reason_code.is_default() {
+            Ok(1)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Only the authentication method and data, a reason string and user
+    /// properties may be sent with AUTH (MQTT 5.0 §3.15.2.2).
+    fn check_properties(properties: &Properties) -> Result<(), AuthError> {
+        for property in properties {
+            match *property {
+                Property::AuthenticationMethod(_) |
+                Property::AuthenticationData(_) |
+                Property::ReasonString(_) |
+                Property::UserProperty(_, _) => {},
+                _ => bail!(ErrorKind::InvalidProperty(property.identifier())),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthBuilder {
+    reason_code: AuthReasonCode,
+    properties: Properties,
+}
+
+impl AuthBuilder {
+    fn new() -> AuthBuilder {
+        AuthBuilder {
+            reason_code: AuthReasonCode::default(),
+            properties: Properties::new(),
+        }
+    }
+
+    pub fn reason_code(mut self, reason_code: AuthReasonCode) -> AuthBuilder {
+        self.reason_code = reason_code;
+        self
+    }
+
+    pub fn properties(mut self, properties: Properties) -> AuthBuilder {
+        self.properties = properties;
+        self
+    }
+
+    pub fn build(self) -> Result<Auth, AuthError> {
+        Auth::check_properties(&self.properties)?;
+
+        let mut packet = Auth {
+            fixed_header: AuthFixedHeader::new(),
+            reason_code: self.reason_code,
+            properties: self.properties,
+        };
+        packet.fixed_header.remaining_length = packet.reason_length()?;
+        AuthFixedHeader::get_remaining_length_bytes(packet.fixed_header.remaining_length)?;
+        Ok(packet)
+    }
+}
+
+impl<'a> Decodable<'a> for Auth{
+    type Error = AuthError;
+
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header: AuthFixedHeader = Decodable::decode_with(byte, ctx)?;
+        let reason_code = if fixed_header.remaining_length > 0 { Decodable::decode_with(byte, ctx)? } else { AuthReasonCode::default() };
+        let properties = if fixed_header.remaining_length > 1 { Decodable::decode_with(byte, ctx)? } else { Properties::new() };
+        Auth::check_properties(&properties)?;
+
+        let mut packet = Auth{
+            fixed_header,
+            reason_code,
+            properties,
+        };
+        packet.fixed_header.remaining_length = packet.reason_length()?;
+        Ok(packet)
+    }
+}
+
+impl Encodable for Auth {
+    type Error = AuthError;
+
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        if self.reason_length()? > 0 {
+            self.reason_code.encode_to_with(dst, ctx)?;
+        }
+        if !self.properties.is_empty() {
+            self.properties.encode_to_with(dst, ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn encode_length(&self) -> Result<u32, Self::Error> {
+        Ok(self.fixed_header.encode_length()? + self.reason_length()?)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use super::*;
+
+    #[test]
+    fn test_auth_encode_decode(){
+        let packet = Auth::builder().build().unwrap();
+        assert_eq!(packet.encode().unwrap(), vec![0xF0, 0x00]);
+
+        let properties = Properties::new()
+                            .with(Property::AuthenticationMethod("SCRAM".into())).unwrap()
+                            .with(Property::AuthenticationData(vec![1u8, 2].into())).unwrap();
+        let packet = Auth::builder()
+                        .reason_code(AuthReasonCode::ContinueAuthentication)
+                        .properties(properties)
+                        .build()
+                        .unwrap();
+        let vecbytes = packet.encode().unwrap();
+        assert_eq!(vecbytes, vec![0xF0, 0x0F, 0x18, 0x0D, 0x15, 0x00, 0x05, b'S', b'C', b'R', b'A', b'M', 0x16, 0x00, 0x02, 1, 2]);
+
+        let result = Auth::decode(&mut BytesMut::from(&vecbytes[..])).unwrap();
+        assert_eq!(result.encode().unwrap(), vecbytes);
+        assert_eq!(result.reason_code(), AuthReasonCode::ContinueAuthentication);
+        assert_eq!(result.authentication_method(), Some("SCRAM"));
+        assert_eq!(result.authentication_data(), Some(&[1u8, 2][..]));
+
+        let properties = Properties::new().with(Property::SessionExpiryInterval(0)).unwrap();
+        assert!(Auth::builder().properties(properties).build().is_err());
+        assert!(Auth::decode(&mut BytesMut::from(&[0xF0, 0x01, 0x04][..])).is_err());
+    }
+}
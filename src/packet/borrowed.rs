@@ -0,0 +1,758 @@
+use std::str;
+use bytes::Bytes;
+
+use {DecodeContext, ProtocolVersion};
+use packet::{Connect, Connack, Publish, PubAck, PubRec, PubRel, PubComp, Subscribe, SubAck,
+             Unsubscribe, UnSubAck, PingReq, PingResp, Disconnect, ValuePacket, ValuePacketError,
+             peek_fixed_header, valid_fixed_header_flags};
+use control::variable_header::ConnectFlags;
+use qos::QualityOfService;
+use return_code::SubscribeReturnCode;
+use topic_name::TopicName;
+use topic_filter::TopicFilter;
+
+error_chain!{
+    types {
+        ValuePacketRefError, ErrorKind, ResultExt, ValuePacketRefResult;
+    }
+
+    errors {
+        MalformedPacket(r: String)
+        UnknownPacketType(packet_type: u8)
+        InvalidQos(qos: u8)
+        DupFlagWithQos0
+        UnsupportedProtocolVersion(protocol_version: ProtocolVersion)
+    }
+
+    links {
+        FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
+        TopicNameError(::topic_name::TopicNameError, ::topic_name::ErrorKind);
+        TopicFilterError(::topic_filter::TopicFilterError, ::topic_filter::ErrorKind);
+        ConnectError(::packet::ConnectError, ::packet::ConnectErrorKind);
+    }
+
+    foreign_links {
+        Utf8(::std::str::Utf8Error);
+    }
+}
+
+/// Packet decoded in place from a byte slice.
+///
+/// Strings and payloads borrow from the input instead of being copied, so
+/// decoding never allocates. The same checks as the owned decoders are applied;
+/// use `to_value_packet` to get a `ValuePacket` once the packet needs to
+/// outlive the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValuePacketRef<'a> {
+    ConnectPacket(ConnectRef<'a>),
+    ConnackPacket(ConnackRef),
+    PublishPacket(PublishRef<'a>),
+    PubAckPacket(u16),
+    PubRecPacket(u16),
+    PubRelPacket(u16),
+    PubCompPacket(u16),
+    SubscribePacket(SubscribeRef<'a>),
+    SubAckPacket(SubAckRef<'a>),
+    UnsubscribePacket(UnsubscribeRef<'a>),
+    UnSubAckPacket(u16),
+    PingReqPacket,
+    PingRespPacket,
+    DisconnecPacket,
+}
+
+impl<'a> ValuePacketRef<'a> {
+    /// Decodes the packet at the start of `bytes`, returning it together with
+    /// the length of its frame, or `Ok(None)` while the frame is incomplete.
+    pub fn decode(bytes: &'a [u8]) -> Result<Option<(ValuePacketRef<'a>, usize)>, ValuePacketRefError> {
+        ValuePacketRef::decode_with(bytes, &DecodeContext::default())
+    }
+
+    /// Like `decode`, for the protocol version of `ctx`. MQTT 5 properties are
+    /// not decoded in place, so `ProtocolVersion::V5` is refused with
+    /// `UnsupportedProtocolVersion` rather than misreading its packets.
+    pub fn decode_with(bytes: &'a [u8], ctx: &DecodeContext) -> Result<Option<(ValuePacketRef<'a>, usize)>, ValuePacketRefError> {
+        if ctx.protocol_version.has_properties() {
+            bail!(ErrorKind::UnsupportedProtocolVersion(ctx.protocol_version));
+        }
+        let (packet_type, flags, remaining_length, n) = match peek_fixed_header(bytes)? {
+            Some(fixed_header) => fixed_header,
+            None => return Ok(None),
+        };
+        if !valid_fixed_header_flags(packet_type, flags) {
+            bail!(ErrorKind::MalformedPacket(format!("invalid flags {:#06b} for packet type {}", flags, packet_type)));
+        }
+        let frame_length = 1 + n + remaining_length as usize;
+        if bytes.len() < frame_length {
+            return Ok(None);
+        }
+
+        let mut reader = Reader(&bytes[1 + n..frame_length]);
+        let packet = match packet_type {
+            1 => ValuePacketRef::ConnectPacket(ConnectRef::decode(&mut reader)?),
+            2 => ValuePacketRef::ConnackPacket(ConnackRef {
+                // MQTT 3.1 reserves the byte holding the session present flag
+                session_present: reader.u8()? & 0x01 == 0x01 && ctx.protocol_version != ProtocolVersion::V31,
+                return_code: reader.u8()?,
+            }),
+            3 => ValuePacketRef::PublishPacket(PublishRef::decode(&mut reader, flags)?),
+            4 => ValuePacketRef::PubAckPacket(reader.u16()?),
+            5 => ValuePacketRef::PubRecPacket(reader.u16()?),
+            6 => ValuePacketRef::PubRelPacket(reader.u16()?),
+            7 => ValuePacketRef::PubCompPacket(reader.u16()?),
+            8 => ValuePacketRef::SubscribePacket(SubscribeRef::decode(&mut reader)?),
+            9 => ValuePacketRef::SubAckPacket(SubAckRef::decode(&mut reader, ctx)?),
+            10 => ValuePacketRef::UnsubscribePacket(UnsubscribeRef::decode(&mut reader)?),
+            11 => ValuePacketRef::UnSubAckPacket(reader.u16()?),
+            12 => ValuePacketRef::PingReqPacket,
+            13 => ValuePacketRef::PingRespPacket,
+            14 => ValuePacketRef::DisconnecPacket,
+            _ => bail!(ErrorKind::UnknownPacketType(packet_type)),
+        };
+        if !reader.0.is_empty() {
+            bail!(ErrorKind::MalformedPacket(format!("{} unexpected bytes after packet", reader.0.len())));
+        }
+        Ok(Some((packet, frame_length)))
+    }
+
+    /// Copies the packet into an owned `ValuePacket`.
+    ///
+    /// The packet goes through the builders, so values the decoder accepts but
+    /// the builders refuse, such as a zero packet identifier, are errors here.
+    pub fn to_value_packet(self) -> Result<ValuePacket, ValuePacketError> {
+        let packet = match self {
+            ValuePacketRef::ConnectPacket(ref connect) => ValuePacket::ConnectPacket(connect.to_connect()?),
+            ValuePacketRef::ConnackPacket(ref connack) => {
+                ValuePacket::ConnackPacket(Connack::builder().session_present(connack.session_present)
+                                                             .return_code(connack.return_code)
+                                                             .build()?)
+            },
+            ValuePacketRef::PublishPacket(ref publish) => ValuePacket::PublishPacket(publish.to_publish()?),
+            ValuePacketRef::PubAckPacket(id) => ValuePacket::PubAckPacket(PubAck::builder().packet_identifier(id).build()?),
+            ValuePacketRef::PubRecPacket(id) => ValuePacket::PubRecPacket(PubRec::builder().packet_identifier(id).build()?),
+            ValuePacketRef::PubRelPacket(id) => ValuePacket::PubRelPacket(PubRel::builder().packet_identifier(id).build()?),
+            ValuePacketRef::PubCompPacket(id) => ValuePacket::PubCompPacket(PubComp::builder().packet_identifier(id).build()?),
+            ValuePacketRef::SubscribePacket(ref subscribe) => {
+                let builder = subscribe.subscribes().fold(Subscribe::builder().packet_identifier(subscribe.packet_identifier),
+                                                          |builder, (topic_filter, qos)| builder.subscribe(topic_filter, qos));
+                ValuePacket::SubscribePacket(builder.build()?)
+            },
+            ValuePacketRef::SubAckPacket(ref suback) => {
+                let builder = suback.return_codes().fold(SubAck::builder().packet_identifier(suback.packet_identifier),
+                                                         |builder, return_code| builder.return_code(return_code));
+                ValuePacket::SubAckPacket(builder.build()?)
+            },
+            ValuePacketRef::UnsubscribePacket(ref unsubscribe) => {
+                let builder = unsubscribe.topic_filters().fold(Unsubscribe::builder().packet_identifier(unsubscribe.packet_identifier),
+                                                               |builder, topic_filter| builder.topic_filter(topic_filter));
+                ValuePacket::UnsubscribePacket(builder.build()?)
+            },
+            ValuePacketRef::UnSubAckPacket(id) => ValuePacket::UnSubAckPacket(UnSubAck::builder().packet_identifier(id).build()?),
+            ValuePacketRef::PingReqPacket => ValuePacket::PingReqPacket(PingReq::builder().build()?),
+            ValuePacketRef::PingRespPacket => ValuePacket::PingRespPacket(PingResp::builder().build()?),
+            ValuePacketRef::DisconnecPacket => ValuePacket::DisconnecPacket(Disconnect::builder().build()?),
+        };
+        Ok(packet)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectRef<'a> {
+    protocol_name: &'a str,
+    protocol_level: u8,
+    connect_flags: ConnectFlags,
+    keep_alive: u16,
+    client_identifier: &'a str,
+    will: Option<(&'a str, &'a [u8])>,
+    user_name: Option<&'a str>,
+    password: Option<&'a [u8]>,
+}
+
+impl<'a> ConnectRef<'a> {
+    pub fn protocol_name(&self) -> &'a str {
+        self.protocol_name
+    }
+
+    pub fn protocol_level(&self) -> u8 {
+        self.protocol_level
+    }
+
+    pub fn connect_flags(&self) -> &ConnectFlags {
+        &self.connect_flags
+    }
+
+    pub fn clean_session(&self) -> bool {
+        self.connect_flags.clean_session
+    }
+
+    pub fn keep_alive(&self) -> u16 {
+        self.keep_alive
+    }
+
+    pub fn client_identifier(&self) -> &'a str {
+        self.client_identifier
+    }
+
+    pub fn will_topic(&self) -> Option<&'a str> {
+        self.will.map(|(topic, _)| topic)
+    }
+
+    pub fn will_message(&self) -> Option<&'a [u8]> {
+        self.will.map(|(_, message)| message)
+    }
+
+    pub fn will_qos(&self) -> QualityOfService {
+        self.connect_flags.will_qos()
+    }
+
+    pub fn will_retain(&self) -> bool {
+        self.connect_flags.will_retain
+    }
+
+    pub fn user_name(&self) -> Option<&'a str> {
+        self.user_name
+    }
+
+    pub fn password(&self) -> Option<&'a [u8]> {
+        self.password
+    }
+
+    fn decode(reader: &mut Reader<'a>) -> Result<ConnectRef<'a>, ValuePacketRefError> {
+        let protocol_name = reader.str()?;
+        let protocol_level = reader.u8()?;
+        let flags = reader.u8()?;
+        let connect_flags = ConnectFlags {
+            user_name_flag: flags & 0x80 == 0x80,
+            password_flag: flags & 0x40 == 0x40,
+            will_retain: flags & 0x20 == 0x20,
+            will_qos: flags >> 3 & 0x03,
+            will_flag: flags & 0x04 == 0x04,
+            clean_session: flags & 0x02 == 0x02,
+            reserved: flags & 0x01 == 0x01,
+        };
+        if connect_flags.will_qos > 2 {
+            bail!(ErrorKind::InvalidQos(connect_flags.will_qos));
+        }
+        let protocol_version = Connect::validate_protocol(protocol_name, protocol_level)?;
+        if protocol_version.has_properties() {
+            bail!(ErrorKind::UnsupportedProtocolVersion(protocol_version));
+        }
+        Connect::validate_connect_flags(&connect_flags, protocol_version)?;
+        let keep_alive = reader.u16()?;
+        let client_identifier = reader.str()?;
+        Connect::validate_client_identifier(client_identifier, protocol_version)?;
+        let will = if connect_flags.will_flag {
+            let will_topic = reader.str()?;
+            TopicName::validate(will_topic)?;
+            Some((will_topic, reader.binary()?))
+        } else {
+            None
+        };
+        let user_name = if connect_flags.user_name_flag { Some(reader.str()?) } else { None };
+        let password = if connect_flags.password_flag { Some(reader.binary()?) } else { None };
+
+        Ok(ConnectRef {
+            protocol_name,
+            protocol_level,
+            connect_flags,
+            keep_alive,
+            client_identifier,
+            will,
+            user_name,
+            password,
+        })
+    }
+
+    fn to_connect(self) -> Result<Connect, ValuePacketError> {
+        let mut builder = Connect::builder().protocol(self.protocol_name, self.protocol_level)
+                                            .client_id(self.client_identifier)
+                                            .clean_session(self.clean_session())
+                                            .keep_alive(self.keep_alive)
+                                            .will_qos(self.will_qos())
+                                            .will_retain(self.will_retain());
+        if let Some((topic, message)) = self.will {
+            builder = builder.will(topic, message);
+        }
+        if let Some(user_name) = self.user_name {
+            builder = builder.user_name(user_name);
+        }
+        if let Some(password) = self.password {
+            builder = builder.password(password);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnackRef {
+    session_present: bool,
+    return_code: u8,
+}
+
+impl ConnackRef {
+    pub fn session_present(&self) -> bool {
+        self.session_present
+    }
+
+    pub fn return_code(&self) -> u8 {
+        self.return_code
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublishRef<'a> {
+    dup: bool,
+    qos: QualityOfService,
+    retain: bool,
+    topic_name: &'a str,
+    packet_identifier: Option<u16>,
+    payload: &'a [u8],
+}
+
+impl<'a> PublishRef<'a> {
+    pub fn topic_name(&self) -> &'a str {
+        self.topic_name
+    }
+
+    pub fn qos(&self) -> QualityOfService {
+        self.qos
+    }
+
+    pub fn dup(&self) -> bool {
+        self.dup
+    }
+
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+
+    pub fn packet_identifier(&self) -> Option<u16> {
+        self.packet_identifier
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    fn decode(reader: &mut Reader<'a>, flags: u8) -> Result<PublishRef<'a>, ValuePacketRefError> {
+        let dup = flags >> 3 == 0x01;
+        let qos = match QualityOfService::from_u8(flags >> 1 & 0x03) {
+            Ok(qos) => qos,
+            Err(_) => bail!(ErrorKind::InvalidQos(flags >> 1 & 0x03)),
+        };
+        if dup && qos == QualityOfService::Level0 {
+            bail!(ErrorKind::DupFlagWithQos0);
+        }
+        let topic_name = reader.str()?;
+        TopicName::validate(topic_name)?;
+        let packet_identifier = match qos {
+            QualityOfService::Level0 => None,
+            _ => Some(reader.u16()?),
+        };
+        let payload = reader.take(reader.0.len())?;
+
+        Ok(PublishRef {
+            dup,
+            qos,
+            retain: flags & 0x01 == 0x01,
+            topic_name,
+            packet_identifier,
+            payload,
+        })
+    }
+
+    fn to_publish(self) -> Result<Publish, ValuePacketError> {
+        let mut builder = Publish::builder().topic_name(self.topic_name)
+                                            .qos(self.qos)
+                                            .dup(self.dup)
+                                            .retain(self.retain)
+                                            .payload(Bytes::copy_from_slice(self.payload));
+        if let Some(packet_identifier) = self.packet_identifier {
+            builder = builder.packet_identifier(packet_identifier);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Borrowed SUBSCRIBE. The subscriptions are validated while decoding and
+/// parsed again from the input on every call to `subscribes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscribeRef<'a> {
+    packet_identifier: u16,
+    payload: &'a [u8],
+}
+
+impl<'a> SubscribeRef<'a> {
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier
+    }
+
+    pub fn subscribes(&self) -> Subscribes<'a> {
+        Subscribes(Reader(self.payload))
+    }
+
+    fn decode(reader: &mut Reader<'a>) -> Result<SubscribeRef<'a>, ValuePacketRefError> {
+        let packet_identifier = reader.u16()?;
+        let payload = reader.take(reader.0.len())?;
+        if payload.is_empty() {
+            bail!(ErrorKind::MalformedPacket("subscribe without subscriptions".into()));
+        }
+        let mut subscribes = Reader(payload);
+        while !subscribes.0.is_empty() {
+            TopicFilter::validate(subscribes.str()?)?;
+            let qos = subscribes.u8()?;
+            if qos & 0xFC != 0 {
+                bail!(ErrorKind::MalformedPacket(format!("reserved bits set in requested qos {}", qos)));
+            }
+            if QualityOfService::from_u8(qos).is_err() {
+                bail!(ErrorKind::InvalidQos(qos));
+            }
+        }
+
+        Ok(SubscribeRef {
+            packet_identifier,
+            payload,
+        })
+    }
+}
+
+/// Iterator over the topic filters and requested QoS of a `SubscribeRef`.
+#[derive(Debug, Clone)]
+pub struct Subscribes<'a>(Reader<'a>);
+
+impl<'a> Iterator for Subscribes<'a> {
+    type Item = (&'a str, QualityOfService);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let topic_filter = self.0.str().expect("subscriptions validated on decode");
+        let qos = self.0.u8().ok().and_then(|qos| QualityOfService::from_u8(qos).ok());
+        Some((topic_filter, qos.expect("subscriptions validated on decode")))
+    }
+}
+
+/// Borrowed SUBACK, holding the undecoded return codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubAckRef<'a> {
+    packet_identifier: u16,
+    return_codes: &'a [u8],
+}
+
+impl<'a> SubAckRef<'a> {
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier
+    }
+
+    pub fn return_codes(&self) -> ReturnCodes<'a> {
+        ReturnCodes(self.return_codes.iter())
+    }
+
+    fn decode(reader: &mut Reader<'a>, ctx: &DecodeContext) -> Result<SubAckRef<'a>, ValuePacketRefError> {
+        let packet_identifier = reader.u16()?;
+        let return_codes = reader.take(reader.0.len())?;
+        for &code in return_codes {
+            match return_code(code) {
+                None => bail!(ErrorKind::MalformedPacket(format!("invalid subscribe return code {}", code))),
+                Some(SubscribeReturnCode::Failure) if ctx.protocol_version == ProtocolVersion::V31 => {
+                    bail!(ErrorKind::MalformedPacket("subscribe failure return code requires 3.1.1".into()))
+                },
+                Some(_) => {},
+            }
+        }
+
+        Ok(SubAckRef {
+            packet_identifier,
+            return_codes,
+        })
+    }
+}
+
+/// Iterator over the return codes of a `SubAckRef`.
+#[derive(Debug, Clone)]
+pub struct ReturnCodes<'a>(::std::slice::Iter<'a, u8>);
+
+impl<'a> Iterator for ReturnCodes<'a> {
+    type Item = SubscribeReturnCode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|&code| return_code(code).expect("return codes validated on decode"))
+    }
+}
+
+fn return_code(code: u8) -> Option<SubscribeReturnCode> {
+    match code {
+        0x00 => Some(SubscribeReturnCode::MaximumQos0),
+        0x01 => Some(SubscribeReturnCode::MaximumQos1),
+        0x02 => Some(SubscribeReturnCode::MaximumQos2),
+        0x80 => Some(SubscribeReturnCode::Failure),
+        _ => None,
+    }
+}
+
+/// Borrowed UNSUBSCRIBE. The topic filters are validated while decoding and
+/// parsed again from the input on every call to `topic_filters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsubscribeRef<'a> {
+    packet_identifier: u16,
+    payload: &'a [u8],
+}
+
+impl<'a> UnsubscribeRef<'a> {
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier
+    }
+
+    pub fn topic_filters(&self) -> TopicFilters<'a> {
+        TopicFilters(Reader(self.payload))
+    }
+
+    fn decode(reader: &mut Reader<'a>) -> Result<UnsubscribeRef<'a>, ValuePacketRefError> {
+        let packet_identifier = reader.u16()?;
+        let payload = reader.take(reader.0.len())?;
+        let mut topic_filters = Reader(payload);
+        while !topic_filters.0.is_empty() {
+            TopicFilter::validate(topic_filters.str()?)?;
+        }
+
+        Ok(UnsubscribeRef {
+            packet_identifier,
+            payload,
+        })
+    }
+}
+
+/// Iterator over the topic filters of an `UnsubscribeRef`.
+#[derive(Debug, Clone)]
+pub struct TopicFilters<'a>(Reader<'a>);
+
+impl<'a> Iterator for TopicFilters<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        Some(self.0.str().expect("topic filters validated on decode"))
+    }
+}
+
+/// Cursor over the body of a single frame.
+#[derive(Debug, Clone)]
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ValuePacketRefError> {
+        if self.0.len() < n {
+            bail!(ErrorKind::MalformedPacket("remaining length shorter than packet".into()));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, ValuePacketRefError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ValuePacketRefError> {
+        let bytes = self.take(2)?;
+        Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn binary(&mut self) -> Result<&'a [u8], ValuePacketRefError> {
+        let len = self.u16()?;
+        self.take(len as usize)
+    }
+
+    fn str(&mut self) -> Result<&'a str, ValuePacketRefError> {
+        Ok(str::from_utf8(self.binary()?)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use {Encodable, EncodeContext};
+
+    fn contains(outer: &[u8], inner: &[u8]) -> bool {
+        let start = outer.as_ptr() as usize;
+        let inner_start = inner.as_ptr() as usize;
+        inner_start >= start && inner_start + inner.len() <= start + outer.len()
+    }
+
+    #[test]
+    fn test_decode_publish_ref_borrows_input() {
+        let mut vec = Publish::builder().topic_name("a/b").qos(QualityOfService::Level1).packet_identifier(7)
+                                        .retain(true).payload(vec![1, 2, 3]).build().unwrap().encode().unwrap();
+        let frame_length = vec.len();
+        vec.extend_from_slice(&[0xC0, 0x00]);
+
+        let (packet, n) = ValuePacketRef::decode(&vec).unwrap().unwrap();
+        assert_eq!(n, frame_length);
+        match packet {
+            ValuePacketRef::PublishPacket(publish) => {
+                assert_eq!(publish.topic_name(), "a/b");
+                assert_eq!(publish.packet_identifier(), Some(7));
+                assert_eq!(publish.qos(), QualityOfService::Level1);
+                assert!(publish.retain());
+                assert_eq!(publish.payload(), &[1, 2, 3]);
+                assert!(contains(&vec, publish.topic_name().as_bytes()));
+                assert!(contains(&vec, publish.payload()));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(ValuePacketRef::decode(&vec[n..]).unwrap().unwrap(), (ValuePacketRef::PingReqPacket, 2));
+
+        for len in 0..frame_length {
+            assert!(ValuePacketRef::decode(&vec[..len]).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_decode_ref_lists() {
+        let vec = Subscribe::builder().packet_identifier(1).subscribe("a/+", QualityOfService::Level1)
+                                      .subscribe("b/#", QualityOfService::Level2).build().unwrap().encode().unwrap();
+        match ValuePacketRef::decode(&vec).unwrap().unwrap().0 {
+            ValuePacketRef::SubscribePacket(subscribe) => {
+                let subscribes: Vec<_> = subscribe.subscribes().collect();
+                assert_eq!(subscribes, vec![("a/+", QualityOfService::Level1), ("b/#", QualityOfService::Level2)]);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let vec = Unsubscribe::builder().packet_identifier(1).topic_filter("a/+").topic_filter("b/#").build().unwrap().encode().unwrap();
+        match ValuePacketRef::decode(&vec).unwrap().unwrap().0 {
+            ValuePacketRef::UnsubscribePacket(unsubscribe) => {
+                assert_eq!(unsubscribe.topic_filters().collect::<Vec<_>>(), vec!["a/+", "b/#"]);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_ref_invalid() {
+        let invalid: &[&[u8]] = &[
+            &[0x36, 0x05, 0x00, 0x01, b'a', 0x00, 0x01],
+            &[0x38, 0x03, 0x00, 0x01, b'a'],
+            &[0x30, 0x04, 0x00, 0x02, 0xC3, 0x28],
+            &[0x30, 0x02, 0x00, 0x03],
+            &[0x82, 0x06, 0x00, 0x01, 0x00, 0x01, b'a', 0x03],
+            &[0x82, 0x06, 0x00, 0x01, 0x00, 0x01, b'a', 0x41],
+            &[0x82, 0x07, 0x00, 0x01, 0x00, 0x02, b'#', b'a', 0x01],
+            &[0x82, 0x02, 0x00, 0x01],
+            &[0xA2, 0x06, 0x00, 0x01, 0x00, 0x02, b'a', b'+'],
+            &[0x30, 0x05, 0x00, 0x03, b'a', b'/', b'#'],
+            &[0x90, 0x03, 0x00, 0x01, 0x03],
+            &[0x40, 0x03, 0x00, 0x01, 0x00],
+            &[0xF0, 0x00],
+        ];
+        for bytes in invalid {
+            assert!(ValuePacketRef::decode(bytes).is_err(), "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn test_decode_connect_ref_invalid() {
+        let vec = Connect::builder().client_id("a").build().unwrap().encode().unwrap();
+        let mut level = vec.clone();
+        level[8] = 3;
+        match ValuePacketRef::decode(&level) {
+            Err(ValuePacketRefError(ErrorKind::ConnectError(::packet::ConnectErrorKind::UnsupportedProtocol(_, 3)), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut flags = vec.clone();
+        flags[9] |= 0x01;
+        assert!(ValuePacketRef::decode(&flags).is_err());
+        let mut will_retain = vec.clone();
+        will_retain[9] |= 0x20;
+        match ValuePacketRef::decode(&will_retain) {
+            Err(ValuePacketRefError(ErrorKind::ConnectError(::packet::ConnectErrorKind::WillFlagsWithoutWill), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut password = Connect::builder().client_id("a").user_name("u").password("p").build().unwrap().encode().unwrap();
+        password[9] &= !0x80;
+        password.drain(15..18);
+        password[1] -= 3;
+        match ValuePacketRef::decode(&password) {
+            Err(ValuePacketRefError(ErrorKind::ConnectError(::packet::ConnectErrorKind::PasswordWithoutUserName), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut v5 = vec.clone();
+        v5[8] = 5;
+        assert!(ValuePacketRef::decode(&v5).is_err());
+
+        let mut will = Connect::builder().client_id("a").will("b", vec![1]).build().unwrap().encode().unwrap();
+        will[17] = b'#';
+        match ValuePacketRef::decode(&will) {
+            Err(ValuePacketRefError(ErrorKind::TopicNameError(::topic_name::ErrorKind::WildcardInTopicName(_)), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let vec = Connect::builder().protocol_version(ProtocolVersion::V31).client_id("a".repeat(23)).build().unwrap().encode().unwrap();
+        assert!(ValuePacketRef::decode(&vec).is_ok());
+        let mut long = vec.clone();
+        long.insert(16, b'a');
+        long[1] += 1;
+        long[15] += 1;
+        match ValuePacketRef::decode(&long) {
+            Err(ValuePacketRefError(ErrorKind::ConnectError(::packet::ConnectErrorKind::ClientIdentifierTooLong(24)), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_ref_protocol_version() {
+        let vec = PubAck::builder().packet_identifier(3).build().unwrap().encode().unwrap();
+        let ctx = DecodeContext::new(ProtocolVersion::V31);
+        assert_eq!(ValuePacketRef::decode_with(&vec, &ctx).unwrap(), Some((ValuePacketRef::PubAckPacket(3), 4)));
+
+        let vec = PubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(3).build().unwrap()
+                        .encode_with(&EncodeContext::new(ProtocolVersion::V5)).unwrap();
+        let suback = [0x90, 0x03, 0x00, 0x01, 0x80];
+        assert!(ValuePacketRef::decode(&suback).is_ok());
+        assert!(ValuePacketRef::decode_with(&suback, &ctx).is_err());
+        match ValuePacketRef::decode_with(&[0x20, 0x02, 0x01, 0x00], &ctx).unwrap() {
+            Some((ValuePacketRef::ConnackPacket(connack), 4)) => assert!(!connack.session_present()),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match ValuePacketRef::decode_with(&vec, &DecodeContext::new(ProtocolVersion::V5)) {
+            Err(ValuePacketRefError(ErrorKind::UnsupportedProtocolVersion(ProtocolVersion::V5), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ref_to_value_packet() {
+        let packets = vec![
+            Connect::builder().client_id("enjie").will("a/b", vec![1, 2]).user_name("user").password(vec![0xC3, 0x28]).build().unwrap().encode().unwrap(),
+            Connack::builder().session_present(true).build().unwrap().encode().unwrap(),
+            Publish::builder().topic_name("a/b").qos(QualityOfService::Level2).packet_identifier(1).payload(vec![1, 2, 3]).build().unwrap().encode().unwrap(),
+            PubAck::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            PubRec::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            PubRel::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            PubComp::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            Subscribe::builder().packet_identifier(1).subscribe("a/+", QualityOfService::Level1).build().unwrap().encode().unwrap(),
+            SubAck::builder().packet_identifier(1).return_code(SubscribeReturnCode::MaximumQos1).return_code(SubscribeReturnCode::Failure).build().unwrap().encode().unwrap(),
+            Unsubscribe::builder().packet_identifier(1).topic_filter("a/+").build().unwrap().encode().unwrap(),
+            UnSubAck::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            PingReq::builder().build().unwrap().encode().unwrap(),
+            PingResp::builder().build().unwrap().encode().unwrap(),
+            Disconnect::builder().build().unwrap().encode().unwrap(),
+        ];
+        for vec in packets {
+            let (packet, n) = ValuePacketRef::decode(&vec).unwrap().unwrap();
+            assert_eq!(n, vec.len());
+            assert_eq!(packet.to_value_packet().unwrap().encode().unwrap(), vec);
+        }
+
+        let (packet, _) = ValuePacketRef::decode(&[0x40, 0x02, 0x00, 0x00]).unwrap().unwrap();
+        assert_eq!(packet, ValuePacketRef::PubAckPacket(0));
+        assert!(packet.to_value_packet().is_err());
+    }
+}
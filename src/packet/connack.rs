@@ -1,18 +1,26 @@
-use {Decodable, Encodable};
-use packet::FixedHeader;
+use {Decodable, Encodable, ProtocolVersion, DecodeContext, EncodeContext};
+use packet::{FixedHeader, Properties};
 use control::variable_header::{ConnectAckFlags, ConnectAckFlagsErrorKind, ConnectAckFlagsError, ConnectReturnCode, ConnectReturnCodeError, ConnectReturnCodeErrorKind};
-use bytes::BytesMut;
+use bytes::{BytesMut, Buf, BufMut};
 
 error_chain!{
     types{
         ConnackError, ErrorKind, ResultExt, ConnackResult;
     }
 
+    errors{
+        InvalidReturnCode(code: u8)
+        SessionPresentOnRefusal
+        SessionPresentRequiresV311
+        PropertiesRequireV5
+    }
+
 
     links{
         FixedHeader(::packet::FixedHeaderError, ::packet::ErrorKind);
         ConnectAckFlags(ConnectAckFlagsError, ConnectAckFlagsErrorKind);
         ConnectReturnCode(ConnectReturnCodeError, ConnectReturnCodeErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
     }
 }
 
@@ -42,12 +50,11 @@ impl FixedHeader for ConnackFixedHeader{
 
 impl<'a> Decodable<'a> for ConnackFixedHeader {
     type Error =  ConnackError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error>{
         match Self::get_fixheader(byte) {
             Ok((packet_type, reserved, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(ConnackFixedHeader{
                     packet_type: packet_type,
                     reserved: reserved,
@@ -63,11 +70,10 @@ impl<'a> Decodable<'a> for ConnackFixedHeader {
 
 impl Encodable for ConnackFixedHeader {
     type Error = ConnackError;
-    type Cond = ();
 
     
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        Self::encode_fixedheader(self.packet_type, self.reserved, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+        Self::encode_fixedheader(dst, self.packet_type, self.reserved, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -80,75 +86,169 @@ pub struct Connack {
     fixed_header: ConnackFixedHeader,
     connect_ack_flag: ConnectAckFlags,
     connect_return_code: ConnectReturnCode,
+    properties: Option<Properties>,
 }
 
 impl Connack{
+    pub fn builder() -> ConnackBuilder {
+        ConnackBuilder::new()
+    }
+
+    pub fn session_present(&self) -> bool {
+        self.connect_ack_flag.0
+    }
+
+    pub fn return_code(&self) -> u8 {
+        self.connect_return_code.0
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
     fn new() -> Connack {
         let mut connack = Connack{
             fixed_header: ConnackFixedHeader::new(),
             connect_ack_flag: ConnectAckFlags(false),
             connect_return_code: ConnectReturnCode(0u8),
+            properties: None,
         };
-        connack.calculate_remaining_length();
+        let _ = connack.calculate_remaining_length();
         
         connack
     }
 
     fn calculate_remaining_length(&mut self) -> Result<(), ConnackError> {
-        let remaining_length = self.connect_ack_flag.encode_length()? 
+        let mut remaining_length = self.connect_ack_flag.encode_length()? 
                             + self.connect_return_code.encode_length()?;
+        if let Some(ref properties) = self.properties {
+            remaining_length += properties.encode_length()?;
+        }
+        ConnackFixedHeader::get_remaining_length_bytes(remaining_length)?;
         self.fixed_header.set_remaining_length(remaining_length);
         Ok(())
     }
 
-    fn set_connect_ack_flag(&mut self, flag: bool){
+    fn set_connect_ack_flag(&mut self, flag: bool) -> Result<(), ConnackError> {
         self.connect_ack_flag.0 = flag;
-        self.calculate_remaining_length();
+        self.calculate_remaining_length()
     }
 
-    fn set_connect_return_code(&mut self, code: u8) {
+    fn set_connect_return_code(&mut self, code: u8) -> Result<(), ConnackError> {
         self.connect_return_code.0 = code;
-        self.calculate_remaining_length();
+        self.calculate_remaining_length()
+    }
+
+    fn set_properties(&mut self, properties: Option<Properties>) -> Result<(), ConnackError> {
+        self.properties = properties;
+        self.calculate_remaining_length()
+    }
+}
+
+#[derive(Debug)]
+pub struct ConnackBuilder {
+    session_present: bool,
+    return_code: u8,
+    protocol_version: ProtocolVersion,
+    properties: Option<Properties>,
+}
+
+impl ConnackBuilder {
+    fn new() -> ConnackBuilder {
+        ConnackBuilder {
+            session_present: false,
+            return_code: 0,
+            protocol_version: ProtocolVersion::V311,
+            properties: None,
+        }
+    }
+
+    pub fn session_present(mut self, session_present: bool) -> ConnackBuilder {
+        self.session_present = session_present;
+        self
+    }
+
+    pub fn return_code(mut self, return_code: u8) -> ConnackBuilder {
+        self.return_code = return_code;
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> ConnackBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with `ProtocolVersion::V5`.
+    pub fn properties(mut self, properties: Properties) -> ConnackBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn build(self) -> Result<Connack, ConnackError> {
+        if self.return_code > 5 {
+            bail!(ErrorKind::InvalidReturnCode(self.return_code));
+        }
+        if self.session_present && self.return_code != 0 {
+            bail!(ErrorKind::SessionPresentOnRefusal);
+        }
+        if self.session_present && self.protocol_version == ProtocolVersion::V31 {
+            bail!(ErrorKind::SessionPresentRequiresV311);
+        }
+        let properties = match (self.protocol_version, self.properties) {
+            (ProtocolVersion::V5, properties) => Some(properties.unwrap_or_default()),
+            (_, None) => None,
+            (_, Some(_)) => bail!(ErrorKind::PropertiesRequireV5),
+        };
+
+        let mut connack = Connack::new();
+        connack.set_connect_ack_flag(self.session_present)?;
+        connack.set_connect_return_code(self.return_code)?;
+        connack.set_properties(properties)?;
+        Ok(connack)
     }
 }
 
 impl<'a> Decodable<'a> for Connack {
     type Error = ConnackError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header = Decodable::decode(byte)?;
-        let connect_ack_flag = Decodable::decode(byte)?;
-        let connect_return_code = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header = Decodable::decode_with(byte, ctx)?;
+        let connect_ack_flag = Decodable::decode_with(byte, ctx)?;
+        let connect_return_code = Decodable::decode_with(byte, ctx)?;
+        let properties = match ctx.protocol_version {
+            ProtocolVersion::V5 => Some(Decodable::decode_with(byte, ctx)?),
+            _ => None,
+        };
 
         Ok(Connack{
             fixed_header: fixed_header,
             connect_ack_flag: connect_ack_flag,
             connect_return_code: connect_return_code,
+            properties,
         })
     }
 }
 
 impl Encodable for Connack {
     type Error = ConnackError;
-    type Cond = ();
 
-    fn encode_with(&self, _: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let mut vec = vec![];
-
-        let fixed_header = self.fixed_header.encode()?;
-        let connect_ack_flag = self.connect_ack_flag.encode()?;
-        let connect_return_code = self.connect_return_code.encode()?;
-
-        vec.extend(fixed_header);
-        vec.extend(connect_ack_flag);
-        vec.extend(connect_return_code);
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        self.connect_ack_flag.encode_to_with(dst, ctx)?;
+        self.connect_return_code.encode_to_with(dst, ctx)?;
+        if let Some(ref properties) = self.properties {
+            properties.encode_to_with(dst, ctx)?;
+        }
 
-        Ok(vec)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
-        let length = self.fixed_header.encode_length()? + self.connect_ack_flag.encode_length()? + self.connect_return_code.encode_length()?;
+        let mut length = self.fixed_header.encode_length()? + self.connect_ack_flag.encode_length()? + self.connect_return_code.encode_length()?;
+        if let Some(ref properties) = self.properties {
+            length += properties.encode_length()?;
+        }
         Ok(length)
     }
 }
@@ -160,10 +260,51 @@ mod tests {
     use bytes::BytesMut;
     #[test]
     fn test_encode_decode_connack_packet(){
-        let connack = Connack::new(); 
+        let connack = Connack::builder().session_present(true).build().unwrap(); 
         //println!("{:?}",connack.encode());
-        let mut bytes = BytesMut::from(connack.encode().unwrap());
-        let connack_copy = Connack::decode(&mut bytes);
+        let mut bytes = BytesMut::from(&connack.encode().unwrap()[..]);
+        let connack_copy = Connack::decode(&mut bytes).unwrap();
         //println!("{:?}", connack_copy);
+        assert_eq!(connack_copy.encode().unwrap(), vec![0x20, 0x02, 0x01, 0x00]);
+        assert!(connack_copy.session_present());
+        assert_eq!(connack_copy.return_code(), 0);
+    }
+
+    #[test]
+    fn test_connack_builder_invalid(){
+        assert!(Connack::builder().return_code(6).build().is_err());
+        assert!(Connack::builder().session_present(true).return_code(5).build().is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_connack_v5(){
+        let properties = Properties::new().with(::packet::Property::ReceiveMaximum(10)).unwrap();
+        let connack = Connack::builder()
+                        .protocol_version(ProtocolVersion::V5)
+                        .session_present(true)
+                        .properties(properties.clone())
+                        .build()
+                        .unwrap();
+        let vec = connack.encode().unwrap();
+        assert_eq!(vec, vec![0x20, 0x06, 0x01, 0x00, 0x03, 0x21, 0x00, 0x0A]);
+
+        let decoded = Connack::decode_with(&mut BytesMut::from(&vec[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(decoded.encode().unwrap(), vec);
+        assert_eq!(decoded.properties(), Some(&properties));
+        assert!(Connack::builder().properties(properties).build().is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_connack_v31(){
+        // the byte before the return code is reserved in 3.1
+        let vec = vec![0x20, 0x02, 0x01, 0x02];
+        let decoded = Connack::decode_with(&mut BytesMut::from(&vec[..]), &DecodeContext::new(ProtocolVersion::V31)).unwrap();
+        assert!(!decoded.session_present());
+        assert_eq!(decoded.return_code(), 2);
+        assert_eq!(decoded.properties(), None);
+
+        let connack = Connack::builder().protocol_version(ProtocolVersion::V31).return_code(2).build().unwrap();
+        assert_eq!(connack.encode().unwrap(), vec![0x20, 0x02, 0x00, 0x02]);
+        assert!(Connack::builder().protocol_version(ProtocolVersion::V31).session_present(true).build().is_err());
     }
 }
@@ -1,10 +1,11 @@
-use super::super::{Decodable, Encodable};
+use {DecodeContext, EncodeContext};
+use super::super::{Decodable, Encodable, ProtocolVersion};
 use super::super::PacketError;
-use super::FixedHeader;
-use bytes::BytesMut;
-use bytes::BigEndian;
-use bytes::ByteOrder;
+use super::{FixedHeader, Properties};
+use bytes::{BytesMut, Buf, BufMut};
 use control::variable_header::{ConnectFlags, ProtocolName, ProtocolLevel, KeepAlive, VecBytes};
+use qos::QualityOfService;
+use topic_name::TopicName;
 
 
 error_chain!{
@@ -15,16 +16,27 @@ error_chain!{
     errors{
         ConnectFlagsDecodeError(r: String)
         ConnectPayloadError(r: String)
+        PasswordWithoutUserName
+        WillFlagsWithoutWill
+        EmptyClientIdentifier
+        PropertiesRequireV5
+        UnsupportedProtocol(name: String, level: u8)
+        ClientIdentifierTooLong(len: usize)
+        ReservedConnectFlag
     }
 
     links {
         FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
         VecBytesError(::control::variable_header::VecBytesError, ::control::variable_header::VecBytesErrorKind);
         ConnectFlagsError(::control::variable_header::ConnectFlagsError, ::control::variable_header::ConnectFlagsErrorKind);
+        PropertiesError(::packet::PropertiesError, ::packet::PropertiesErrorKind);
+        TopicName(::topic_name::TopicNameError, ::topic_name::ErrorKind);
     }
 }
 
 
+/// Longest client identifier MQTT 3.1 allows, in characters.
+const MAX_V31_CLIENT_IDENTIFIER: usize = 23;
 
 #[derive(Debug)]
 struct ConnectFixedHeader{
@@ -52,12 +64,11 @@ impl FixedHeader for ConnectFixedHeader{
 
 impl<'a> Decodable<'a> for ConnectFixedHeader{
     type Error = ConnectError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error>{
         match Self::get_fixheader(byte) {
             Ok((packet_type, reserved, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(ConnectFixedHeader{
                     packet_type: packet_type,
                     reserved: reserved,
@@ -73,10 +84,9 @@ impl<'a> Decodable<'a> for ConnectFixedHeader{
 
 impl Encodable for ConnectFixedHeader {
     type Error = ConnectError;
-    type Cond = ();
     
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        Self::encode_fixedheader(self.packet_type, self.reserved, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, self.reserved, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -92,10 +102,80 @@ pub struct Connect{
     protocol_level: ProtocolLevel,
     connect_flags: ConnectFlags,
     keep_alive: KeepAlive,
+    properties: Option<Properties>,
     payload: ConnectPayload,
 }
 
 impl Connect {
+    pub fn builder() -> ConnectBuilder {
+        ConnectBuilder::new()
+    }
+
+    pub fn protocol_name(&self) -> &str {
+        &self.protocol_name.0
+    }
+
+    pub fn protocol_level(&self) -> u8 {
+        self.protocol_level.0
+    }
+
+    /// Version named by the protocol name and level, which `build` and
+    /// `decode` have checked to be a valid pair.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        ProtocolVersion::from_level(self.protocol_level.0).unwrap_or_default()
+    }
+
+    pub fn connect_flags(&self) -> &ConnectFlags {
+        &self.connect_flags
+    }
+
+    pub fn clean_session(&self) -> bool {
+        self.connect_flags.clean_session
+    }
+
+    pub fn keep_alive(&self) -> u16 {
+        self.keep_alive.0
+    }
+
+    pub fn client_identifier(&self) -> &str {
+        &self.payload.client_identifier
+    }
+
+    pub fn will_topic(&self) -> Option<&str> {
+        self.payload.will_topic.as_ref().map(|topic| topic.as_str())
+    }
+
+    pub fn will_message(&self) -> Option<&[u8]> {
+        self.payload.will_message.as_ref().map(|message| &message.0[..])
+    }
+
+    pub fn will_qos(&self) -> QualityOfService {
+        self.connect_flags.will_qos()
+    }
+
+    pub fn will_retain(&self) -> bool {
+        self.connect_flags.will_retain
+    }
+
+    /// MQTT 5 properties; `None` unless the protocol level is 5.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
+    /// MQTT 5 will properties; `None` without a will or below level 5.
+    pub fn will_properties(&self) -> Option<&Properties> {
+        self.payload.will_properties.as_ref()
+    }
+
+    pub fn user_name(&self) -> Option<&str> {
+        self.payload.user_name.as_deref()
+    }
+
+    /// Password as the binary data MQTT defines it to be.
+    pub fn password(&self) -> Option<&[u8]> {
+        self.payload.password.as_ref().map(|password| &password.0[..])
+    }
+
     fn with_level<P, C>(protocol_name: P, client_identifier: C, level: u8) -> Connect 
         where P: Into<String>,
               C: Into<String>
@@ -106,9 +186,10 @@ impl Connect {
             protocol_level: ProtocolLevel(level),
             connect_flags: ConnectFlags::new(),
             keep_alive: KeepAlive(0),
+            properties: None,
             payload: ConnectPayload::new(client_identifier.into()),
         };
-        connect.calculate_remaining_length();
+        let _ = connect.calculate_remaining_length();
 
         connect
     }
@@ -118,18 +199,72 @@ impl Connect {
                         + self.protocol_level.encode_length().chain_err(||"encode protocol level length error")?
                         + self.connect_flags.encode_length().chain_err(||"encode connect flags length error")?
                         + self.keep_alive.encode_length().chain_err(||"encode keep alive length error")?
+                        + self.properties_length()?
                         + self.payload.encode_length().chain_err(||"encode payload length error")?;
+        ConnectFixedHeader::get_remaining_length_bytes(remaining_length)?;
         self.fix_header.remaining_length = remaining_length;
         Ok(())
     }
 
-    fn set_will(&mut self, will: Option<(String, Vec<u8>)>) -> Result<(), ConnectError>{
+    /// Version named by a CONNECT protocol name and level, or
+    /// `UnsupportedProtocol` if the pair is not a valid one.
+    pub fn validate_protocol(protocol_name: &str, protocol_level: u8) -> Result<ProtocolVersion, ConnectError> {
+        match ProtocolVersion::from_protocol(protocol_name, protocol_level) {
+            Some(protocol_version) => Ok(protocol_version),
+            None => bail!(ErrorKind::UnsupportedProtocol(protocol_name.into(), protocol_level)),
+        }
+    }
+
+    /// Checks the client identifier against the limits of `protocol_version`;
+    /// 3.1 requires one of 1 to 23 characters.
+    pub fn validate_client_identifier(client_identifier: &str, protocol_version: ProtocolVersion) -> Result<(), ConnectError> {
+        if protocol_version == ProtocolVersion::V31 {
+            if client_identifier.is_empty() {
+                bail!(ErrorKind::EmptyClientIdentifier);
+            }
+            if client_identifier.chars().count() > MAX_V31_CLIENT_IDENTIFIER {
+                bail!(ErrorKind::ClientIdentifierTooLong(client_identifier.chars().count()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the connect flags agree with each other: the reserved bit
+    /// is clear, will QoS and retain are only set with a will, and below
+    /// MQTT 5 a password comes with a user name.
+    pub fn validate_connect_flags(connect_flags: &ConnectFlags, protocol_version: ProtocolVersion) -> Result<(), ConnectError> {
+        if connect_flags.reserved {
+            bail!(ErrorKind::ReservedConnectFlag);
+        }
+        if !connect_flags.will_flag && (connect_flags.will_retain || connect_flags.will_qos != 0) {
+            bail!(ErrorKind::WillFlagsWithoutWill);
+        }
+        if connect_flags.password_flag && !connect_flags.user_name_flag && protocol_version != ProtocolVersion::V5 {
+            bail!(ErrorKind::PasswordWithoutUserName);
+        }
+        Ok(())
+    }
+
+    fn properties_length(&self) -> Result<u32, ConnectError> {
+        match self.properties {
+            Some(ref properties) => Ok(properties.encode_length()?),
+            None => Ok(0),
+        }
+    }
+
+    fn set_properties(&mut self, properties: Option<Properties>, will_properties: Option<Properties>) -> Result<(), ConnectError> {
+        self.properties = properties;
+        self.payload.will_properties = will_properties;
+        self.calculate_remaining_length()
+    }
+
+    fn set_will(&mut self, will: Option<(TopicName, Vec<u8>)>) -> Result<(), ConnectError>{
         self.connect_flags.will_flag = will.is_some();
         
         match will {
             Some((topic_name, message)) => {
                 self.payload.will_topic = Some(topic_name);
-                self.payload.will_message = Some(VecBytes(message));
+                self.payload.will_message = Some(VecBytes(message.into()));
             },
             None => {
                 self.payload.will_topic = None;
@@ -145,12 +280,16 @@ impl Connect {
         self.calculate_remaining_length()
     }
 
-    fn set_password(&mut self, password: Option<String>) -> Result<(), ConnectError> {
+    fn set_password(&mut self, password: Option<Vec<u8>>) -> Result<(), ConnectError> {
         self.connect_flags.password_flag = password.is_some();
-        self.payload.password = password;
+        self.payload.password = password.map(|password| VecBytes(password.into()));
         self.calculate_remaining_length()
     }
 
+    fn set_keep_alive(&mut self, keep_alive: u16) {
+        self.keep_alive.0 = keep_alive;
+    }
+
     fn set_clean_session(&mut self, clean_session: bool) {
         self.connect_flags.clean_session = clean_session;
     }
@@ -161,22 +300,178 @@ impl Connect {
 
     fn set_will_qos(&mut self, will_qos: u8){
         assert!(will_qos <= 2);
-        self.connect_flags.will_QoS = will_qos;
+        self.connect_flags.will_qos = will_qos;
+    }
+}
+
+#[derive(Debug)]
+pub struct ConnectBuilder {
+    protocol_name: String,
+    protocol_level: u8,
+    client_identifier: String,
+    clean_session: bool,
+    keep_alive: u16,
+    will: Option<(String, Vec<u8>)>,
+    will_qos: QualityOfService,
+    will_retain: bool,
+    user_name: Option<String>,
+    password: Option<Vec<u8>>,
+    properties: Option<Properties>,
+    will_properties: Option<Properties>,
+}
+
+impl ConnectBuilder {
+    fn new() -> ConnectBuilder {
+        ConnectBuilder {
+            protocol_name: "MQTT".into(),
+            protocol_level: 4,
+            client_identifier: String::new(),
+            clean_session: true,
+            keep_alive: 0,
+            will: None,
+            will_qos: QualityOfService::Level0,
+            will_retain: false,
+            user_name: None,
+            password: None,
+            properties: None,
+            will_properties: None,
+        }
+    }
+
+    pub fn protocol<P: Into<String>>(mut self, protocol_name: P, protocol_level: u8) -> ConnectBuilder {
+        self.protocol_name = protocol_name.into();
+        self.protocol_level = protocol_level;
+        self
+    }
+
+    /// Sets the protocol name and level of `protocol_version`.
+    pub fn protocol_version(self, protocol_version: ProtocolVersion) -> ConnectBuilder {
+        self.protocol(protocol_version.protocol_name(), protocol_version.level())
+    }
+
+    pub fn client_id<C: Into<String>>(mut self, client_identifier: C) -> ConnectBuilder {
+        self.client_identifier = client_identifier.into();
+        self
+    }
+
+    pub fn clean_session(mut self, clean_session: bool) -> ConnectBuilder {
+        self.clean_session = clean_session;
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: u16) -> ConnectBuilder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn will<T, M>(mut self, topic: T, message: M) -> ConnectBuilder
+        where T: Into<String>,
+              M: Into<Vec<u8>>
+    {
+        self.will = Some((topic.into(), message.into()));
+        self
+    }
+
+    pub fn will_qos(mut self, will_qos: QualityOfService) -> ConnectBuilder {
+        self.will_qos = will_qos;
+        self
+    }
+
+    pub fn will_retain(mut self, will_retain: bool) -> ConnectBuilder {
+        self.will_retain = will_retain;
+        self
+    }
+
+    pub fn user_name<U: Into<String>>(mut self, user_name: U) -> ConnectBuilder {
+        self.user_name = Some(user_name.into());
+        self
+    }
+
+    pub fn password<P: Into<Vec<u8>>>(mut self, password: P) -> ConnectBuilder {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with protocol level 5.
+    pub fn properties(mut self, properties: Properties) -> ConnectBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    /// Sets the MQTT 5 will properties; only valid with protocol level 5 and a
+    /// will.
+    pub fn will_properties(mut self, will_properties: Properties) -> ConnectBuilder {
+        self.will_properties = Some(will_properties);
+        self
+    }
+
+    pub fn build(self) -> Result<Connect, ConnectError> {
+        if self.password.is_some() && self.user_name.is_none() {
+            bail!(ErrorKind::PasswordWithoutUserName);
+        }
+        if self.will.is_none() && (self.will_retain || self.will_qos != QualityOfService::Level0 || self.will_properties.is_some()) {
+            bail!(ErrorKind::WillFlagsWithoutWill);
+        }
+        if self.client_identifier.is_empty() && !self.clean_session {
+            bail!(ErrorKind::EmptyClientIdentifier);
+        }
+
+        let protocol_version = Connect::validate_protocol(&self.protocol_name, self.protocol_level)?;
+        Connect::validate_client_identifier(&self.client_identifier, protocol_version)?;
+
+        let (properties, will_properties) = match protocol_version {
+            ProtocolVersion::V5 => {
+                let will_properties = match self.will {
+                    Some(_) => Some(self.will_properties.unwrap_or_default()),
+                    None => None,
+                };
+                (Some(self.properties.unwrap_or_default()), will_properties)
+            },
+            _ if self.properties.is_some() || self.will_properties.is_some() => bail!(ErrorKind::PropertiesRequireV5),
+            _ => (None, None),
+        };
+
+        let will = match self.will {
+            Some((topic_name, message)) => Some((TopicName::new(topic_name)?, message)),
+            None => None,
+        };
+
+        let mut connect = Connect::with_level(self.protocol_name, self.client_identifier, self.protocol_level);
+        connect.set_keep_alive(self.keep_alive);
+        connect.set_clean_session(self.clean_session);
+        connect.set_will_qos(self.will_qos as u8);
+        connect.set_will_retain(self.will_retain);
+        connect.set_will(will)?;
+        connect.set_user_name(self.user_name)?;
+        connect.set_password(self.password)?;
+        connect.set_properties(properties, will_properties)?;
+        Ok(connect)
     }
 }
 
 impl<'a> Decodable<'a> for Connect{
     type Error = ConnectError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
         //byte is fixable length according remaining length
-        let fix_header = Decodable::decode(byte)?;
-        let protocol_name = Decodable::decode(byte).chain_err(||"decode protocol name fail")?;
-        let protocol_level = Decodable::decode(byte).chain_err(||"decode protocol level fail")?;
-        let connect_flags = Decodable::decode(byte)?;
-        let keep_alive = Decodable::decode(byte).chain_err(||"decode keep alive fail")?;
-        let payload = Decodable::decode_with(byte, Some(connect_flags).as_ref())?;
+        let fix_header = Decodable::decode_with(byte, ctx)?;
+        let protocol_name: ProtocolName = Decodable::decode_with(byte, ctx).chain_err(||"decode protocol name fail")?;
+        let protocol_level: ProtocolLevel = Decodable::decode_with(byte, ctx).chain_err(||"decode protocol level fail")?;
+        let connect_flags: ConnectFlags = Decodable::decode_with(byte, ctx)?;
+        let keep_alive = Decodable::decode_with(byte, ctx).chain_err(||"decode keep alive fail")?;
+        // CONNECT names its own protocol level, whatever was negotiated before
+        let protocol_version = Connect::validate_protocol(&protocol_name.0, protocol_level.0)?;
+        Connect::validate_connect_flags(&connect_flags, protocol_version)?;
+        let ctx = &DecodeContext {
+            protocol_version,
+            ..*ctx
+        };
+        let properties = match ctx.protocol_version {
+            ProtocolVersion::V5 => Some(Decodable::decode_with(byte, ctx)?),
+            _ => None,
+        };
+        let payload = ConnectPayload::decode(byte, &connect_flags, ctx)?;
+        Connect::validate_client_identifier(&payload.client_identifier, protocol_version)?;
 
         let connect = Connect{
             fix_header: fix_header,
@@ -184,6 +479,7 @@ impl<'a> Decodable<'a> for Connect{
             protocol_level: protocol_level,
             connect_flags: connect_flags,
             keep_alive: keep_alive,
+            properties,
             payload: payload,
         };
 
@@ -193,26 +489,19 @@ impl<'a> Decodable<'a> for Connect{
 
 impl Encodable for Connect {
     type Error = ConnectError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let mut result = vec![];
-        
-        let fix_header = self.fix_header.encode().chain_err(|| "encode fix header fail")?;
-        let protocol_name = self.protocol_name.encode().chain_err(|| "encode protocol name fail")?;
-        let protocol_level = self.protocol_level.encode().chain_err(|| "encode protocol level fail")?;
-        let connect_flag = self.connect_flags.encode().chain_err(|| "encode connect flag fail")?;
-        let keep_alive = self.keep_alive.encode().chain_err(|| "encode keep alive fail")?;
-        let payload = self.payload.encode_with(Some(self.connect_flags)).chain_err(|| "encode payload fail")?;
-
-        result.extend(fix_header);
-        result.extend(protocol_name);
-        result.extend(protocol_level);
-        result.extend(connect_flag);
-        result.extend(keep_alive);
-        result.extend(payload);
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error>{
+        self.fix_header.encode_to_with(dst, ctx).chain_err(|| "encode fix header fail")?;
+        self.protocol_name.encode_to_with(dst, ctx).chain_err(|| "encode protocol name fail")?;
+        self.protocol_level.encode_to_with(dst, ctx).chain_err(|| "encode protocol level fail")?;
+        self.connect_flags.encode_to_with(dst, ctx).chain_err(|| "encode connect flag fail")?;
+        self.keep_alive.encode_to_with(dst, ctx).chain_err(|| "encode keep alive fail")?;
+        if let Some(ref properties) = self.properties {
+            properties.encode_to_with(dst, ctx)?;
+        }
+        self.payload.encode_to(dst, &self.connect_flags, ctx).chain_err(|| "encode payload fail")?;
 
-        Ok(result)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, ConnectError> {
@@ -221,6 +510,7 @@ impl Encodable for Connect {
         length += self.protocol_level.encode_length().chain_err(||"encode protocol level length error")?;
         length += self.connect_flags.encode_length().chain_err(||"encode connect flags length error")?;
         length += self.keep_alive.encode_length().chain_err(||"encode keep alive length error")?;
+        length += self.properties_length()?;
         length += self.payload.encode_length().chain_err(||"encode payload length error")?;
 
         Ok(length)
@@ -230,16 +520,18 @@ impl Encodable for Connect {
 #[derive(Debug)]
 struct ConnectPayload{
     client_identifier: String,
-    will_topic: Option<String>,
+    will_properties: Option<Properties>,
+    will_topic: Option<TopicName>,
     will_message: Option<VecBytes>,
     user_name: Option<String>,
-    password: Option<String>,
+    password: Option<VecBytes>,
 }
 
 impl ConnectPayload {
     fn new(client_identifier: String) -> ConnectPayload {
         ConnectPayload {
             client_identifier: client_identifier,
+            will_properties: None,
             will_topic: None,
             will_message: None,
             user_name: None,
@@ -248,93 +540,84 @@ impl ConnectPayload {
     }
 }
 
-impl<'a> Decodable<'a> for ConnectPayload{
-    type Error = ConnectError;
-    type Cond = &'a ConnectFlags;
-
-    fn decode_with(byte: &mut BytesMut, connect_flags: Option<Self::Cond>) -> Result<Self, Self::Error>{
-        let client_identifier = Decodable::decode(byte).chain_err(|| ErrorKind::ConnectPayloadError("decode clien identifier error".into()))?;
-
-        if let Some(connect_flag) = connect_flags{
-            let will_topic = if connect_flag.will_flag {
-                Some(Decodable::decode(byte).chain_err(|| ErrorKind::ConnectPayloadError("decode will topic error".into()))?)
-            }else{
-                None
-            }; 
-            let will_message = if connect_flag.will_flag {
-                Some(Decodable::decode(byte).chain_err(|| ErrorKind::ConnectPayloadError("decode will message error".into()))?)
-            }else{
-                None
-            };
-            let user_name = if connect_flag.user_name_flag {
-                Some(Decodable::decode(byte).chain_err(|| ErrorKind::ConnectPayloadError("decode user name error".into()))?)
-            }else{
-                None
-            };
-            let password = if connect_flag.password_flag {
-                Some(Decodable::decode(byte).chain_err(|| ErrorKind::ConnectPayloadError("decode password error".into()))?)
-            }else{
-                None
-            };
+impl ConnectPayload {
+    /// Decodes the fields announced by `connect_flags`.
+    fn decode(byte: &mut BytesMut, connect_flag: &ConnectFlags, ctx: &DecodeContext) -> Result<ConnectPayload, ConnectError> {
+        let client_identifier = Decodable::decode_with(byte, ctx).chain_err(|| ErrorKind::ConnectPayloadError("decode clien identifier error".into()))?;
+
+        let will_properties = if connect_flag.will_flag && ctx.protocol_version == ProtocolVersion::V5 {
+            Some(Decodable::decode_with(byte, ctx)?)
+        }else{
+            None
+        };
+        let will_topic = if connect_flag.will_flag {
+            Some(Decodable::decode_with(byte, ctx)?)
+        }else{
+            None
+        }; 
+        let will_message = if connect_flag.will_flag {
+            Some(Decodable::decode_with(byte, ctx).chain_err(|| ErrorKind::ConnectPayloadError("decode will message error".into()))?)
+        }else{
+            None
+        };
+        let user_name = if connect_flag.user_name_flag {
+            Some(Decodable::decode_with(byte, ctx).chain_err(|| ErrorKind::ConnectPayloadError("decode user name error".into()))?)
+        }else{
+            None
+        };
+        let password = if connect_flag.password_flag {
+            Some(Decodable::decode_with(byte, ctx).chain_err(|| ErrorKind::ConnectPayloadError("decode password error".into()))?)
+        }else{
+            None
+        };
 
-            Ok(ConnectPayload{
-                client_identifier: client_identifier,
-                will_topic: will_topic,
-                will_message: will_message,
-                user_name: user_name,
-                password: password,
-            })
-        }else {
-            // error!("connect payload is not encode code to decode");
-            // Err(ConnectError::NoEnoughBytesToDecode)
-            bail!(ErrorKind::ConnectPayloadError("decode connect payload fail ".into()))
-        } 
+        Ok(ConnectPayload{
+            client_identifier,
+            will_properties,
+            will_topic,
+            will_message,
+            user_name,
+            password,
+        })
     }
-}
 
-impl Encodable for ConnectPayload{
-    type Error = ConnectError;
-    type Cond = ConnectFlags;
-
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let mut vec = vec![];
-        match cond {
-            Some(connect_flag) => {
-                vec.extend(self.client_identifier.encode().chain_err(||"encode client identifier is fail")?);
-                if connect_flag.will_flag {
-                    //TODO eles return connectflag and content unmatchable error
-                    if let Some(ref topic) = self.will_topic {
-                        vec.extend(topic.encode().chain_err(||"encode will topic is fail")?);        
-                    };
-
-                    if let Some(ref message) = self.will_message{
-                        vec.extend(message.encode().chain_err(||"encode will message is fail")?);
-                    };
-                };
+    /// Encodes the fields announced by `connect_flags`.
+    fn encode_to<B: BufMut>(&self, dst: &mut B, connect_flag: &ConnectFlags, ctx: &EncodeContext) -> Result<(), ConnectError> {
+        self.client_identifier.encode_to_with(dst, ctx).chain_err(||"encode client identifier is fail")?;
+        if connect_flag.will_flag {
+            //TODO eles return connectflag and content unmatchable error
+            if let Some(ref will_properties) = self.will_properties {
+                will_properties.encode_to_with(dst, ctx)?;
+            };
 
-                if connect_flag.user_name_flag {
-                    if let Some(ref user_name) = self.user_name {
-                        vec.extend(user_name.encode().chain_err(||"encode username is fail")?);
-                    };
-                };
+            if let Some(ref topic) = self.will_topic {
+                topic.encode_to_with(dst, ctx).chain_err(||"encode will topic is fail")?;        
+            };
 
-                if connect_flag.password_flag {
-                    if let Some(ref password) = self.password {
-                        vec.extend(password.encode().chain_err(||"encode password is fail")?);
-                    };
-                };
-                Ok(vec)
-            },
-            _ => {
-                error!("connect payload encoding payload is none");
-                // Err(ConnectError::InvalidEncode)
-                bail!(ErrorKind::ConnectPayloadError("connect payload parm is none".into()))
-            }
-        }
+            if let Some(ref message) = self.will_message{
+                message.encode_to_with(dst, ctx).chain_err(||"encode will message is fail")?;
+            };
+        };
+
+        if connect_flag.user_name_flag {
+            if let Some(ref user_name) = self.user_name {
+                user_name.encode_to_with(dst, ctx).chain_err(||"encode username is fail")?;
+            };
+        };
+
+        if connect_flag.password_flag {
+            if let Some(ref password) = self.password {
+                password.encode_to_with(dst, ctx).chain_err(||"encode password is fail")?;
+            };
+        };
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, ConnectError> {
         let mut length = self.client_identifier.encode_length().chain_err(|| "client identifier encode lenght fail")?;
+        if let Some(ref will_properties) = self.will_properties {
+            length += will_properties.encode_length()?;
+        }
         if let Some(ref will_topic) = self.will_topic {
             length += will_topic.encode_length().chain_err(|| "will topic encode lenght fail")?;
         }
@@ -366,7 +649,7 @@ mod tests {
     #[test]
     fn test_connect_flag(){
         let vec = vec![0x13];
-        let mut bytes = BytesMut::from(vec);
+        let mut bytes = BytesMut::from(&vec[..]);
         let connect_flag = ConnectFlags::decode(&mut bytes);
         // println!("{:?}", connect_flag);
     }
@@ -374,7 +657,7 @@ mod tests {
     #[test]
     fn test_vecbytes(){
         let vec = vec![0x00, 0x02, 0x13, 0x32, 0x33];
-        let mut bytes = BytesMut::from(vec);
+        let mut bytes = BytesMut::from(&vec[..]);
         let vec_bytes = VecBytes::decode(&mut bytes);
         // println!("{:?}", vec_bytes); 
     }
@@ -382,7 +665,7 @@ mod tests {
     #[test]
     fn test_encode_vecbytes(){
         let vec = vec![0x00, 0x02, 0x13, 0x32, 0x33];
-        let param = VecBytes(vec);
+        let param = VecBytes(vec.into());
         // println!("{:?}", param.encode()); 
     }
 
@@ -390,7 +673,7 @@ mod tests {
     #[test]
     fn test_connect_packet(){
         let vec = vec![];
-        let mut bytes = BytesMut::from(vec);
+        let mut bytes = BytesMut::from(&vec[..]);
         let packet = Connect::decode(&mut bytes);
         //println!("{:?}", packet);
     }
@@ -401,7 +684,7 @@ mod tests {
             user_name_flag: true,
             password_flag: false,
             will_retain: true,
-            will_QoS: 3u8,
+            will_qos: 3u8,
             will_flag: true,
             clean_session: false,
             reserved: true,
@@ -415,13 +698,173 @@ mod tests {
         let packet = Connect::with_level("MQTT", "123", 4);
 
         let vec = packet.encode().unwrap();
-        let mut bytes = BytesMut::from(vec);
+        let mut bytes = BytesMut::from(&vec[..]);
         // match Connect::decode(&mut bytes) {
         //     Ok(result) => println!("{:?}", result),
         //     Err(err) => println!("{:?}", err)
         // }
     }
 
-}
+    #[test]
+    fn test_connect_builder(){
+        let packet = Connect::builder()
+                        .client_id("enjie")
+                        .keep_alive(30)
+                        .will("a/b", vec![1, 2, 3])
+                        .will_qos(QualityOfService::Level1)
+                        .user_name("user")
+                        .password("pass")
+                        .build()
+                        .unwrap();
 
+        let vec = packet.encode().unwrap();
+        assert_eq!(vec.len() as u32, packet.encode_length().unwrap());
+        assert_eq!(vec[1] as usize, vec.len() - 2);
+        assert_eq!(vec[9], 0xCE);
+
+        let mut bytes = BytesMut::from(&vec[..]);
+        let decoded = Connect::decode(&mut bytes).unwrap();
+        assert_eq!(decoded.encode().unwrap(), vec);
+        assert_eq!(decoded.protocol_name(), "MQTT");
+        assert_eq!(decoded.protocol_level(), 4);
+        assert_eq!(decoded.client_identifier(), "enjie");
+        assert_eq!(decoded.keep_alive(), 30);
+        assert!(decoded.clean_session());
+        assert_eq!(decoded.will_topic(), Some("a/b"));
+        assert_eq!(decoded.will_message(), Some(&[1u8, 2, 3][..]));
+        assert_eq!(decoded.will_qos(), QualityOfService::Level1);
+        assert!(!decoded.will_retain());
+        let connect_flags = decoded.connect_flags();
+        assert!(connect_flags.user_name_flag() && connect_flags.password_flag() && connect_flags.will_flag() && connect_flags.clean_session());
+        assert_eq!(connect_flags.will_qos(), QualityOfService::Level1);
+        assert!(!connect_flags.will_retain() && !connect_flags.reserved());
+        assert_eq!(decoded.user_name(), Some("user"));
+        assert_eq!(decoded.password(), Some(&b"pass"[..]));
+
+        let packet = Connect::builder().client_id("enjie").user_name("user").password(vec![0xC3, 0x28]).build().unwrap();
+        let vec = packet.encode().unwrap();
+        let decoded = Connect::decode(&mut BytesMut::from(&vec[..])).unwrap();
+        assert_eq!(decoded.password(), Some(&[0xC3u8, 0x28][..]));
+    }
 
+    #[test]
+    fn test_connect_builder_invalid(){
+        assert!(Connect::builder().password("pass").build().is_err());
+        assert!(Connect::builder().will_retain(true).build().is_err());
+        assert!(Connect::builder().clean_session(false).build().is_err());
+        match Connect::builder().client_id("a").will("a/#", vec![1]).build() {
+            Err(ConnectError(ErrorKind::TopicName(::topic_name::ErrorKind::WildcardInTopicName(_)), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(Connect::builder().client_id("a").will("", vec![1]).build().is_err());
+
+        let vec = Connect::builder().client_id("a").will("b", vec![1]).build().unwrap().encode().unwrap();
+        let mut wildcard = vec.clone();
+        wildcard[17] = b'+';
+        match Connect::decode(&mut BytesMut::from(&wildcard[..])) {
+            Err(ConnectError(ErrorKind::TopicName(::topic_name::ErrorKind::WildcardInTopicName(_)), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut empty = vec.clone();
+        empty.remove(17);
+        empty[1] -= 1;
+        empty[16] = 0;
+        assert!(Connect::decode(&mut BytesMut::from(&empty[..])).is_err());
+    }
+
+    #[test]
+    fn test_connect_builder_v5(){
+        let properties = Properties::new().with(::packet::Property::SessionExpiryInterval(30)).unwrap();
+        let will_properties = Properties::new().with(::packet::Property::WillDelayInterval(5)).unwrap();
+        let packet = Connect::builder()
+                        .protocol("MQTT", 5)
+                        .client_id("enjie")
+                        .will("a/b", vec![1, 2, 3])
+                        .properties(properties.clone())
+                        .will_properties(will_properties.clone())
+                        .build()
+                        .unwrap();
+
+        let vec = packet.encode().unwrap();
+        assert_eq!(vec.len() as u32, packet.encode_length().unwrap());
+        assert_eq!(vec[1] as usize, vec.len() - 2);
+
+        let decoded = Connect::decode(&mut BytesMut::from(&vec[..])).unwrap();
+        assert_eq!(decoded.encode().unwrap(), vec);
+        assert_eq!(decoded.protocol_level(), 5);
+        assert_eq!(decoded.properties(), Some(&properties));
+        assert_eq!(decoded.will_properties(), Some(&will_properties));
+        assert_eq!(decoded.will_topic(), Some("a/b"));
+
+        assert!(Connect::builder().properties(properties).build().is_err());
+        assert!(Connect::builder().protocol("MQTT", 5).will_properties(will_properties).build().is_err());
+    }
+
+    #[test]
+    fn test_connect_v31(){
+        // hand-built MQTT 3.1 CONNECT in the shape mosquitto_pub -V mqttv31 -k 60 sends
+        let vec = vec![0x10, 0x1A, 0x00, 0x06, b'M', b'Q', b'I', b's', b'd', b'p', 0x03, 0x02, 0x00, 0x3C,
+                       0x00, 0x0C, b'm', b'o', b's', b'q', b'p', b'u', b'b', b'|', b'1', b'2', b'3', b'4'];
+        let decoded = Connect::decode(&mut BytesMut::from(&vec[..])).unwrap();
+        assert_eq!(decoded.protocol_version(), ProtocolVersion::V31);
+        assert_eq!(decoded.protocol_name(), "MQIsdp");
+        assert_eq!(decoded.client_identifier(), "mosqpub|1234");
+        assert_eq!(decoded.keep_alive(), 60);
+        assert_eq!(decoded.properties(), None);
+
+        let packet = Connect::builder().protocol_version(ProtocolVersion::V31).client_id("mosqpub|1234").keep_alive(60).build().unwrap();
+        assert_eq!(packet.encode().unwrap(), vec);
+
+        match Connect::builder().protocol_version(ProtocolVersion::V31).client_id("a".repeat(24)).build() {
+            Err(ConnectError(ErrorKind::ClientIdentifierTooLong(24), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(Connect::builder().protocol_version(ProtocolVersion::V31).build().is_err());
+        assert!(Connect::builder().client_id("a".repeat(24)).build().is_ok());
+    }
+
+    #[test]
+    fn test_connect_protocol_mismatch(){
+        match Connect::builder().protocol("MQIsdp", 4).client_id("a").build() {
+            Err(ConnectError(ErrorKind::UnsupportedProtocol(ref name, 4), _)) if name == "MQIsdp" => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(Connect::builder().protocol("MQTT", 3).client_id("a").build().is_err());
+
+        let mut vec = Connect::builder().client_id("a").build().unwrap().encode().unwrap();
+        vec[8] = 3;
+        match Connect::decode(&mut BytesMut::from(&vec[..])) {
+            Err(ConnectError(ErrorKind::UnsupportedProtocol(_, 3), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut vec = Connect::builder().client_id("a").build().unwrap().encode().unwrap();
+        vec[9] |= 0x01;
+        match Connect::decode(&mut BytesMut::from(&vec[..])) {
+            Err(ConnectError(ErrorKind::ReservedConnectFlag, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_connect_decode_inconsistent_flags(){
+        let vec = Connect::builder().client_id("a").build().unwrap().encode().unwrap();
+        for &flags in &[0x08, 0x10, 0x20] {
+            let mut without_will = vec.clone();
+            without_will[9] |= flags;
+            match Connect::decode(&mut BytesMut::from(&without_will[..])) {
+                Err(ConnectError(ErrorKind::WillFlagsWithoutWill, _)) => {},
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        let mut vec = Connect::builder().client_id("a").user_name("u").password("p").build().unwrap().encode().unwrap();
+        vec[9] &= !0x80;
+        vec.drain(15..18);
+        vec[1] -= 3;
+        match Connect::decode(&mut BytesMut::from(&vec[..])) {
+            Err(ConnectError(ErrorKind::PasswordWithoutUserName, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
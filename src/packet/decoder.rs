@@ -0,0 +1,666 @@
+use std::cmp;
+use bytes::{Buf, Bytes, BytesMut};
+use {Decodable, DecodeContext, ByteStr, ProtocolVersion};
+use topic_name::TopicName;
+use packet::{ValuePacket, Properties, FixedHeaderError, peek_fixed_header, decode_remaining_length, valid_fixed_header_flags, MAX_REMAINING_LENGTH};
+use qos::QualityOfService;
+
+error_chain!{
+    types {
+        DecodeError, ErrorKind, ResultExt, DecodeResult;
+    }
+
+    errors {
+        MalformedPacket(r: String)
+        PacketTooLarge(size: usize, max: usize)
+        TopicTooLong(len: usize, max: usize)
+        TooManySubscriptions(count: usize, max: usize)
+    }
+
+    links {
+        FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
+        ValuePacketError(::packet::ValuePacketError, ::packet::ValuePacketErrorKind);
+        PropertiesError(::packet::PropertiesError, ::packet::PropertiesErrorKind);
+    }
+}
+
+/// Limits enforced by `PacketDecoder`.
+///
+/// `max_packet_size` counts the whole packet including its fixed header and is
+/// checked as soon as the fixed header has been read, before the body is
+/// buffered. `max_string_length` is handed to the field decoders, which refuse
+/// a longer UTF-8 string or binary field before reading it. The topic and
+/// subscription limits are checked once the packet has been decoded. The
+/// defaults are the largest values the protocol can express.
+///
+/// `protocol_version` selects the layout of every packet but CONNECT, which
+/// names its own level; it is set once the CONNECT has been exchanged. It,
+/// `max_string_length` and `strict` make up the `DecodeContext` packets are
+/// decoded with.
+#[derive(Debug, Clone)]
+pub struct DecoderConfig {
+    pub max_packet_size: usize,
+    pub max_topic_length: usize,
+    pub max_subscriptions: usize,
+    pub max_string_length: usize,
+    pub protocol_version: ProtocolVersion,
+    pub strict: bool,
+}
+
+impl Default for DecoderConfig {
+    fn default() -> DecoderConfig {
+        DecoderConfig {
+            max_packet_size: 5 + MAX_REMAINING_LENGTH as usize,
+            max_topic_length: u16::MAX as usize,
+            max_subscriptions: usize::MAX,
+            max_string_length: u16::MAX as usize,
+            protocol_version: ProtocolVersion::V311,
+            strict: false,
+        }
+    }
+}
+
+impl DecoderConfig {
+    /// Context packets are decoded with.
+    pub fn context(&self) -> DecodeContext {
+        DecodeContext {
+            protocol_version: self.protocol_version,
+            max_string_length: self.max_string_length,
+            strict: self.strict,
+        }
+    }
+
+    fn check_packet_size(&self, size: usize) -> Result<(), DecodeError> {
+        if size > self.max_packet_size {
+            bail!(ErrorKind::PacketTooLarge(size, self.max_packet_size));
+        }
+        Ok(())
+    }
+
+    fn check_topic(&self, topic: &str) -> Result<(), DecodeError> {
+        if topic.len() > self.max_topic_length {
+            bail!(ErrorKind::TopicTooLong(topic.len(), self.max_topic_length));
+        }
+        Ok(())
+    }
+
+    fn check_packet(&self, packet: &ValuePacket) -> Result<(), DecodeError> {
+        match *packet {
+            ValuePacket::ConnectPacket(ref connect) => {
+                if let Some(will_topic) = connect.will_topic() {
+                    self.check_topic(will_topic)?;
+                }
+            },
+            ValuePacket::PublishPacket(ref publish) => {
+                self.check_topic(publish.topic_name())?;
+            },
+            ValuePacket::SubscribePacket(ref subscribe) => {
+                let count = subscribe.subscribes().len();
+                if count > self.max_subscriptions {
+                    bail!(ErrorKind::TooManySubscriptions(count, self.max_subscriptions));
+                }
+                for (topic_filter, _) in subscribe.subscribes() {
+                    self.check_topic(topic_filter.as_str())?;
+                }
+            },
+            ValuePacket::UnsubscribePacket(ref unsubscribe) => {
+                for topic_filter in unsubscribe.topic_filters() {
+                    self.check_topic(topic_filter.as_str())?;
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+/// Incremental decoder for a stream of packets.
+///
+/// `decode` only ever consumes whole frames: while the fixed header or the body
+/// announced by it is incomplete it returns `Ok(None)` and leaves the buffer
+/// untouched, so callers can append more bytes and try again. Errors are only
+/// returned for input that can never become a valid packet or that breaks the
+/// configured `DecoderConfig` limits.
+#[derive(Debug, Default)]
+pub struct PacketDecoder {
+    config: DecoderConfig,
+}
+
+impl PacketDecoder {
+    pub fn new() -> PacketDecoder {
+        PacketDecoder::with_config(DecoderConfig::default())
+    }
+
+    pub fn with_config(config: DecoderConfig) -> PacketDecoder {
+        PacketDecoder {
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &DecoderConfig {
+        &self.config
+    }
+
+    pub fn set_protocol_version(&mut self, protocol_version: ProtocolVersion) {
+        self.config.protocol_version = protocol_version;
+    }
+
+    /// Number of bytes still missing before `bytes` holds a whole frame, `0`
+    /// once it does.
+    ///
+    /// While the fixed header is incomplete only the bytes needed to read the
+    /// next part of it are counted, so a reader that fetches exactly this many
+    /// bytes at a time never consumes anything past the end of the packet.
+    pub fn bytes_needed(&self, bytes: &[u8]) -> Result<usize, DecodeError> {
+        match self.frame_length(bytes)? {
+            Some(frame_length) => Ok(frame_length.saturating_sub(bytes.len())),
+            None => Ok(cmp::max(2, bytes.len() + 1) - bytes.len()),
+        }
+    }
+
+    fn frame_length(&self, bytes: &[u8]) -> Result<Option<usize>, DecodeError> {
+        match peek_fixed_header(bytes)? {
+            Some((packet_type, flags, remaining_length, n)) => {
+                if !valid_fixed_header_flags(packet_type, flags) {
+                    bail!(ErrorKind::MalformedPacket(format!("invalid flags {:#06b} for packet type {}", flags, packet_type)));
+                }
+                let frame_length = 1 + n + remaining_length as usize;
+                self.config.check_packet_size(frame_length)?;
+                Ok(Some(frame_length))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn decode(&mut self, bytes: &mut BytesMut) -> Result<Option<ValuePacket>, DecodeError> {
+        let frame_length = match self.frame_length(bytes)? {
+            Some(frame_length) => frame_length,
+            None => return Ok(None),
+        };
+        if bytes.len() < frame_length {
+            bytes.reserve(frame_length - bytes.len());
+            return Ok(None);
+        }
+
+        let mut frame = bytes.split_to(frame_length);
+        let packet = ValuePacket::decode_with(&mut frame, &self.config.context())?;
+        if !frame.is_empty() {
+            bail!(ErrorKind::MalformedPacket(format!("{} unexpected bytes after packet", frame.len())));
+        }
+        self.config.check_packet(&packet)?;
+        Ok(Some(packet))
+    }
+}
+
+/// Item produced by `StreamingDecoder`.
+///
+/// A PUBLISH is reported as `PublishStart`, followed by as many `PublishChunk`s
+/// as it takes to deliver `payload_length` bytes and then `PublishEnd`. Every
+/// other packet is decoded whole.
+#[derive(Debug)]
+pub enum StreamingItem {
+    Packet(ValuePacket),
+    PublishStart(PublishHeader),
+    PublishChunk(Bytes),
+    PublishEnd,
+}
+
+/// Fixed and variable header of a PUBLISH whose payload is still to come.
+#[derive(Debug, Clone)]
+pub struct PublishHeader {
+    topic_name: TopicName,
+    qos: QualityOfService,
+    dup: bool,
+    retain: bool,
+    packet_identifier: Option<u16>,
+    properties: Option<Properties>,
+    payload_length: usize,
+}
+
+impl PublishHeader {
+    pub fn topic_name(&self) -> &str {
+        self.topic_name.as_str()
+    }
+
+    pub fn topic_name_bytes(&self) -> &ByteStr {
+        self.topic_name.as_byte_str()
+    }
+
+    pub fn qos(&self) -> QualityOfService {
+        self.qos
+    }
+
+    pub fn dup(&self) -> bool {
+        self.dup
+    }
+
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+
+    pub fn packet_identifier(&self) -> Option<u16> {
+        self.packet_identifier
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
+    pub fn payload_length(&self) -> usize {
+        self.payload_length
+    }
+}
+
+/// Decoder that hands out PUBLISH payloads as they arrive instead of
+/// buffering the whole packet.
+///
+/// Chunks are split off whatever is buffered, so memory stays bounded by the
+/// read size however large the payload. `max_packet_size` applies to what is
+/// buffered: whole packets other than PUBLISH, and PUBLISH headers.
+#[derive(Debug, Default)]
+pub struct StreamingDecoder {
+    decoder: PacketDecoder,
+    payload_remaining: Option<usize>,
+}
+
+impl StreamingDecoder {
+    pub fn new() -> StreamingDecoder {
+        StreamingDecoder::with_config(DecoderConfig::default())
+    }
+
+    pub fn with_config(config: DecoderConfig) -> StreamingDecoder {
+        StreamingDecoder {
+            decoder: PacketDecoder::with_config(config),
+            payload_remaining: None,
+        }
+    }
+
+    pub fn config(&self) -> &DecoderConfig {
+        self.decoder.config()
+    }
+
+    pub fn set_protocol_version(&mut self, protocol_version: ProtocolVersion) {
+        self.decoder.set_protocol_version(protocol_version);
+    }
+
+    pub fn decode(&mut self, bytes: &mut BytesMut) -> Result<Option<StreamingItem>, DecodeError> {
+        if let Some(remaining) = self.payload_remaining {
+            if remaining == 0 {
+                self.payload_remaining = None;
+                return Ok(Some(StreamingItem::PublishEnd));
+            }
+            if bytes.is_empty() {
+                return Ok(None);
+            }
+            let n = cmp::min(remaining, bytes.len());
+            self.payload_remaining = Some(remaining - n);
+            return Ok(Some(StreamingItem::PublishChunk(bytes.split_to(n).freeze())));
+        }
+
+        match peek_fixed_header(bytes)? {
+            Some((3, flags, remaining_length, n)) => {
+                let header = self.decode_publish_header(bytes, flags, remaining_length as usize, 1 + n)?;
+                if let Some(ref header) = header {
+                    self.payload_remaining = Some(header.payload_length);
+                }
+                Ok(header.map(StreamingItem::PublishStart))
+            },
+            Some(_) => Ok(self.decoder.decode(bytes)?.map(StreamingItem::Packet)),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_publish_header(&self, bytes: &mut BytesMut, flags: u8, remaining_length: usize, start: usize) -> Result<Option<PublishHeader>, DecodeError> {
+        let qos = match QualityOfService::from_u8(flags >> 1 & 0x03) {
+            Ok(qos) => qos,
+            Err(_) => bail!(ErrorKind::MalformedPacket(format!("invalid publish qos {}", flags >> 1 & 0x03))),
+        };
+        let dup = flags & 0x08 == 0x08;
+        if dup && qos == QualityOfService::Level0 {
+            bail!(ErrorKind::MalformedPacket("dup flag set on qos 0 publish".into()));
+        }
+
+        // the topic length and packet identifier must fit before waiting on them
+        let identifier_length = if qos == QualityOfService::Level0 { 0 } else { 2 };
+        if remaining_length < 2 + identifier_length {
+            bail!(ErrorKind::MalformedPacket("remaining length shorter than publish variable header".into()));
+        }
+        if bytes.len() < start + 2 {
+            bytes.reserve(start + 2 - bytes.len());
+            return Ok(None);
+        }
+        let mut header_length = 2 + (&bytes[start..]).get_u16() as usize + identifier_length;
+        if self.config().protocol_version == ProtocolVersion::V5 {
+            if header_length >= remaining_length {
+                bail!(ErrorKind::MalformedPacket("remaining length shorter than publish variable header".into()));
+            }
+            if bytes.len() < start + header_length + 1 {
+                bytes.reserve(start + header_length + 1 - bytes.len());
+                return Ok(None);
+            }
+            let (properties_length, n) = match decode_remaining_length(&bytes[start + header_length..]) {
+                Ok(length) => length,
+                Err(FixedHeaderError(::packet::ErrorKind::NoEnoughBytes(_), _)) => {
+                    bytes.reserve(1);
+                    return Ok(None);
+                },
+                Err(err) => return Err(err.into()),
+            };
+            header_length += n + properties_length as usize;
+        }
+        if header_length > remaining_length {
+            bail!(ErrorKind::MalformedPacket("remaining length shorter than publish variable header".into()));
+        }
+        self.config().check_packet_size(start + header_length)?;
+        if bytes.len() < start + header_length {
+            bytes.reserve(start + header_length - bytes.len());
+            return Ok(None);
+        }
+
+        let ctx = self.config().context();
+        let mut header = bytes.split_to(start + header_length);
+        header.advance(start);
+        let topic_name = match TopicName::decode_with(&mut header, &ctx) {
+            Ok(topic_name) => topic_name,
+            Err(err) => bail!(ErrorKind::MalformedPacket(format!("invalid publish topic: {}", err))),
+        };
+        self.config().check_topic(topic_name.as_str())?;
+        let packet_identifier = if identifier_length > 0 { Some(header.get_u16()) } else { None };
+        let properties = match ctx.protocol_version {
+            ProtocolVersion::V5 => Some(Properties::decode_with(&mut header, &ctx)?),
+            _ => None,
+        };
+
+        Ok(Some(PublishHeader {
+            topic_name,
+            qos,
+            dup,
+            retain: flags & 0x01 == 0x01,
+            packet_identifier,
+            properties,
+            payload_length: remaining_length - header_length,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Encodable;
+    use packet::{PingReq, Publish, Subscribe, Connect};
+    use qos::QualityOfService;
+
+    #[test]
+    fn test_decode_partial_frames() {
+        let vec = Publish::builder().topic_name("a/b").payload(vec![0u8; 300]).build().unwrap().encode().unwrap();
+        let mut decoder = PacketDecoder::new();
+        let mut bytes = BytesMut::new();
+
+        for (i, &byte) in vec[..vec.len() - 1].iter().enumerate() {
+            bytes.extend_from_slice(&[byte]);
+            assert!(decoder.decode(&mut bytes).unwrap().is_none());
+            assert_eq!(&bytes[..], &vec[..i + 1]);
+        }
+
+        bytes.extend_from_slice(&vec[vec.len() - 1..]);
+        match decoder.decode(&mut bytes).unwrap() {
+            Some(ValuePacket::PublishPacket(publish)) => assert_eq!(publish.payload().len(), 300),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_bytes_needed() {
+        let vec = Publish::builder().topic_name("a/b").payload(vec![0u8; 300]).build().unwrap().encode().unwrap();
+        let decoder = PacketDecoder::new();
+
+        assert_eq!(decoder.bytes_needed(&[]).unwrap(), 2);
+        assert_eq!(decoder.bytes_needed(&vec[..1]).unwrap(), 1);
+        assert_eq!(decoder.bytes_needed(&vec[..2]).unwrap(), 1);
+        assert_eq!(decoder.bytes_needed(&vec[..3]).unwrap(), vec.len() - 3);
+        assert_eq!(decoder.bytes_needed(&vec).unwrap(), 0);
+
+        let decoder = PacketDecoder::with_config(DecoderConfig {
+            max_packet_size: 16,
+            ..DecoderConfig::default()
+        });
+        assert!(decoder.bytes_needed(&vec[..3]).is_err());
+    }
+
+    #[test]
+    fn test_streaming_decode_publish() {
+        let payload: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut vec = Publish::builder().topic_name("fw/image").qos(QualityOfService::Level1).packet_identifier(9)
+                                        .payload(payload.clone()).build().unwrap().encode().unwrap();
+        vec.extend(PingReq::builder().build().unwrap().encode().unwrap());
+
+        let mut decoder = StreamingDecoder::new();
+        let mut bytes = BytesMut::new();
+        let mut items = Vec::new();
+        for piece in vec.chunks(64) {
+            bytes.extend_from_slice(piece);
+            while let Some(item) = decoder.decode(&mut bytes).unwrap() {
+                items.push(item);
+            }
+            assert!(bytes.len() < 64);
+        }
+        assert!(bytes.is_empty());
+
+        match items.remove(0) {
+            StreamingItem::PublishStart(header) => {
+                assert_eq!(header.topic_name(), "fw/image");
+                assert_eq!(header.qos(), QualityOfService::Level1);
+                assert_eq!(header.packet_identifier(), Some(9));
+                assert_eq!(header.payload_length(), 1000);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut received = Vec::new();
+        while let StreamingItem::PublishChunk(chunk) = items.remove(0) {
+            received.extend_from_slice(&chunk);
+        }
+        assert_eq!(received, payload);
+        match items.remove(0) {
+            StreamingItem::Packet(ValuePacket::PingReqPacket(_)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn test_streaming_decode_empty_payload() {
+        let vec = Publish::builder().topic_name("a").build().unwrap().encode().unwrap();
+        let mut decoder = StreamingDecoder::new();
+        let mut bytes = BytesMut::from(&vec[..2]);
+        assert!(decoder.decode(&mut bytes).unwrap().is_none());
+        bytes.extend_from_slice(&vec[2..]);
+        match decoder.decode(&mut bytes).unwrap() {
+            Some(StreamingItem::PublishStart(ref header)) if header.payload_length() == 0 => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match decoder.decode(&mut bytes).unwrap() {
+            Some(StreamingItem::PublishEnd) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(decoder.decode(&mut bytes).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_streaming_decode_malformed() {
+        let malformed: &[&[u8]] = &[
+            &[0x36, 0x05, 0x00, 0x01, b'a', 0x00, 0x01],
+            &[0x38, 0x03, 0x00, 0x01, b'a'],
+            &[0x30, 0x02, 0x00, 0x03, b'a', b'b', b'c'],
+            &[0x30, 0x04, 0x00, 0x02, 0xC3, 0x28],
+            &[0x30, 0x00],
+            &[0x30, 0x01, 0x00],
+            &[0x32, 0x03, 0x00, 0x01],
+        ];
+        for bytes in malformed {
+            assert!(StreamingDecoder::new().decode(&mut BytesMut::from(&bytes[..])).is_err(), "{:?}", bytes);
+        }
+
+        let config = DecoderConfig {
+            max_packet_size: 16,
+            ..DecoderConfig::default()
+        };
+        let vec = Publish::builder().topic_name("a").payload(vec![0u8; 100]).build().unwrap().encode().unwrap();
+        assert!(StreamingDecoder::with_config(config.clone()).decode(&mut BytesMut::from(&vec[..])).unwrap().is_some());
+        let vec = Publish::builder().topic_name("a/very/long/topic").build().unwrap().encode().unwrap();
+        match StreamingDecoder::with_config(config).decode(&mut BytesMut::from(&vec[..])) {
+            Err(DecodeError(ErrorKind::PacketTooLarge(21, 16), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_consecutive_frames() {
+        let mut vec = PingReq::builder().build().unwrap().encode().unwrap();
+        vec.extend(Publish::builder().topic_name("a").build().unwrap().encode().unwrap());
+        vec.extend(&[0xC0]);
+
+        let mut decoder = PacketDecoder::new();
+        let mut bytes = BytesMut::from(&vec[..]);
+        match decoder.decode(&mut bytes).unwrap() {
+            Some(ValuePacket::PingReqPacket(_)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match decoder.decode(&mut bytes).unwrap() {
+            Some(ValuePacket::PublishPacket(publish)) => assert_eq!(publish.topic_name(), "a"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(decoder.decode(&mut bytes).unwrap().is_none());
+        assert_eq!(&bytes[..], &[0xC0]);
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        let mut decoder = PacketDecoder::new();
+
+        let mut bytes = BytesMut::from(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01][..]);
+        assert!(decoder.decode(&mut bytes).is_err());
+
+        let mut bytes = BytesMut::from(&[0x40, 0x01, 0x00][..]);
+        assert!(decoder.decode(&mut bytes).is_err());
+
+        let mut bytes = BytesMut::from(&[0x40, 0x03, 0x00, 0x01, 0x00][..]);
+        match decoder.decode(&mut bytes) {
+            Err(DecodeError(ErrorKind::MalformedPacket(_), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut bytes = BytesMut::from(&[0x00, 0x00][..]);
+        assert!(decoder.decode(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn test_decode_packet_too_large() {
+        let mut decoder = PacketDecoder::with_config(DecoderConfig {
+            max_packet_size: 1024,
+            ..DecoderConfig::default()
+        });
+
+        let mut bytes = BytesMut::from(&[0x30, 0x80, 0x80, 0x80, 0x01][..]);
+        match decoder.decode(&mut bytes) {
+            Err(DecodeError(ErrorKind::PacketTooLarge(2_097_157, 1024), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(bytes.len(), 5);
+
+        let vec = Publish::builder().topic_name("a").payload(vec![0u8; 1018]).build().unwrap().encode().unwrap();
+        assert_eq!(vec.len(), 1024);
+        let mut bytes = BytesMut::from(&vec[..]);
+        assert!(decoder.decode(&mut bytes).unwrap().is_some());
+
+        let vec = Publish::builder().topic_name("a").payload(vec![0u8; 1019]).build().unwrap().encode().unwrap();
+        let mut bytes = BytesMut::from(&vec[..4]);
+        match decoder.decode(&mut bytes) {
+            Err(DecodeError(ErrorKind::PacketTooLarge(1025, 1024), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_field_limits() {
+        let config = DecoderConfig {
+            max_topic_length: 3,
+            max_subscriptions: 1,
+            max_string_length: 4,
+            ..DecoderConfig::default()
+        };
+
+        let vec = Publish::builder().topic_name("a/bc").build().unwrap().encode().unwrap();
+        match PacketDecoder::with_config(config.clone()).decode(&mut BytesMut::from(&vec[..])) {
+            Err(DecodeError(ErrorKind::TopicTooLong(4, 3), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let vec = Subscribe::builder()
+                    .packet_identifier(1)
+                    .subscribe("a", QualityOfService::Level0)
+                    .subscribe("b", QualityOfService::Level0)
+                    .build().unwrap().encode().unwrap();
+        match PacketDecoder::with_config(config.clone()).decode(&mut BytesMut::from(&vec[..])) {
+            Err(DecodeError(ErrorKind::TooManySubscriptions(2, 1), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let vec = Connect::builder().client_id("enjie").build().unwrap().encode().unwrap();
+        assert!(PacketDecoder::new().decode(&mut BytesMut::from(&vec[..])).unwrap().is_some());
+        match PacketDecoder::with_config(config.clone()).decode(&mut BytesMut::from(&vec[..])) {
+            Err(DecodeError(ErrorKind::ValuePacketError(_), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(config.context().max_string_length, 4);
+
+        let vec = Connect::builder().client_id("a").user_name("u").password(vec![0u8; 5]).build().unwrap().encode().unwrap();
+        assert!(PacketDecoder::with_config(config).decode(&mut BytesMut::from(&vec[..])).is_err());
+    }
+
+    #[test]
+    fn test_decode_v5_packets() {
+        use packet::{PubAck, Properties, Property};
+        let properties = Properties::new().with(Property::ContentType("text/plain".into())).unwrap();
+        let mut vec = Publish::builder().protocol_version(ProtocolVersion::V5).topic_name("a/b")
+                                        .properties(properties.clone()).payload(vec![1u8; 10]).build().unwrap().encode().unwrap();
+        vec.extend(PubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(3).reason_code(::PubAckReasonCode::NoMatchingSubscribers)
+                                    .build().unwrap().encode().unwrap());
+
+        let mut decoder = PacketDecoder::new();
+        decoder.set_protocol_version(ProtocolVersion::V5);
+        let mut bytes = BytesMut::from(&vec[..]);
+        match decoder.decode(&mut bytes).unwrap() {
+            Some(ValuePacket::PublishPacket(ref publish)) => assert_eq!(publish.properties(), Some(&properties)),
+            other => panic!("unexpected {:?}", other),
+        }
+        match decoder.decode(&mut bytes).unwrap() {
+            Some(ValuePacket::PubAckPacket(ref puback)) => assert_eq!(puback.reason_code(), ::PubAckReasonCode::NoMatchingSubscribers),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_protocol_version(ProtocolVersion::V5);
+        let mut bytes = BytesMut::new();
+        let mut items = Vec::new();
+        for &byte in &vec[..] {
+            bytes.extend_from_slice(&[byte]);
+            while let Some(item) = decoder.decode(&mut bytes).unwrap() {
+                items.push(item);
+            }
+        }
+        match items.remove(0) {
+            StreamingItem::PublishStart(header) => {
+                assert_eq!(header.topic_name(), "a/b");
+                assert_eq!(header.properties(), Some(&properties));
+                assert_eq!(header.payload_length(), 10);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        match items.pop() {
+            Some(StreamingItem::Packet(ValuePacket::PubAckPacket(_))) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
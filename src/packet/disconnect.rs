@@ -1,13 +1,19 @@
-use {Encodable, Decodable};
-use packet::FixedHeader;
-use bytes::BytesMut;
+use {Encodable, Decodable, ProtocolVersion, DisconnectReasonCode, DecodeContext, EncodeContext};
+use packet::{FixedHeader, Properties};
+use bytes::{BytesMut, Buf, BufMut};
 
 error_chain!{
     types{
         DisconnectError, ErrorKind, ResultExt, DisconnectResult;
     }
+    errors{
+        ReasonCodeRequiresV5
+        PropertiesRequireV5
+    }
     links{
         FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
+        ReasonCode(::reason_code::ReasonCodeError, ::reason_code::ErrorKind);
     }
 }
 
@@ -36,12 +42,11 @@ impl FixedHeader for DisconnectFixedHeader {
 impl<'a> Decodable<'a> for DisconnectFixedHeader{
 
     type Error = DisconnectError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
         match Self::get_fixheader(byte) {
             Ok((packet_type, _, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(DisconnectFixedHeader {
                     packet_type: packet_type,
                     remaining_length: remaining_length,
@@ -54,10 +59,9 @@ impl<'a> Decodable<'a> for DisconnectFixedHeader{
 
 impl Encodable for DisconnectFixedHeader{
     type Error = DisconnectError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        Self::encode_fixedheader(self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, 0u8, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -68,40 +72,134 @@ impl Encodable for DisconnectFixedHeader{
 #[derive(Debug)]
 pub struct Disconnect{
     fixed_header: DisconnectFixedHeader,  
+    reason_code: DisconnectReasonCode,
+    properties: Option<Properties>,
 }
 
 impl Disconnect {
-    fn new() -> Disconnect{
-        Disconnect{
-            fixed_header: DisconnectFixedHeader::new()
+    pub fn builder() -> DisconnectBuilder {
+        DisconnectBuilder::new()
+    }
+
+    /// MQTT 5 reason code; always the success code for a 3.1.1 packet.
+    pub fn reason_code(&self) -> DisconnectReasonCode {
+        self.reason_code
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
+    /// Length of the MQTT 5 reason code and properties. Both are left out while
+    /// they hold their defaults, normal disconnection and no properties.
+    fn reason_length(&self) -> Result<u32, DisconnectError> {
+        match self.properties {
+            Some(ref properties) if !properties.is_empty() => Ok(1 + properties.encode_length()?),
+            Some(_) if !self.reason_code.is_default() => Ok(1),
+            _ => Ok(0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DisconnectBuilder {
+    protocol_version: ProtocolVersion,
+    reason_code: DisconnectReasonCode,
+    properties: Option<Properties>,
+}
+
+impl DisconnectBuilder {
+    fn new() -> DisconnectBuilder {
+        DisconnectBuilder {
+            protocol_version: ProtocolVersion::V311,
+            reason_code: DisconnectReasonCode::default(),
+            properties: None,
+        }
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> DisconnectBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the MQTT 5 reason code; only valid with `ProtocolVersion::V5`.
+    pub fn reason_code(mut self, reason_code: DisconnectReasonCode) -> DisconnectBuilder {
+        self.reason_code = reason_code;
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with `ProtocolVersion::V5`.
+    pub fn properties(mut self, properties: Properties) -> DisconnectBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn build(self) -> Result<Disconnect, DisconnectError> {
+        let properties = match (self.protocol_version, self.properties) {
+            (ProtocolVersion::V5, properties) => Some(properties.unwrap_or_default()),
+            (_, None) => None,
+            (_, Some(_)) => bail!(ErrorKind::PropertiesRequireV5),
+        };
+        if properties.is_none() && !self.reason_code.is_default() {
+            bail!(ErrorKind::ReasonCodeRequiresV5);
         }
+
+        let mut packet = Disconnect {
+            fixed_header: DisconnectFixedHeader::new(),
+            reason_code: self.reason_code,
+            properties,
+        };
+        packet.fixed_header.remaining_length = packet.reason_length()?;
+        DisconnectFixedHeader::get_remaining_length_bytes(packet.fixed_header.remaining_length)?;
+        Ok(packet)
     }
 }
 
 impl<'a> Decodable<'a> for Disconnect{
     type Error = DisconnectError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header: DisconnectFixedHeader = Decodable::decode_with(byte, ctx)?;
+        let (reason_code, properties) = match ctx.protocol_version {
+            ProtocolVersion::V5 => {
+                let reason_code = if fixed_header.remaining_length > 0 { Decodable::decode_with(byte, ctx)? } else { DisconnectReasonCode::default() };
+                let properties = if fixed_header.remaining_length > 1 { Decodable::decode_with(byte, ctx)? } else { Properties::new() };
+                (reason_code, Some(properties))
+            },
+            _ => (DisconnectReasonCode::default(), None),
+        };
 
-        Ok(Disconnect{
+        let mut packet = Disconnect{
             fixed_header: fixed_header,
-        })
+            reason_code,
+            properties,
+        };
+        packet.fixed_header.remaining_length = packet.reason_length()?;
+        Ok(packet)
     }
 }
 
 impl Encodable for Disconnect {
     type Error = DisconnectError;
-    type Cond = ();
 
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        self.fixed_header.encode().map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        if self.reason_length()? > 0 {
+            self.reason_code.encode_to_with(dst, ctx)?;
+        }
+        if let Some(ref properties) = self.properties {
+            if !properties.is_empty() {
+                properties.encode_to_with(dst, ctx)?;
+            }
+        }
+
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
-        self.fixed_header.encode_length().map_err(From::from)
+        Ok(self.fixed_header.encode_length()? + self.reason_length()?)
     }
 }
 
@@ -110,13 +208,40 @@ mod test{
     use super::*;
     #[test]
     fn test_disconnect_encode_decode(){
-        let packet = Disconnect::new();
+        let packet = Disconnect::builder().build().unwrap();
         let vecbytes = packet.encode().unwrap();
 
         //println!("{:?}", vecbytes);
+        assert_eq!(vecbytes, vec![0xE0, 0x00]);
 
-        let mut bytes = BytesMut::from(vecbytes);
+        let mut bytes = BytesMut::from(&vecbytes[..]);
         let result = Disconnect::decode(&mut bytes);
         //println!("{:?}", result);
+        assert_eq!(result.unwrap().encode().unwrap(), vecbytes);
+    }
+
+    #[test]
+    fn test_disconnect_encode_decode_v5(){
+        let packet = Disconnect::builder().protocol_version(ProtocolVersion::V5).build().unwrap();
+        assert_eq!(packet.encode().unwrap(), vec![0xE0, 0x00]);
+
+        let properties = Properties::new().with(::packet::Property::SessionExpiryInterval(0)).unwrap();
+        let packet = Disconnect::builder()
+                        .protocol_version(ProtocolVersion::V5)
+                        .reason_code(DisconnectReasonCode::DisconnectWithWillMessage)
+                        .properties(properties.clone())
+                        .build()
+                        .unwrap();
+        let vecbytes = packet.encode().unwrap();
+        assert_eq!(vecbytes, vec![0xE0, 0x07, 0x04, 0x05, 0x11, 0x00, 0x00, 0x00, 0x00]);
+
+        let result = Disconnect::decode_with(&mut BytesMut::from(&vecbytes[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(result.encode().unwrap(), vecbytes);
+        assert_eq!(result.reason_code(), DisconnectReasonCode::DisconnectWithWillMessage);
+        assert_eq!(result.properties(), Some(&properties));
+
+        let result = Disconnect::decode_with(&mut BytesMut::from(&[0xE0, 0x01, 0x04][..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(result.encode().unwrap(), vec![0xE0, 0x01, 0x04]);
+        assert!(Disconnect::builder().reason_code(DisconnectReasonCode::DisconnectWithWillMessage).build().is_err());
     }
 }
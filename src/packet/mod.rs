@@ -1,4 +1,4 @@
-use bytes::BytesMut;
+use bytes::{BytesMut, BufMut};
 use std::fmt;
 use std::error::Error;
 
@@ -17,104 +17,137 @@ mod unsuback;
 mod pingreq;
 mod pingresp;
 mod disconnect;
+mod auth;
 mod value_packet;
+mod decoder;
+mod borrowed;
+mod vectored;
+mod properties;
 
-pub use self::connect::{ Connect, ConnectError, ErrorKind as ConnectErrorKind };
-pub use self::connack::{ Connack, ConnackError, ErrorKind as ConnackErrorKind };
-pub use self::publish::{ Publish, PublishError, ErrorKind as PublishErrorKind };
-pub use self::puback::{ PubAck, PubAckError, ErrorKind as PubAckErrorKind };
-pub use self::pubrec::{ PubRec, PubRecError, ErrorKind as PubRecErrorKind };
-pub use self::pubrel::{ PubRel, PubRelError, ErrorKind as PubRelErrorKind };
-pub use self::pubcomp::{ PubComp, PubCompError, ErrorKind as PubCompErrorKind };
-pub use self::subscribe::{ Subscribe, SubscribeError, ErrorKind as SubscribeErrorKind };
-pub use self::suback::{ SubAck, SubAckError, ErrorKind as SubAckErrorKind };
-pub use self::unsubscribe::{ Unsubscribe, UnsubscribeError, ErrorKind as UnsubscribeErrorKind };
-pub use self::unsuback::{ UnSubAck, UnSubAckError, ErrorKind as UnSubAckErrorKind };
-pub use self::pingreq::{ PingReq, PingReqError, ErrorKind as PingReqErrorKind };
-pub use self::pingresp::{ PingResp, PingRespError, ErrorKind as PingRespErrorKind };
-pub use self::disconnect::{ Disconnect, DisconnectError, ErrorKind as DisconnectErrorKind };
+pub use self::connect::{ Connect, ConnectBuilder, ConnectError, ErrorKind as ConnectErrorKind };
+pub use self::connack::{ Connack, ConnackBuilder, ConnackError, ErrorKind as ConnackErrorKind };
+pub use self::publish::{ Publish, PublishBuilder, PublishError, ErrorKind as PublishErrorKind };
+pub use self::puback::{ PubAck, PubAckBuilder, PubAckError, ErrorKind as PubAckErrorKind };
+pub use self::pubrec::{ PubRec, PubRecBuilder, PubRecError, ErrorKind as PubRecErrorKind };
+pub use self::pubrel::{ PubRel, PubRelBuilder, PubRelError, ErrorKind as PubRelErrorKind };
+pub use self::pubcomp::{ PubComp, PubCompBuilder, PubCompError, ErrorKind as PubCompErrorKind };
+pub use self::subscribe::{ Subscribe, SubscribeBuilder, Subscriptions, SubscriptionOptions, RetainHandling, SubscribeError,
+                           ErrorKind as SubscribeErrorKind };
+pub use self::suback::{ SubAck, SubAckBuilder, SubAckError, ErrorKind as SubAckErrorKind };
+pub use self::unsubscribe::{ Unsubscribe, UnsubscribeBuilder, UnsubscribeError, ErrorKind as UnsubscribeErrorKind };
+pub use self::unsuback::{ UnSubAck, UnSubAckBuilder, UnSubAckError, ErrorKind as UnSubAckErrorKind };
+pub use self::pingreq::{ PingReq, PingReqBuilder, PingReqError, ErrorKind as PingReqErrorKind };
+pub use self::pingresp::{ PingResp, PingRespBuilder, PingRespError, ErrorKind as PingRespErrorKind };
+pub use self::disconnect::{ Disconnect, DisconnectBuilder, DisconnectError, ErrorKind as DisconnectErrorKind };
+pub use self::auth::{ Auth, AuthBuilder, AuthError, ErrorKind as AuthErrorKind };
 pub use self::value_packet::{ ValuePacket, ValuePacketError, ErrorKind as ValuePacketErrorKind };
+pub use self::decoder::{ PacketDecoder, DecoderConfig, StreamingDecoder, StreamingItem, PublishHeader, DecodeError, ErrorKind as DecodeErrorKind };
+pub use self::vectored::VectoredPacket;
+pub use self::properties::{ Property, Properties, PropertiesError, ErrorKind as PropertiesErrorKind };
+pub use self::borrowed::{ ValuePacketRef, ConnectRef, ConnackRef, PublishRef, SubscribeRef, SubAckRef, UnsubscribeRef,
+                         Subscribes, ReturnCodes, TopicFilters, ValuePacketRefError, ErrorKind as ValuePacketRefErrorKind };
 
 
+/// Largest value the four byte remaining length field can carry (MQTT 3.1.1 §2.2.3).
+pub const MAX_REMAINING_LENGTH: u32 = 268_435_455;
+
 pub trait FixedHeader {
     //fn new() -> Self;
      
     fn set_remaining_length(&mut self, len: u32);
     
     fn get_fixheader(bytes: &mut BytesMut) -> Result<(u8, u8, u32, usize), FixedHeaderError> {
-        let len = bytes.len();
-        let mut control_packet_type = 0u8;
-        let mut reserved_code = 0u8;
-        if len >= 2 {
-            control_packet_type = bytes[0] >> 4;
-            reserved_code = bytes[0] & 0x0f
-        } else {
-            // return Err(FixedHeaderError::NoEnoughBytes);
-            bail!(ErrorKind::NoEnoughBytes("enjie".into()));
+        match peek_fixed_header(bytes)? {
+            Some(fixed_header) => Ok(fixed_header),
+            None => bail!(ErrorKind::NoEnoughBytes("fixed header is incomplete".into())),
         }
+    }
 
-        let mut n = 1;
-        let mut sum = 0u32;
-        while n < 5 {
-            let a = bytes[n];
-            let k = (((a & 0x7f) as u32) << (7 * (n - 1))) as u32;
-            sum = sum | k;
-            if a / 128 > 0 {
-                n += 1;
-                if n + 1 > len {
-                    // return Err(FixedHeaderError::NoEnoughBytes);
-                    bail!(ErrorKind::NoEnoughBytes("enjie".into()));
-                }
-            } else {
-                return Ok((control_packet_type, reserved_code, sum, n));
+    fn encode_fixedheader<B: BufMut>(dst: &mut B, packet_type: u8, reserved: u8, remaining_length: u32) -> Result<(), FixedHeaderError> {
+        remaining_length_size(remaining_length)?;
+        dst.put_u8((packet_type << 4) | reserved);
+        encode_remaining_length(dst, remaining_length)
+    }
+
+    fn get_remaining_length_bytes(remaining_length: u32) -> Result<u32, FixedHeaderError> {
+        Ok(1 + remaining_length_size(remaining_length)? as u32)
+    }
+}
+
+/// Parses the fixed header at the start of `bytes` without consuming anything.
+///
+/// Returns the packet type, the flags, the remaining length and the number of
+/// bytes used by the remaining length field, or `Ok(None)` while the header
+/// itself is still incomplete.
+fn peek_fixed_header(bytes: &[u8]) -> Result<Option<(u8, u8, u32, usize)>, FixedHeaderError> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    match decode_remaining_length(&bytes[1..]) {
+        Ok((remaining_length, n)) => Ok(Some((bytes[0] >> 4, bytes[0] & 0x0f, remaining_length, n))),
+        Err(FixedHeaderError(ErrorKind::NoEnoughBytes(_), _)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether `flags` are the fixed header flags required for `packet_type`
+/// (MQTT 3.1.1 §2.2.2). PUBREL, SUBSCRIBE and UNSUBSCRIBE carry `0b0010`, the
+/// others but PUBLISH, whose flags are checked with the rest of it, carry `0`.
+fn valid_fixed_header_flags(packet_type: u8, flags: u8) -> bool {
+    match packet_type {
+        3 => true,
+        6 | 8 | 10 => flags == 0x02,
+        _ => flags == 0,
+    }
+}
+
+/// Decodes the variable length remaining length field at the start of `bytes`,
+/// returning the value and the number of bytes it occupies.
+///
+/// Encodings longer than four bytes, or longer than needed for the value they
+/// carry, are malformed; a field cut short by the end of `bytes` is reported as
+/// `NoEnoughBytes`.
+fn decode_remaining_length(bytes: &[u8]) -> Result<(u32, usize), FixedHeaderError> {
+    let mut value = 0u32;
+    for (i, &byte) in bytes.iter().take(4).enumerate() {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            if i > 0 && byte == 0 {
+                bail!(ErrorKind::MalformedRemainingLength("non minimal remaining length encoding".into()));
             }
+            return Ok((value, i + 1));
         }
-        // Err(FixedHeaderError::RemainLengthAvailable)
-        Err(ErrorKind::NoEnoughBytes("enjie".into()).into())
-    }
-
-    fn encode_fixedheader(packet_type: u8, reserved: u8, remaining_length: u32) -> Result<Vec<u8>, FixedHeaderError> {
-        let mut vec = vec![];
-        let first_u8 = (packet_type << 4) | reserved;
-        vec.push(first_u8);
-
-        if remaining_length >= 0 && remaining_length <= 127 {
-            vec.push(remaining_length as u8);
-        }else if remaining_length >= 128 && remaining_length <= 16_383 {
-            vec.push((remaining_length & 0x7f) as u8);
-            vec.push(( remaining_length >> 7 | 0x80 ) as u8);
-        }else if remaining_length >= 16_384 && remaining_length <= 2_097_151 {
-            vec.push(( remaining_length & 0x7f ) as u8);
-            vec.push(( remaining_length >> 7 & 0x7f ) as u8);
-            vec.push(( remaining_length >> 14 | 0x80 ) as u8);
-        }else if remaining_length >= 2_097_152 && remaining_length <= 268_435_455 {
-            vec.push(( remaining_length & 0x7f ) as u8);
-            vec.push(( remaining_length >> 7 & 0x7f ) as u8);
-            vec.push(( remaining_length >> 14 & 0x7f ) as u8);
-            vec.push(( remaining_length >> 21 | 0x80 ) as u8);
-        }else{
-            // return Err(FixedHeaderError::RemainLengthAvailable);
-            bail!(ErrorKind::RemainLengthAvailable("enjie".into()));
-        }
-        Ok(vec)
-        
     }
 
-    fn get_remaining_length_bytes(remaining_length: u32) -> Result<u32, FixedHeaderError> {
-        let mut num = 1u32;
-        if remaining_length >= 0 && remaining_length <= 127 {
-            num += 1;
-        }else if remaining_length >= 128 && remaining_length <= 16_383 {
-            num += 2;
-        }else if remaining_length >= 16_384 && remaining_length <= 2_097_151 {
-            num += 3;
-        }else if remaining_length >= 2_097_152 && remaining_length <= 268_435_455 {
-            num += 4;
-        }else{
-            // return Err(FixedHeaderError::RemainLengthAvailable);
-            bail!(ErrorKind::RemainLengthAvailable("enjie".into()));
+    if bytes.len() >= 4 {
+        bail!(ErrorKind::MalformedRemainingLength("remaining length longer than 4 bytes".into()));
+    }
+    bail!(ErrorKind::NoEnoughBytes("remaining length is incomplete".into()))
+}
+
+fn encode_remaining_length<B: BufMut>(dst: &mut B, remaining_length: u32) -> Result<(), FixedHeaderError> {
+    remaining_length_size(remaining_length)?;
+    let mut value = remaining_length;
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value > 0 {
+            byte |= 0x80;
+        }
+        dst.put_u8(byte);
+        if value == 0 {
+            return Ok(());
         }
-        Ok(num)
+    }
+}
+
+fn remaining_length_size(remaining_length: u32) -> Result<usize, FixedHeaderError> {
+    match remaining_length {
+        0..=127 => Ok(1),
+        128..=16_383 => Ok(2),
+        16_384..=2_097_151 => Ok(3),
+        2_097_152..=MAX_REMAINING_LENGTH => Ok(4),
+        _ => bail!(ErrorKind::RemainLengthAvailable(format!("remaining length {} exceeds {}", remaining_length, MAX_REMAINING_LENGTH))),
     }
 }
 
@@ -128,6 +161,7 @@ error_chain!{
     errors{
         NoEnoughBytes(r: String)
         RemainLengthAvailable(r: String)
+        MalformedRemainingLength(r: String)
     }
 }
 
@@ -142,17 +176,110 @@ mod tests {
         // let enjie = Enjie;
 
         // let vec = vec![241u8, 120u8];
-        // let mut b = BytesMut::from(vec);
+        // let mut b = BytesMut::from(&vec[..]);
 
 
         // println!("{:?}", enjie.decode(&mut b));
 
         let vec = vec![241u8, 0xFF, 0xFF, 0xFF, 0x8f];
-        let mut b = BytesMut::from(vec);
+        let mut b = BytesMut::from(&vec[..]);
         //let result = Enjie::decode(&mut b);
         //println!("{:?}", result);
         // println!("{:?}", b[0]);
         //println!("{}", b.len());
+        match Enjie::get_fixheader(&mut b) {
+            Err(FixedHeaderError(ErrorKind::MalformedRemainingLength(_), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut b = BytesMut::from(&[0x30u8, 0xC8, 0x01][..]);
+        assert_eq!(Enjie::get_fixheader(&mut b).unwrap(), (3, 0, 200, 2));
+    }
+
+    struct Enjie;
+    impl FixedHeader for Enjie {
+        fn set_remaining_length(&mut self, _len: u32) {}
+    }
+
+    const BOUNDARIES: &'static [(u32, &'static [u8])] = &[
+        (0, &[0x00]),
+        (127, &[0x7F]),
+        (128, &[0x80, 0x01]),
+        (16_383, &[0xFF, 0x7F]),
+        (16_384, &[0x80, 0x80, 0x01]),
+        (2_097_151, &[0xFF, 0xFF, 0x7F]),
+        (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+        (268_435_455, &[0xFF, 0xFF, 0xFF, 0x7F]),
+    ];
+
+    #[test]
+    fn test_encode_remaining_length_boundaries() {
+        for &(value, encoded) in BOUNDARIES {
+            let mut vec = Vec::new();
+            encode_remaining_length(&mut vec, value).unwrap();
+            assert_eq!(vec, encoded.to_vec(), "{}", value);
+            assert_eq!(Enjie::get_remaining_length_bytes(value).unwrap(), 1 + encoded.len() as u32);
+
+            let mut header = vec![0x10];
+            header.extend(encoded);
+            let mut vec = Vec::new();
+            Enjie::encode_fixedheader(&mut vec, 1, 0, value).unwrap();
+            assert_eq!(vec, header);
+        }
+        let mut vec = Vec::new();
+        assert!(encode_remaining_length(&mut vec, 268_435_456).is_err());
+        assert!(Enjie::get_remaining_length_bytes(u32::MAX).is_err());
+        assert!(Enjie::encode_fixedheader(&mut vec, 1, 0, 268_435_456).is_err());
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn test_decode_remaining_length_boundaries() {
+        for &(value, encoded) in BOUNDARIES {
+            assert_eq!(decode_remaining_length(encoded).unwrap(), (value, encoded.len()));
+
+            let mut trailing = encoded.to_vec();
+            trailing.push(0xFF);
+            assert_eq!(decode_remaining_length(&trailing).unwrap(), (value, encoded.len()));
+
+            for cut in 0..encoded.len() {
+                match decode_remaining_length(&encoded[..cut]) {
+                    Err(FixedHeaderError(ErrorKind::NoEnoughBytes(_), _)) => {},
+                    other => panic!("unexpected {:?} for {} cut at {}", other, value, cut),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_malformed_remaining_length() {
+        let malformed: &[&[u8]] = &[
+            &[0xFF, 0xFF, 0xFF, 0xFF, 0x7F],
+            &[0x80, 0x80, 0x80, 0x80, 0x01],
+            &[0xFF, 0xFF, 0xFF, 0xFF],
+            &[0x80, 0x00],
+            &[0xFF, 0x80, 0x00],
+            &[0x80, 0x80, 0x80, 0x00],
+        ];
+        for bytes in malformed {
+            match decode_remaining_length(bytes) {
+                Err(FixedHeaderError(ErrorKind::MalformedRemainingLength(_), _)) => {},
+                other => panic!("unexpected {:?} for {:?}", other, bytes),
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_packet_roundtrip() {
+        use {Decodable, Encodable};
+        let payload = vec![0xA5u8; 20_000];
+        let publish = Publish::builder().topic_name("a/b").payload(payload.clone()).build().unwrap();
+        let vec = publish.encode().unwrap();
+        assert_eq!(&vec[..4], &[0x30, 0xA5, 0x9C, 0x01]);
+
+        let mut bytes = BytesMut::from(&vec[..]);
+        let decoded = Publish::decode(&mut bytes).unwrap();
+        assert_eq!(decoded.payload(), &payload[..]);
     }
 
     //#[test]
@@ -1,6 +1,6 @@
-use {Encodable, Decodable};
+use {Encodable, Decodable, DecodeContext, EncodeContext};
 use packet::FixedHeader;
-use bytes::BytesMut;
+use bytes::{BytesMut, Buf, BufMut};
 
 error_chain!{
     types{
@@ -35,12 +35,11 @@ impl FixedHeader for PingReqFixedHeader {
 impl<'a> Decodable<'a> for PingReqFixedHeader{
 
     type Error = PingReqError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
         match Self::get_fixheader(byte) {
             Ok((packet_type, _, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(PingReqFixedHeader {
                     packet_type: packet_type,
                     remaining_length: remaining_length,
@@ -53,10 +52,9 @@ impl<'a> Decodable<'a> for PingReqFixedHeader{
 
 impl Encodable for PingReqFixedHeader{
     type Error = PingReqError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        Self::encode_fixedheader(self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, 0u8, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -70,19 +68,31 @@ pub struct PingReq{
 }
 
 impl PingReq {
-    fn new() -> PingReq{
-        PingReq{
-            fixed_header: PingReqFixedHeader::new()
-        }
+    pub fn builder() -> PingReqBuilder {
+        PingReqBuilder::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct PingReqBuilder;
+
+impl PingReqBuilder {
+    fn new() -> PingReqBuilder {
+        PingReqBuilder
+    }
+
+    pub fn build(self) -> Result<PingReq, PingReqError> {
+        Ok(PingReq {
+            fixed_header: PingReqFixedHeader::new(),
+        })
     }
 }
 
 impl<'a> Decodable<'a> for PingReq{
     type Error = PingReqError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header = Decodable::decode_with(byte, ctx)?;
 
         Ok(PingReq{
             fixed_header: fixed_header,
@@ -92,11 +102,10 @@ impl<'a> Decodable<'a> for PingReq{
 
 impl Encodable for PingReq {
     type Error = PingReqError;
-    type Cond = ();
 
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        self.fixed_header.encode().map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.fixed_header.encode_to_with(dst, ctx)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -109,13 +118,15 @@ mod test{
     use super::*;
     #[test]
     fn test_pingreq_encode_decode(){
-        let packet = PingReq::new();
+        let packet = PingReq::builder().build().unwrap();
         let vecbytes = packet.encode().unwrap();
 
         //println!("{:?}", vecbytes);
+        assert_eq!(vecbytes, vec![0xC0, 0x00]);
 
-        let mut bytes = BytesMut::from(vecbytes);
+        let mut bytes = BytesMut::from(&vecbytes[..]);
         let result = PingReq::decode(&mut bytes);
         //println!("{:?}", result);
+        assert_eq!(result.unwrap().encode().unwrap(), vecbytes);
     }
 }
@@ -1,7 +1,7 @@
 
-use {Encodable, Decodable};
+use {Encodable, Decodable, DecodeContext, EncodeContext};
 use packet::FixedHeader;
-use bytes::BytesMut;
+use bytes::{BytesMut, Buf, BufMut};
 
 error_chain!{
     types{
@@ -36,12 +36,11 @@ impl FixedHeader for PingRespFixedHeader {
 impl<'a> Decodable<'a> for PingRespFixedHeader{
 
     type Error = PingRespError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
         match Self::get_fixheader(byte) {
             Ok((packet_type, _, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(PingRespFixedHeader {
                     packet_type: packet_type,
                     remaining_length: remaining_length,
@@ -54,10 +53,9 @@ impl<'a> Decodable<'a> for PingRespFixedHeader{
 
 impl Encodable for PingRespFixedHeader{
     type Error = PingRespError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        Self::encode_fixedheader(self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, 0u8, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -71,19 +69,31 @@ pub struct PingResp{
 }
 
 impl PingResp {
-    fn new() -> PingResp{
-        PingResp{
-            fixed_header: PingRespFixedHeader::new()
-        }
+    pub fn builder() -> PingRespBuilder {
+        PingRespBuilder::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct PingRespBuilder;
+
+impl PingRespBuilder {
+    fn new() -> PingRespBuilder {
+        PingRespBuilder
+    }
+
+    pub fn build(self) -> Result<PingResp, PingRespError> {
+        Ok(PingResp {
+            fixed_header: PingRespFixedHeader::new(),
+        })
     }
 }
 
 impl<'a> Decodable<'a> for PingResp{
     type Error = PingRespError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header = Decodable::decode_with(byte, ctx)?;
 
         Ok(PingResp{
             fixed_header: fixed_header,
@@ -93,10 +103,9 @@ impl<'a> Decodable<'a> for PingResp{
 
 impl Encodable for PingResp {
     type Error = PingRespError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        self.fixed_header.encode().map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.fixed_header.encode_to_with(dst, ctx)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -109,13 +118,15 @@ mod test{
     use super::*;
     #[test]
     fn test_pingresp_encode_decode(){
-        let packet = PingResp::new();
+        let packet = PingResp::builder().build().unwrap();
         let vecbytes = packet.encode().unwrap();
 
         //println!("{:?}", vecbytes);
+        assert_eq!(vecbytes, vec![0xD0, 0x00]);
 
-        let mut bytes = BytesMut::from(vecbytes);
+        let mut bytes = BytesMut::from(&vecbytes[..]);
         let result = PingResp::decode(&mut bytes);
         //println!("{:?}", result);
+        assert_eq!(result.unwrap().encode().unwrap(), vecbytes);
     }
 }
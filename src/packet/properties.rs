@@ -0,0 +1,463 @@
+use std::slice;
+use bytes::{Bytes, BytesMut, Buf, BufMut};
+
+use {Decodable, Encodable, ByteStr, DecodeContext, EncodeContext};
+use packet::{decode_remaining_length, encode_remaining_length, remaining_length_size};
+use control::variable_header::VecBytes;
+
+error_chain!{
+    types {
+        PropertiesError, ErrorKind, ResultExt, PropertiesResult;
+    }
+
+    errors {
+        UnknownProperty(identifier: u8)
+        DuplicateProperty(identifier: u8)
+        ZeroSubscriptionIdentifier
+        MalformedProperties(r: String)
+    }
+
+    links {
+        FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
+        PacketError(::PacketError, ::ErrorKind);
+        VecBytesError(::control::variable_header::VecBytesError, ::control::variable_header::VecBytesErrorKind);
+    }
+}
+
+/// MQTT 5 property (MQTT 5.0 §2.2.2.2), tagged by its identifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Property {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(ByteStr),
+    ResponseTopic(ByteStr),
+    CorrelationData(Bytes),
+    SubscriptionIdentifier(u32),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(ByteStr),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(ByteStr),
+    AuthenticationData(Bytes),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(ByteStr),
+    ServerReference(ByteStr),
+    ReasonString(ByteStr),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQos(u8),
+    RetainAvailable(u8),
+    UserProperty(ByteStr, ByteStr),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(u8),
+    SubscriptionIdentifierAvailable(u8),
+    SharedSubscriptionAvailable(u8),
+}
+
+impl Property {
+    pub fn identifier(&self) -> u8 {
+        match *self {
+            Property::PayloadFormatIndicator(_) => 0x01,
+            Property::MessageExpiryInterval(_) => 0x02,
+            Property::ContentType(_) => 0x03,
+            Property::ResponseTopic(_) => 0x08,
+            Property::CorrelationData(_) => 0x09,
+            Property::SubscriptionIdentifier(_) => 0x0B,
+            Property::SessionExpiryInterval(_) => 0x11,
+            Property::AssignedClientIdentifier(_) => 0x12,
+            Property::ServerKeepAlive(_) => 0x13,
+            Property::AuthenticationMethod(_) => 0x15,
+            Property::AuthenticationData(_) => 0x16,
+            Property::RequestProblemInformation(_) => 0x17,
+            Property::WillDelayInterval(_) => 0x18,
+            Property::RequestResponseInformation(_) => 0x19,
+            Property::ResponseInformation(_) => 0x1A,
+            Property::ServerReference(_) => 0x1C,
+            Property::ReasonString(_) => 0x1F,
+            Property::ReceiveMaximum(_) => 0x21,
+            Property::TopicAliasMaximum(_) => 0x22,
+            Property::TopicAlias(_) => 0x23,
+            Property::MaximumQos(_) => 0x24,
+            Property::RetainAvailable(_) => 0x25,
+            Property::UserProperty(_, _) => 0x26,
+            Property::MaximumPacketSize(_) => 0x27,
+            Property::WildcardSubscriptionAvailable(_) => 0x28,
+            Property::SubscriptionIdentifierAvailable(_) => 0x29,
+            Property::SharedSubscriptionAvailable(_) => 0x2A,
+        }
+    }
+
+    /// Whether the property may appear more than once in the property list
+    /// of a packet of `packet_type`. User properties always may, subscription
+    /// identifiers only in PUBLISH.
+    pub fn is_repeatable(&self, packet_type: u8) -> bool {
+        match *self {
+            Property::UserProperty(_, _) => true,
+            Property::SubscriptionIdentifier(_) => packet_type == PUBLISH,
+            _ => false,
+        }
+    }
+}
+
+/// Packet type of PUBLISH, the one packet that may repeat a property other
+/// than a user property.
+const PUBLISH: u8 = 3;
+
+fn decode_u32(bytes: &mut BytesMut) -> Result<u32, PropertiesError> {
+    if bytes.len() < 4 {
+        bail!(ErrorKind::MalformedProperties("four byte integer is incomplete".into()));
+    }
+    Ok(bytes.get_u32())
+}
+
+fn decode_variable_integer(bytes: &mut BytesMut) -> Result<u32, PropertiesError> {
+    let (value, n) = decode_remaining_length(&bytes[..])?;
+    bytes.advance(n);
+    Ok(value)
+}
+
+fn decode_binary(bytes: &mut BytesMut, ctx: &DecodeContext) -> Result<Bytes, PropertiesError> {
+    let binary: VecBytes = Decodable::decode_with(bytes, ctx)?;
+    Ok(binary.0)
+}
+
+impl<'a> Decodable<'a> for Property {
+    type Error = PropertiesError;
+
+    fn decode_with(bytes: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let identifier: u8 = Decodable::decode_with(bytes, ctx)?;
+        let property = match identifier {
+            0x01 => Property::PayloadFormatIndicator(Decodable::decode_with(bytes, ctx)?),
+            0x02 => Property::MessageExpiryInterval(decode_u32(bytes)?),
+            0x03 => Property::ContentType(Decodable::decode_with(bytes, ctx)?),
+            0x08 => Property::ResponseTopic(Decodable::decode_with(bytes, ctx)?),
+            0x09 => Property::CorrelationData(decode_binary(bytes, ctx)?),
+            0x0B => Property::SubscriptionIdentifier(decode_variable_integer(bytes)?),
+            0x11 => Property::SessionExpiryInterval(decode_u32(bytes)?),
+            0x12 => Property::AssignedClientIdentifier(Decodable::decode_with(bytes, ctx)?),
+            0x13 => Property::ServerKeepAlive(Decodable::decode_with(bytes, ctx)?),
+            0x15 => Property::AuthenticationMethod(Decodable::decode_with(bytes, ctx)?),
+            0x16 => Property::AuthenticationData(decode_binary(bytes, ctx)?),
+            0x17 => Property::RequestProblemInformation(Decodable::decode_with(bytes, ctx)?),
+            0x18 => Property::WillDelayInterval(decode_u32(bytes)?),
+            0x19 => Property::RequestResponseInformation(Decodable::decode_with(bytes, ctx)?),
+            0x1A => Property::ResponseInformation(Decodable::decode_with(bytes, ctx)?),
+            0x1C => Property::ServerReference(Decodable::decode_with(bytes, ctx)?),
+            0x1F => Property::ReasonString(Decodable::decode_with(bytes, ctx)?),
+            0x21 => Property::ReceiveMaximum(Decodable::decode_with(bytes, ctx)?),
+            0x22 => Property::TopicAliasMaximum(Decodable::decode_with(bytes, ctx)?),
+            0x23 => Property::TopicAlias(Decodable::decode_with(bytes, ctx)?),
+            0x24 => Property::MaximumQos(Decodable::decode_with(bytes, ctx)?),
+            0x25 => Property::RetainAvailable(Decodable::decode_with(bytes, ctx)?),
+            0x26 => Property::UserProperty(Decodable::decode_with(bytes, ctx)?, Decodable::decode_with(bytes, ctx)?),
+            0x27 => Property::MaximumPacketSize(decode_u32(bytes)?),
+            0x28 => Property::WildcardSubscriptionAvailable(Decodable::decode_with(bytes, ctx)?),
+            0x29 => Property::SubscriptionIdentifierAvailable(Decodable::decode_with(bytes, ctx)?),
+            0x2A => Property::SharedSubscriptionAvailable(Decodable::decode_with(bytes, ctx)?),
+            _ => bail!(ErrorKind::UnknownProperty(identifier)),
+        };
+        Ok(property)
+    }
+}
+
+impl Encodable for Property {
+    type Error = PropertiesError;
+
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        dst.put_u8(self.identifier());
+        match *self {
+            Property::PayloadFormatIndicator(value) |
+            Property::RequestProblemInformation(value) |
+            Property::RequestResponseInformation(value) |
+            Property::MaximumQos(value) |
+            Property::RetainAvailable(value) |
+            Property::WildcardSubscriptionAvailable(value) |
+            Property::SubscriptionIdentifierAvailable(value) |
+            Property::SharedSubscriptionAvailable(value) => dst.put_u8(value),
+            Property::ServerKeepAlive(value) |
+            Property::ReceiveMaximum(value) |
+            Property::TopicAliasMaximum(value) |
+            Property::TopicAlias(value) => dst.put_u16(value),
+            Property::MessageExpiryInterval(value) |
+            Property::SessionExpiryInterval(value) |
+            Property::WillDelayInterval(value) |
+            Property::MaximumPacketSize(value) => dst.put_u32(value),
+            Property::SubscriptionIdentifier(value) => encode_remaining_length(dst, value)?,
+            Property::ContentType(ref value) |
+            Property::ResponseTopic(ref value) |
+            Property::AssignedClientIdentifier(ref value) |
+            Property::AuthenticationMethod(ref value) |
+            Property::ResponseInformation(ref value) |
+            Property::ServerReference(ref value) |
+            Property::ReasonString(ref value) => value.encode_to_with(dst, ctx)?,
+            Property::CorrelationData(ref value) |
+            Property::AuthenticationData(ref value) => VecBytes(value.clone()).encode_to_with(dst, ctx)?,
+            Property::UserProperty(ref name, ref value) => {
+                name.encode_to_with(dst, ctx)?;
+                value.encode_to_with(dst, ctx)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn encode_length(&self) -> Result<u32, Self::Error> {
+        let length = match *self {
+            Property::PayloadFormatIndicator(_) |
+            Property::RequestProblemInformation(_) |
+            Property::RequestResponseInformation(_) |
+            Property::MaximumQos(_) |
+            Property::RetainAvailable(_) |
+            Property::WildcardSubscriptionAvailable(_) |
+            Property::SubscriptionIdentifierAvailable(_) |
+            Property::SharedSubscriptionAvailable(_) => 1,
+            Property::ServerKeepAlive(_) |
+            Property::ReceiveMaximum(_) |
+            Property::TopicAliasMaximum(_) |
+            Property::TopicAlias(_) => 2,
+            Property::MessageExpiryInterval(_) |
+            Property::SessionExpiryInterval(_) |
+            Property::WillDelayInterval(_) |
+            Property::MaximumPacketSize(_) => 4,
+            Property::SubscriptionIdentifier(value) => remaining_length_size(value)? as u32,
+            Property::ContentType(ref value) |
+            Property::ResponseTopic(ref value) |
+            Property::AssignedClientIdentifier(ref value) |
+            Property::AuthenticationMethod(ref value) |
+            Property::ResponseInformation(ref value) |
+            Property::ServerReference(ref value) |
+            Property::ReasonString(ref value) => value.encode_length()?,
+            Property::CorrelationData(ref value) |
+            Property::AuthenticationData(ref value) => VecBytes(value.clone()).encode_length()?,
+            Property::UserProperty(ref name, ref value) => name.encode_length()? + value.encode_length()?,
+        };
+        Ok(1 + length)
+    }
+}
+
+/// MQTT 5 property list, encoded as its variable byte integer length followed
+/// by the properties.
+///
+/// Every property but user properties and subscription identifiers may appear
+/// at most once; `push` and decoding reject a second one.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Properties {
+    properties: Vec<Property>,
+}
+
+impl Properties {
+    pub fn new() -> Properties {
+        Properties::default()
+    }
+
+    /// Adds `property`, refusing a repeat no packet allows. Which packet the
+    /// list ends up in is not known here; `validate` checks it for one.
+    pub fn push(&mut self, property: Property) -> Result<(), PropertiesError> {
+        if property == Property::SubscriptionIdentifier(0) {
+            bail!(ErrorKind::ZeroSubscriptionIdentifier);
+        }
+        if !property.is_repeatable(PUBLISH) && self.get(property.identifier()).is_some() {
+            bail!(ErrorKind::DuplicateProperty(property.identifier()));
+        }
+        self.properties.push(property);
+        Ok(())
+    }
+
+    /// Adds `property`, for building a list in one expression.
+    pub fn with(mut self, property: Property) -> Result<Properties, PropertiesError> {
+        self.push(property)?;
+        Ok(self)
+    }
+
+    /// Checks the list may be sent in a packet of `packet_type`, which only
+    /// PUBLISH allows to carry more than one subscription identifier.
+    pub fn validate(&self, packet_type: u8) -> Result<(), PropertiesError> {
+        for (i, property) in self.properties.iter().enumerate() {
+            if !property.is_repeatable(packet_type) && self.properties[..i].iter().any(|other| other.identifier() == property.identifier()) {
+                bail!(ErrorKind::DuplicateProperty(property.identifier()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the first property with the given identifier.
+    pub fn get(&self, identifier: u8) -> Option<&Property> {
+        self.properties.iter().find(|property| property.identifier() == identifier)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Property> {
+        self.properties.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.properties.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+
+    fn body_length(&self) -> Result<u32, PropertiesError> {
+        let mut length = 0;
+        for property in &self.properties {
+            length += property.encode_length()?;
+        }
+        Ok(length)
+    }
+}
+
+impl<'a> IntoIterator for &'a Properties {
+    type Item = &'a Property;
+    type IntoIter = slice::Iter<'a, Property>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> Decodable<'a> for Properties {
+    type Error = PropertiesError;
+
+    fn decode_with(bytes: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let length = decode_variable_integer(bytes)? as usize;
+        if bytes.len() < length {
+            bail!(ErrorKind::MalformedProperties("property length overruns packet".into()));
+        }
+
+        let mut body = bytes.split_to(length);
+        let mut properties = Properties::new();
+        while !body.is_empty() {
+            properties.push(Decodable::decode_with(&mut body, ctx)?)?;
+        }
+        Ok(properties)
+    }
+}
+
+impl Encodable for Properties {
+    type Error = PropertiesError;
+
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        encode_remaining_length(dst, self.body_length()?)?;
+        for property in &self.properties {
+            property.encode_to_with(dst, ctx)?;
+        }
+        Ok(())
+    }
+
+    fn encode_length(&self) -> Result<u32, Self::Error> {
+        let length = self.body_length()?;
+        Ok(remaining_length_size(length)? as u32 + length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn all_properties() -> Vec<Property> {
+        vec![
+            Property::PayloadFormatIndicator(1),
+            Property::MessageExpiryInterval(3600),
+            Property::ContentType("text/plain".into()),
+            Property::ResponseTopic("reply/to".into()),
+            Property::CorrelationData(Bytes::from_static(&[1, 2, 3])),
+            Property::SubscriptionIdentifier(268_435_455),
+            Property::SessionExpiryInterval(0xFFFF_FFFF),
+            Property::AssignedClientIdentifier("auto-1".into()),
+            Property::ServerKeepAlive(30),
+            Property::AuthenticationMethod("SCRAM-SHA-1".into()),
+            Property::AuthenticationData(Bytes::from_static(b"data")),
+            Property::RequestProblemInformation(0),
+            Property::WillDelayInterval(10),
+            Property::RequestResponseInformation(1),
+            Property::ResponseInformation("info".into()),
+            Property::ServerReference("other".into()),
+            Property::ReasonString("because".into()),
+            Property::ReceiveMaximum(100),
+            Property::TopicAliasMaximum(10),
+            Property::TopicAlias(2),
+            Property::MaximumQos(1),
+            Property::RetainAvailable(0),
+            Property::UserProperty("k".into(), "v".into()),
+            Property::MaximumPacketSize(1024),
+            Property::WildcardSubscriptionAvailable(1),
+            Property::SubscriptionIdentifierAvailable(1),
+            Property::SharedSubscriptionAvailable(0),
+        ]
+    }
+
+    #[test]
+    fn test_properties_roundtrip() {
+        let mut properties = Properties::new();
+        for property in all_properties() {
+            properties.push(property).unwrap();
+        }
+        properties.push(Property::UserProperty("k".into(), "w".into())).unwrap();
+        properties.push(Property::SubscriptionIdentifier(1)).unwrap();
+        assert_eq!(properties.len(), 29);
+
+        let vec = properties.encode().unwrap();
+        assert_eq!(vec.len() as u32, properties.encode_length().unwrap());
+        assert_eq!(&vec[..2], &[0x9A, 0x01]);
+
+        let mut bytes = BytesMut::from(&vec[..]);
+        bytes.extend_from_slice(&[0xFF]);
+        let decoded = Properties::decode(&mut bytes).unwrap();
+        assert_eq!(decoded, properties);
+        assert_eq!(&bytes[..], &[0xFF]);
+
+        let empty = Properties::new();
+        assert_eq!(empty.encode().unwrap(), vec![0x00]);
+        assert!(Properties::decode(&mut BytesMut::from(&[0x00][..])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_properties_reject_duplicates() {
+        let properties = Properties::new().with(Property::TopicAlias(1)).unwrap();
+        assert!(properties.clone().with(Property::TopicAlias(2)).is_err());
+        assert_eq!(properties.get(0x23), Some(&Property::TopicAlias(1)));
+
+        let mut bytes = BytesMut::from(&[0x06, 0x23, 0x00, 0x01, 0x23, 0x00, 0x02][..]);
+        match Properties::decode(&mut bytes) {
+            Err(PropertiesError(ErrorKind::DuplicateProperty(0x23), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_properties_subscription_identifier() {
+        let properties = Properties::new().with(Property::SubscriptionIdentifier(1)).unwrap()
+                                          .with(Property::SubscriptionIdentifier(2)).unwrap();
+        assert!(properties.validate(3).is_ok());
+        match properties.validate(8) {
+            Err(PropertiesError(ErrorKind::DuplicateProperty(0x0B), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(Properties::new().with(Property::SubscriptionIdentifier(1)).unwrap().validate(8).is_ok());
+
+        match Properties::new().with(Property::SubscriptionIdentifier(0)) {
+            Err(PropertiesError(ErrorKind::ZeroSubscriptionIdentifier, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match Properties::decode(&mut BytesMut::from(&[0x02, 0x0B, 0x00][..])) {
+            Err(PropertiesError(ErrorKind::ZeroSubscriptionIdentifier, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_properties_malformed() {
+        let malformed: &[&[u8]] = &[
+            &[0x05, 0x23, 0x00],
+            &[0x02, 0x04, 0x00],
+            &[0x03, 0x02, 0x00, 0x00],
+            &[0x04, 0x03, 0x00, 0x02, 0xC3],
+            &[0x02, 0x0B, 0x80],
+            &[0x80],
+        ];
+        for bytes in malformed {
+            assert!(Properties::decode(&mut BytesMut::from(&bytes[..])).is_err(), "{:?}", bytes);
+        }
+    }
+}
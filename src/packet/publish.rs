@@ -1,7 +1,9 @@
-use bytes::BytesMut;
-use {Decodable, Encodable};
-use packet::FixedHeader;
-use control::variable_header::{PacketIdentifier, TopicName};
+use bytes::{Bytes, BytesMut, Buf, BufMut};
+use {Decodable, Encodable, ByteStr, ProtocolVersion, DecodeContext, EncodeContext};
+use packet::{FixedHeader, VectoredPacket, Properties};
+use control::variable_header::PacketIdentifier;
+use topic_name::TopicName;
+use qos::QualityOfService;
 
 
 
@@ -12,12 +14,19 @@ error_chain!{
 
     errors {
         PublishPayloadError(r: String)
+        MissingTopicName
+        MissingPacketIdentifier
+        DupFlagWithQos0
+        PacketIdentifierWithQos0
+        InvalidQos(qos: u8)
+        PropertiesRequireV5
     }
 
     links{
         FixedHeader(::packet::FixedHeaderError, ::packet::ErrorKind);
-        TopicName(::control::variable_header::TopicNameError, ::control::variable_header::TopicNameErrorKind);
+        TopicName(::topic_name::TopicNameError, ::topic_name::ErrorKind);
         PacketIdentifier(::control::variable_header::PacketIdentifierError, ::control::variable_header::PacketIdentifierErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
     }
 }
 
@@ -25,7 +34,7 @@ error_chain!{
 struct PublishFixedHeader{
     packet_type: u8,
     dup_flag: bool,
-    qos_level: u8,
+    qos_level: QualityOfService,
     retain: bool,
     remaining_length: u32,
 }
@@ -35,7 +44,7 @@ impl PublishFixedHeader {
         PublishFixedHeader{
             packet_type: 3,
             dup_flag: false,
-            qos_level: 0,
+            qos_level: QualityOfService::Level0,
             retain: false,
             remaining_length: 0,
         }
@@ -52,9 +61,8 @@ impl FixedHeader for PublishFixedHeader {
 
 impl<'a> Decodable<'a> for PublishFixedHeader {
     type Error = PublishError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
         match Self::get_fixheader(byte) {
             Ok((packet_type, reserved, remaining_length, n)) => {
                 let dup_flag = if (reserved >> 3) == 0x01 {
@@ -62,16 +70,20 @@ impl<'a> Decodable<'a> for PublishFixedHeader {
                 }else {
                     false
                 };
-                let qos_level = {
-                    reserved >> 1 & 0x03
+                let qos_level = match QualityOfService::from_u8(reserved >> 1 & 0x03) {
+                    Ok(qos_level) => qos_level,
+                    Err(_) => bail!(ErrorKind::InvalidQos(reserved >> 1 & 0x03)),
                 };
+                if dup_flag && qos_level == QualityOfService::Level0 {
+                    bail!(ErrorKind::DupFlagWithQos0);
+                }
                 let retain = if (reserved & 0x01) == 0x01 {
                     true
                 }else {
                     false
                 };
 
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
 
                 Ok(PublishFixedHeader{
                     packet_type: packet_type,
@@ -88,19 +100,18 @@ impl<'a> Decodable<'a> for PublishFixedHeader {
 
 impl Encodable for PublishFixedHeader {
     type Error = PublishError;
-    type Cond = ();
 
-    fn encode_with(&self, _: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
         let mut byte = 0u8;
         if self.dup_flag {
             byte |= 8;
         }
-        byte |= self.qos_level << 1;
+        byte |= (self.qos_level as u8) << 1;
         if self.retain {
             byte |= 1;
         }
 
-        Self::encode_fixedheader(self.packet_type, byte, self.remaining_length).map_err(From::from)
+        Self::encode_fixedheader(dst, self.packet_type, byte, self.remaining_length).map_err(From::from)
     }
 
 
@@ -110,16 +121,14 @@ impl Encodable for PublishFixedHeader {
 }
 
 #[derive(Debug)]
-struct PublishPayload(Vec<u8>);
+struct PublishPayload(Bytes);
 
 impl Encodable for PublishPayload{
     type Error = PublishError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let mut v = vec![];
-        v.extend(self.0.iter().cloned());
-        Ok(v)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+        dst.put_slice(&self.0);
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -127,25 +136,13 @@ impl Encodable for PublishPayload{
     }
 }
 
-impl<'a> Decodable<'a> for PublishPayload{
-    type Error = PublishError;
-    type Cond = usize;
-
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        println!("{:?}", decode_size);
-        if let Some(len) = decode_size {
-            if len >= 0 {
-
-                if len >= byte.len() {
-                    Ok(PublishPayload(byte.split_to(len).to_vec()))
-                }else {
-                    bail!(ErrorKind::PublishPayloadError("no enough byte to decode".into()))
-                }
-            } else{
-                Ok(PublishPayload(vec![]))
-            }
-        } else {
-            bail!(ErrorKind::PublishPayloadError("param is none is avaiable".into()))
+impl PublishPayload{
+    /// Splits the `len` payload bytes left after the variable header off `byte`.
+    fn decode(byte: &mut BytesMut, len: usize) -> Result<PublishPayload, PublishError> {
+        if byte.len() >= len {
+            Ok(PublishPayload(byte.split_to(len).freeze()))
+        }else {
+            bail!(ErrorKind::PublishPayloadError("no enough byte to decode".into()))
         }
     }
 }
@@ -155,49 +152,225 @@ impl<'a> Decodable<'a> for PublishPayload{
 pub struct Publish{
     fixed_header: PublishFixedHeader,
     topic_name: TopicName,    
-    packet_identifier: PacketIdentifier,
+    packet_identifier: Option<PacketIdentifier>,
+    properties: Option<Properties>,
     payload: PublishPayload,
 }
 
 impl Publish{
-    fn new() -> Publish {
-        let mut publish = Publish {
-            fixed_header: PublishFixedHeader::new(),
-            topic_name: TopicName("a/b".into()),
-            packet_identifier: PacketIdentifier(10),
-            payload: PublishPayload(vec![32,32,32])
-        };
-        publish.calculate_remaining_length();
-        publish
+    pub fn builder() -> PublishBuilder {
+        PublishBuilder::new()
+    }
+
+    pub fn topic_name(&self) -> &str {
+        self.topic_name.as_str()
+    }
+
+    /// The topic name as a `ByteStr` sharing the decoded buffer.
+    pub fn topic_name_bytes(&self) -> &ByteStr {
+        self.topic_name.as_byte_str()
+    }
+
+    pub fn qos(&self) -> QualityOfService {
+        self.fixed_header.qos_level
+    }
+
+    pub fn dup(&self) -> bool {
+        self.fixed_header.dup_flag
+    }
+
+    pub fn retain(&self) -> bool {
+        self.fixed_header.retain
+    }
+
+    pub fn packet_identifier(&self) -> Option<u16> {
+        self.packet_identifier.as_ref().map(|packet_identifier| packet_identifier.0)
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload.0
+    }
+
+    /// The payload as `Bytes` sharing the decoded buffer; cloning it does not
+    /// copy the payload.
+    pub fn payload_bytes(&self) -> &Bytes {
+        &self.payload.0
+    }
+
+    /// Encodes the headers into their own buffer and shares the payload, see
+    /// `VectoredPacket`.
+    pub fn encode_vectored(&self) -> Result<VectoredPacket, PublishError> {
+        let length = self.encode_length()? - self.payload.encode_length()?;
+        let mut header = Vec::with_capacity(length as usize);
+        self.fixed_header.encode_to(&mut header)?;
+        self.topic_name.encode_to(&mut header)?;
+        if let Some(ref packet_identifier) = self.packet_identifier {
+            packet_identifier.encode_to(&mut header)?;
+        }
+        if let Some(ref properties) = self.properties {
+            properties.encode_to(&mut header)?;
+        }
+
+        Ok(VectoredPacket::new(header.into(), self.payload.0.clone()))
     }
 
     fn calculate_remaining_length(&mut self) -> Result<(), PublishError> {
-        let remaining_length = self.topic_name.encode_length()?
-                                + self.packet_identifier.encode_length()?
-                                + self.payload.encode_length()?;
+        let mut remaining_length = self.topic_name.encode_length()? + self.payload.encode_length()?;
+        if let Some(ref packet_identifier) = self.packet_identifier {
+            remaining_length += packet_identifier.encode_length()?;
+        }
+        if let Some(ref properties) = self.properties {
+            remaining_length += properties.encode_length()?;
+        }
+        PublishFixedHeader::get_remaining_length_bytes(remaining_length)?;
         self.fixed_header.remaining_length = remaining_length;
         Ok(())
     }
 }
 
+#[derive(Debug)]
+pub struct PublishBuilder {
+    topic_name: Option<String>,
+    packet_identifier: Option<u16>,
+    qos: QualityOfService,
+    dup: bool,
+    retain: bool,
+    protocol_version: ProtocolVersion,
+    properties: Option<Properties>,
+    payload: Bytes,
+}
+
+impl PublishBuilder {
+    fn new() -> PublishBuilder {
+        PublishBuilder {
+            topic_name: None,
+            packet_identifier: None,
+            qos: QualityOfService::Level0,
+            dup: false,
+            retain: false,
+            protocol_version: ProtocolVersion::V311,
+            properties: None,
+            payload: Bytes::new(),
+        }
+    }
+
+    pub fn topic_name<T: Into<String>>(mut self, topic_name: T) -> PublishBuilder {
+        self.topic_name = Some(topic_name.into());
+        self
+    }
+
+    pub fn packet_identifier(mut self, packet_identifier: u16) -> PublishBuilder {
+        self.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    pub fn qos(mut self, qos: QualityOfService) -> PublishBuilder {
+        self.qos = qos;
+        self
+    }
+
+    pub fn dup(mut self, dup: bool) -> PublishBuilder {
+        self.dup = dup;
+        self
+    }
+
+    pub fn retain(mut self, retain: bool) -> PublishBuilder {
+        self.retain = retain;
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> PublishBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with `ProtocolVersion::V5`.
+    pub fn properties(mut self, properties: Properties) -> PublishBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn payload<P: Into<Bytes>>(mut self, payload: P) -> PublishBuilder {
+        self.payload = payload.into();
+        self
+    }
+
+    pub fn build(self) -> Result<Publish, PublishError> {
+        let topic_name = match self.topic_name {
+            Some(topic_name) => topic_name,
+            None => bail!(ErrorKind::MissingTopicName),
+        };
+        if self.qos == QualityOfService::Level0 && self.dup {
+            bail!(ErrorKind::DupFlagWithQos0);
+        }
+        let packet_identifier = match (self.qos, self.packet_identifier) {
+            (QualityOfService::Level0, None) => None,
+            (QualityOfService::Level0, Some(_)) => bail!(ErrorKind::PacketIdentifierWithQos0),
+            (_, Some(packet_identifier)) => Some(PacketIdentifier::new(packet_identifier)?),
+            (_, None) => bail!(ErrorKind::MissingPacketIdentifier),
+        };
+        let properties = match (self.protocol_version, self.properties) {
+            (ProtocolVersion::V5, properties) => Some(properties.unwrap_or_default()),
+            (_, None) => None,
+            (_, Some(_)) => bail!(ErrorKind::PropertiesRequireV5),
+        };
+
+        let mut fixed_header = PublishFixedHeader::new();
+        fixed_header.dup_flag = self.dup;
+        fixed_header.qos_level = self.qos;
+        fixed_header.retain = self.retain;
+
+        let mut publish = Publish {
+            fixed_header,
+            topic_name: TopicName::new(topic_name)?,
+            packet_identifier,
+            properties,
+            payload: PublishPayload(self.payload),
+        };
+        publish.calculate_remaining_length()?;
+        Ok(publish)
+    }
+}
 
 impl<'a> Decodable<'a> for Publish {
     type Error = PublishError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header :PublishFixedHeader = Decodable::decode(byte)?;
-        let topic_name :TopicName= Decodable::decode(byte)?;
-        let packet_identifier: PacketIdentifier = Decodable::decode(byte)?;
-        
-        let paylaod_length = fixed_header.remaining_length - topic_name.encode_length()? - packet_identifier.encode_length()?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header :PublishFixedHeader = Decodable::decode_with(byte, ctx)?;
+        let topic_name :TopicName= Decodable::decode_with(byte, ctx)?;
+        let packet_identifier = match fixed_header.qos_level {
+            QualityOfService::Level0 => None,
+            _ => Some(PacketIdentifier::decode_with(byte, ctx)?),
+        };
+        let properties: Option<Properties> = match ctx.protocol_version {
+            ProtocolVersion::V5 => Some(Decodable::decode_with(byte, ctx)?),
+            _ => None,
+        };
 
-        let payload = Decodable::decode_with(byte, Some(paylaod_length as usize))?;
+        let mut header_length = topic_name.encode_length()?;
+        if let Some(ref packet_identifier) = packet_identifier {
+            header_length += packet_identifier.encode_length()?;
+        }
+        if let Some(ref properties) = properties {
+            header_length += properties.encode_length()?;
+        }
+        let paylaod_length = match fixed_header.remaining_length.checked_sub(header_length) {
+            Some(paylaod_length) => paylaod_length,
+            None => bail!(ErrorKind::PublishPayloadError("remaining length shorter than variable header".into())),
+        };
+
+        let payload = PublishPayload::decode(byte, paylaod_length as usize)?;
 
         Ok(Publish{
             fixed_header: fixed_header,
             topic_name: topic_name,
             packet_identifier: packet_identifier,
+            properties,
             payload: payload,
         })
     }
@@ -205,28 +378,31 @@ impl<'a> Decodable<'a> for Publish {
 
 impl Encodable for Publish {
     type Error = PublishError;
-    type Cond = ();
-
-    fn encode_with(&self, _: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let mut v = vec![];
-        let fixed_header = self.fixed_header.encode()?;
-        let topic_name = self.topic_name.encode()?;
-        let packet_identifier = self.packet_identifier.encode()?;
-        let payload = self.payload.encode()?;
 
-        v.extend(fixed_header);
-        v.extend(topic_name);
-        v.extend(packet_identifier);
-        v.extend(payload);
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error>{
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        self.topic_name.encode_to_with(dst, ctx)?;
+        if let Some(ref packet_identifier) = self.packet_identifier {
+            packet_identifier.encode_to_with(dst, ctx)?;
+        }
+        if let Some(ref properties) = self.properties {
+            properties.encode_to_with(dst, ctx)?;
+        }
+        self.payload.encode_to_with(dst, ctx)?;
 
-        Ok(v)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
-        let len = self.fixed_header.encode_length()?
+        let mut len = self.fixed_header.encode_length()?
                 + self.topic_name.encode_length()?
-                + self.packet_identifier.encode_length()?
-                + self.packet_identifier.encode_length()?;
+                + self.payload.encode_length()?;
+        if let Some(ref packet_identifier) = self.packet_identifier {
+            len += packet_identifier.encode_length()?;
+        }
+        if let Some(ref properties) = self.properties {
+            len += properties.encode_length()?;
+        }
 
         Ok(len)
     }
@@ -245,15 +421,124 @@ mod test{
 
     #[test]
     fn test_encode_decode_publish(){
-        let mut publish = Publish::new(); 
+        let publish = Publish::builder()
+                        .topic_name("a/b")
+                        .qos(QualityOfService::Level1)
+                        .packet_identifier(10)
+                        .retain(true)
+                        .payload(vec![32, 32, 32])
+                        .build()
+                        .unwrap();
         let bytes = publish.encode().unwrap();
         //println!("{:?}", bytes);
+        assert_eq!(bytes, vec![0x33, 0x0A, 0x00, 0x03, b'a', b'/', b'b', 0x00, 0x0A, 32, 32, 32]);
 
-        
-        let mut bytesmut = BytesMut::from(bytes); 
+        let mut bytesmut = BytesMut::from(&bytes[..]); 
         //println!("{:?}", Publish::decode(&mut bytesmut));        
+        let decoded = Publish::decode(&mut bytesmut).unwrap();
+        assert_eq!(decoded.encode().unwrap(), bytes);
+        assert_eq!(decoded.topic_name(), "a/b");
+        assert_eq!(decoded.qos(), QualityOfService::Level1);
+        assert!(!decoded.dup());
+        assert!(decoded.retain());
+        assert_eq!(decoded.packet_identifier(), Some(10));
+        assert_eq!(decoded.encode_length().unwrap() as usize, bytes.len());
+        assert_eq!(decoded.payload(), &[32u8, 32, 32][..]);
+    }
 
+    #[test]
+    fn test_encode_vectored_publish(){
+        let publish = Publish::builder().topic_name("a/b").qos(QualityOfService::Level1).packet_identifier(10)
+                        .payload(vec![32u8; 300]).build().unwrap();
+        let vectored = publish.encode_vectored().unwrap();
+        assert_eq!(&vectored.header()[..], &[0x32, 0xB3, 0x02, 0x00, 0x03, b'a', b'/', b'b', 0x00, 0x0A][..]);
+        assert_eq!(vectored.payload().as_ptr(), publish.payload().as_ptr());
+
+        let mut bytes = BytesMut::new();
+        bytes.put(vectored);
+        assert_eq!(&bytes[..], &publish.encode().unwrap()[..]);
     }
 
-}
+    #[test]
+    fn test_publish_builder_invalid(){
+        assert!(Publish::builder().build().is_err());
+        assert!(Publish::builder().topic_name("a/b").qos(QualityOfService::Level1).build().is_err());
+        assert!(Publish::builder().topic_name("a/b").qos(QualityOfService::Level2).packet_identifier(0).build().is_err());
+        assert!(Publish::builder().topic_name("a/b").dup(true).build().is_err());
+        assert!(Publish::builder().topic_name("a/b").packet_identifier(1).build().is_err());
+        assert!(Publish::builder().topic_name("a/#").build().is_err());
+        assert!(Publish::builder().topic_name("").build().is_err());
+        assert!(Publish::decode(&mut BytesMut::from(&[0x30, 0x05, 0x00, 0x03, b'a', b'/', b'+'][..])).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_publish_qos0(){
+        let publish = Publish::builder().topic_name("a/b").payload(vec![1, 2]).build().unwrap();
+        let bytes = publish.encode().unwrap();
+        assert_eq!(bytes, vec![0x30, 0x07, 0x00, 0x03, b'a', b'/', b'b', 1, 2]);
+        assert_eq!(publish.encode_length().unwrap() as usize, bytes.len());
+
+        let mut bytesmut = BytesMut::from(&bytes[..]);
+        let decoded = Publish::decode(&mut bytesmut).unwrap();
+        assert_eq!(decoded.qos(), QualityOfService::Level0);
+        assert_eq!(decoded.packet_identifier(), None);
+        assert_eq!(decoded.payload(), &[1u8, 2][..]);
+    }
+
+    #[test]
+    fn test_decode_malformed_publish_flags(){
+        let mut qos3 = BytesMut::from(&[0x36, 0x07, 0x00, 0x03, b'a', b'/', b'b', 0x00, 0x01][..]);
+        match Publish::decode(&mut qos3) {
+            Err(PublishError(ErrorKind::InvalidQos(3), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
 
+        let mut dup_qos0 = BytesMut::from(&[0x38, 0x05, 0x00, 0x03, b'a', b'/', b'b'][..]);
+        match Publish::decode(&mut dup_qos0) {
+            Err(PublishError(ErrorKind::DupFlagWithQos0, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_publish_shares_buffer(){
+        let vec = Publish::builder().topic_name("a/b").payload(vec![7u8; 1024]).build().unwrap().encode().unwrap();
+        let mut bytes = BytesMut::from(&vec[..]);
+        let start = bytes.as_ptr() as usize;
+
+        let publish = Publish::decode(&mut bytes).unwrap();
+        assert_eq!(publish.topic_name_bytes().as_bytes().as_ptr() as usize, start + 5);
+        assert_eq!(publish.payload_bytes().as_ptr() as usize, start + 8);
+
+        let forwarded = publish.payload_bytes().clone();
+        assert_eq!(forwarded.as_ptr(), publish.payload().as_ptr());
+        assert_eq!(forwarded.len(), 1024);
+    }
+
+    #[test]
+    fn test_encode_decode_publish_v5(){
+        let properties = Properties::new().with(::packet::Property::MessageExpiryInterval(60)).unwrap();
+        let publish = Publish::builder()
+                        .protocol_version(ProtocolVersion::V5)
+                        .topic_name("a/b")
+                        .qos(QualityOfService::Level1)
+                        .packet_identifier(10)
+                        .properties(properties.clone())
+                        .payload(vec![32, 32, 32])
+                        .build()
+                        .unwrap();
+        let bytes = publish.encode().unwrap();
+        assert_eq!(bytes, vec![0x32, 0x10, 0x00, 0x03, b'a', b'/', b'b', 0x00, 0x0A, 0x05, 0x02, 0x00, 0x00, 0x00, 60, 32, 32, 32]);
+        assert_eq!(publish.encode_length().unwrap() as usize, bytes.len());
+
+        let decoded = Publish::decode_with(&mut BytesMut::from(&bytes[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(decoded.encode().unwrap(), bytes);
+        assert_eq!(decoded.properties(), Some(&properties));
+        assert_eq!(decoded.payload(), &[32u8, 32, 32][..]);
+
+        let vectored = decoded.encode_vectored().unwrap();
+        assert_eq!(vectored.header().len() + vectored.payload().len(), bytes.len());
+
+        assert!(Publish::builder().topic_name("a/b").properties(Properties::new()).build().is_err());
+    }
+}
@@ -1,6 +1,6 @@
-use bytes::BytesMut;
-use {Encodable, Decodable};
-use packet::FixedHeader;
+use bytes::{BytesMut, Buf, BufMut};
+use {Encodable, Decodable, ProtocolVersion, PubRelReasonCode, DecodeContext, EncodeContext};
+use packet::{FixedHeader, Properties};
 use control::variable_header::{PacketIdentifierError, PacketIdentifierErrorKind, PacketIdentifier};
 
 error_chain!{
@@ -8,9 +8,17 @@ error_chain!{
         PubRelError, ErrorKind, ResultExt, PubRelResult;
     }
 
+    errors{
+        MissingPacketIdentifier
+        ReasonCodeRequiresV5
+        PropertiesRequireV5
+    }
+
     links{
         FixedHeader(::packet::FixedHeaderError, ::packet::ErrorKind);
         PacketIdentifierError(::control::variable_header::PacketIdentifierError, ::control::variable_header::PacketIdentifierErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
+        ReasonCode(::reason_code::ReasonCodeError, ::reason_code::ErrorKind);
     }
 }
 
@@ -39,13 +47,12 @@ impl FixedHeader for PubRelFixedHeader{
 
 impl<'a> Decodable<'a> for PubRelFixedHeader {
     type Error = PubRelError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error>{
         
         match Self::get_fixheader(byte) {
             Ok((packet_type, _reserved, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
 
                 Ok(PubRelFixedHeader{
                     packet_type: packet_type,
@@ -61,14 +68,13 @@ impl<'a> Decodable<'a> for PubRelFixedHeader {
 
 impl Encodable for PubRelFixedHeader {
     type Error = PubRelError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        Self::encode_fixedheader(self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+        Self::encode_fixedheader(dst, self.packet_type, 0x02, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
-        Ok(2u32)
+        Self::get_remaining_length_bytes(self.remaining_length).map_err(From::from)
     }
 }
 
@@ -76,52 +82,156 @@ impl Encodable for PubRelFixedHeader {
 pub struct PubRel{
     fixed_header: PubRelFixedHeader,
     packet_identifier: PacketIdentifier,
+    reason_code: PubRelReasonCode,
+    properties: Option<Properties>,
 }
 
 impl PubRel {
-    pub fn new() -> PubRel{
-        PubRel{
-            fixed_header: PubRelFixedHeader::new(),
-            packet_identifier: PacketIdentifier(100),
+    pub fn builder() -> PubRelBuilder {
+        PubRelBuilder::new()
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    /// MQTT 5 reason code; always the success code for a 3.1.1 packet.
+    pub fn reason_code(&self) -> PubRelReasonCode {
+        self.reason_code
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
+    /// Length of the MQTT 5 reason code and properties. Both are left out while
+    /// they hold their defaults, success and no properties.
+    fn reason_length(&self) -> Result<u32, PubRelError> {
+        match self.properties {
+            Some(ref properties) if !properties.is_empty() => Ok(1 + properties.encode_length()?),
+            Some(_) if !self.reason_code.is_default() => Ok(1),
+            _ => Ok(0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PubRelBuilder {
+    packet_identifier: Option<u16>,
+    protocol_version: ProtocolVersion,
+    reason_code: PubRelReasonCode,
+    properties: Option<Properties>,
+}
+
+impl PubRelBuilder {
+    fn new() -> PubRelBuilder {
+        PubRelBuilder {
+            packet_identifier: None,
+            protocol_version: ProtocolVersion::V311,
+            reason_code: PubRelReasonCode::default(),
+            properties: None,
+        }
+    }
+
+    pub fn packet_identifier(mut self, packet_identifier: u16) -> PubRelBuilder {
+        self.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> PubRelBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the MQTT 5 reason code; only valid with `ProtocolVersion::V5`.
+    pub fn reason_code(mut self, reason_code: PubRelReasonCode) -> PubRelBuilder {
+        self.reason_code = reason_code;
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with `ProtocolVersion::V5`.
+    pub fn properties(mut self, properties: Properties) -> PubRelBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn build(self) -> Result<PubRel, PubRelError> {
+        let packet_identifier = match self.packet_identifier {
+            Some(packet_identifier) => PacketIdentifier::new(packet_identifier)?,
+            None => bail!(ErrorKind::MissingPacketIdentifier),
+        };
+        let properties = match (self.protocol_version, self.properties) {
+            (ProtocolVersion::V5, properties) => Some(properties.unwrap_or_default()),
+            (_, None) => None,
+            (_, Some(_)) => bail!(ErrorKind::PropertiesRequireV5),
+        };
+        if properties.is_none() && !self.reason_code.is_default() {
+            bail!(ErrorKind::ReasonCodeRequiresV5);
         }
+
+        let mut packet = PubRel {
+            fixed_header: PubRelFixedHeader::new(),
+            packet_identifier,
+            reason_code: self.reason_code,
+            properties,
+        };
+        packet.fixed_header.remaining_length = 2 + packet.reason_length()?;
+        PubRelFixedHeader::get_remaining_length_bytes(packet.fixed_header.remaining_length)?;
+        Ok(packet)
     }
 }
 
 impl<'a> Decodable<'a> for PubRel {
     
     type Error = PubRelError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header = Decodable::decode(byte)?;
-        let packet_identifier = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header: PubRelFixedHeader = Decodable::decode_with(byte, ctx)?;
+        let packet_identifier = Decodable::decode_with(byte, ctx)?;
+        let (reason_code, properties) = match ctx.protocol_version {
+            ProtocolVersion::V5 => {
+                let reason_code = if fixed_header.remaining_length > 2 { Decodable::decode_with(byte, ctx)? } else { PubRelReasonCode::default() };
+                let properties = if fixed_header.remaining_length > 3 { Decodable::decode_with(byte, ctx)? } else { Properties::new() };
+                (reason_code, Some(properties))
+            },
+            _ => (PubRelReasonCode::default(), None),
+        };
 
-        Ok(PubRel {
+        let mut packet = PubRel {
             fixed_header: fixed_header,
             packet_identifier: packet_identifier,
-        })
+            reason_code,
+            properties,
+        };
+        // a success reason code sent in full is re-encoded in the short form
+        packet.fixed_header.remaining_length = 2 + packet.reason_length()?;
+        Ok(packet)
     }
 }
 
 impl Encodable for PubRel{
     type Error = PubRelError;
-    type Cond = ();
-
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let mut vec = vec![];
-        let fixed_header = self.fixed_header.encode()?;
-        let packet_identifier = self.packet_identifier.encode()?;
 
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        self.packet_identifier.encode_to_with(dst, ctx)?;
+        if self.reason_length()? > 0 {
+            self.reason_code.encode_to_with(dst, ctx)?;
+        }
+        if let Some(ref properties) = self.properties {
+            if !properties.is_empty() {
+                properties.encode_to_with(dst, ctx)?;
+            }
+        }
 
-        vec.extend(fixed_header);
-        vec.extend(packet_identifier);
-
-        Ok(vec)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
         let length = self.fixed_header.encode_length()? 
-                    + self.packet_identifier.encode_length()?;
+                    + self.packet_identifier.encode_length()?
+                    + self.reason_length()?;
         Ok(length)
     }
 }
@@ -133,13 +243,45 @@ mod test{
 
     #[test]
     fn test_encode_decode_pubRel() {
-        let pub_rel = PubRel::new();
+        let pub_rel = PubRel::builder().packet_identifier(100).build().unwrap();
         let vec = pub_rel.encode().unwrap();
         //println!("{:?}", vec);
 
-        let mut bytes = BytesMut::from(vec);
+        let mut bytes = BytesMut::from(&vec[..]);
         //println!("{:?}", PubRel::decode(&mut bytes));
+        assert_eq!(vec, vec![0x62, 0x02, 0x00, 100]);
+        let decoded = PubRel::decode(&mut bytes).unwrap();
+        assert_eq!(decoded.encode().unwrap(), vec);
+        assert_eq!(decoded.packet_identifier(), 100);
+        assert!(PubRel::builder().build().is_err());
     }
-}
 
+    #[test]
+    fn test_encode_decode_pubrel_v5() {
+        let short = PubRel::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).build().unwrap();
+        assert_eq!(short.encode().unwrap(), vec![0x62, 0x02, 0x00, 0x01]);
 
+        let properties = Properties::new().with(::packet::Property::ReasonString("x".into())).unwrap();
+        let packet = PubRel::builder()
+                        .protocol_version(ProtocolVersion::V5)
+                        .packet_identifier(1)
+                        .reason_code(PubRelReasonCode::PacketIdentifierNotFound)
+                        .properties(properties.clone())
+                        .build()
+                        .unwrap();
+        let vec = packet.encode().unwrap();
+        assert_eq!(vec, vec![0x62, 0x08, 0x00, 0x01, 0x92, 0x04, 0x1F, 0x00, 0x01, b'x']);
+
+        let decoded = PubRel::decode_with(&mut BytesMut::from(&vec[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(decoded.encode().unwrap(), vec);
+        assert_eq!(decoded.reason_code(), PubRelReasonCode::PacketIdentifierNotFound);
+        assert_eq!(decoded.properties(), Some(&properties));
+
+        let decoded = PubRel::decode_with(&mut BytesMut::from(&[0x62, 0x03, 0x00, 0x01, 0x92][..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(decoded.reason_code(), PubRelReasonCode::PacketIdentifierNotFound);
+        assert_eq!(decoded.properties(), Some(&Properties::new()));
+
+        assert!(PubRel::builder().packet_identifier(1).reason_code(PubRelReasonCode::PacketIdentifierNotFound).build().is_err());
+        assert!(PubRel::builder().packet_identifier(1).properties(Properties::new()).build().is_err());
+    }
+}
@@ -1,18 +1,29 @@
-use {Encodable, Decodable};
-use bytes::BytesMut;
+use {Encodable, Decodable, ProtocolVersion, SubAckReasonCode, DecodeContext, EncodeContext};
+use bytes::{BytesMut, Buf, BufMut};
 use control::variable_header::PacketIdentifier;
 use return_code::SubscribeReturnCode;
-use packet::FixedHeader;
+use packet::{FixedHeader, Properties};
 
 error_chain!{
     types{
         SubAckError, ErrorKind, ResultExt, SubAckResult;
     }
 
+    errors{
+        MissingPacketIdentifier
+        EmptyReturnCodes
+        FailureRequiresV311
+        PropertiesRequireV5
+        ReasonCodeRequiresV5
+        ReturnCodeWithV5
+    }
+
     links{
         SubscribeReturnCodeError(::return_code::SubscribeReturnCodeError, ::return_code::ErrorKind);
         FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
         PacketIdentifierError(::control::variable_header::PacketIdentifierError, ::control::variable_header::PacketIdentifierErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
+        ReasonCode(::reason_code::ReasonCodeError, ::reason_code::ErrorKind);
     }
 }
 
@@ -40,13 +51,12 @@ impl FixedHeader for SubAckFixedHeader{
 
 impl<'a> Decodable<'a> for SubAckFixedHeader {
     type Error = SubAckError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
 
         match Self::get_fixheader(byte) {
             Ok((packet_type, _, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(SubAckFixedHeader{
                     packet_type: packet_type,
                     remaining_length: remaining_length,
@@ -59,10 +69,9 @@ impl<'a> Decodable<'a> for SubAckFixedHeader {
 
 impl Encodable for SubAckFixedHeader {
     type Error = SubAckError;
-    type Cond =();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        Self::encode_fixedheader(self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, 0u8, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -70,49 +79,50 @@ impl Encodable for SubAckFixedHeader {
     }
 }
 
+/// One code per subscription: 3.1.1 return codes, or MQTT 5 reason codes.
 #[derive(Debug)]
 struct SubAckPayload {
     subscribes: Vec<SubscribeReturnCode>,
+    reason_codes: Vec<SubAckReasonCode>,
 }
 
-impl<'a> Decodable<'a> for SubAckPayload {
-    type Error = SubAckError;
-    type Cond = u32;
-
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let mut size = match decode_size {
-            Some(length) => length,
-            None => bail!("unavaiable no param to decode sub ack payload "),
-        };
+impl SubAckPayload {
+    /// Decodes the `length` bytes of payload left after the variable header.
+    fn decode(byte: &mut BytesMut, length: u32, ctx: &DecodeContext) -> Result<SubAckPayload, SubAckError> {
+        let mut size = length;
 
         let mut vec = Vec::new();
+        let mut reason_codes = Vec::new();
         while size > 0 {
-            let code = Decodable::decode(byte)?;
-            vec.push(code);
+            match ctx.protocol_version {
+                ProtocolVersion::V5 => reason_codes.push(Decodable::decode_with(byte, ctx)?),
+                _ => vec.push(Decodable::decode_with(byte, ctx)?),
+            }
             size -= 1;
         }
 
         Ok(SubAckPayload{
-            subscribes: vec
+            subscribes: vec,
+            reason_codes,
         })
     }
 }
 
 impl Encodable for SubAckPayload {
     type Error = SubAckError;
-    type Cond =();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let mut vec = Vec::new();
-        
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
         for &code in &self.subscribes{
-            vec.push(code as u8);
+            dst.put_u8(code as u8);
         }
-        Ok(vec)
+        for reason_code in &self.reason_codes {
+            reason_code.encode_to_with(dst, ctx)?;
+        }
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
-        Ok(self.subscribes.len() as u32)
+        Ok((self.subscribes.len() + self.reason_codes.len()) as u32)
     }
 }
 
@@ -120,48 +130,162 @@ impl Encodable for SubAckPayload {
 pub struct SubAck {
     fixed_header: SubAckFixedHeader,
     packet_identifier: PacketIdentifier,
+    properties: Option<Properties>,
     payload: SubAckPayload,
 }
 
 impl SubAck {
-    fn new() -> SubAck{
-        let mut vec = vec![];
-        for i in 1..5 {
-            vec.push(SubscribeReturnCode::MaximumQos2);
-        }
-        let mut suback = SubAck{
-            fixed_header: SubAckFixedHeader::new(),
-            packet_identifier: PacketIdentifier(81),
-            payload: SubAckPayload{
-                subscribes: vec
-            }
-        };
+    pub fn builder() -> SubAckBuilder {
+        SubAckBuilder::new()
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
 
-        suback.calculate_remaining_length();
-        suback
+    /// 3.1.1 return codes, one per subscription of the SUBSCRIBE; empty for an
+    /// MQTT 5 packet.
+    pub fn return_codes(&self) -> &[SubscribeReturnCode] {
+        &self.payload.subscribes
+    }
+
+    /// MQTT 5 reason codes, one per subscription of the SUBSCRIBE; empty for a
+    /// 3.1.1 packet.
+    pub fn reason_codes(&self) -> &[SubAckReasonCode] {
+        &self.payload.reason_codes
+    }
+
+    fn properties_length(&self) -> Result<u32, SubAckError> {
+        match self.properties {
+            Some(ref properties) => Ok(properties.encode_length()?),
+            None => Ok(0),
+        }
     }
 
     fn calculate_remaining_length(&mut self) -> Result<(), SubAckError> {
-        let length = self.packet_identifier.encode_length()? + self.payload.encode_length()?; 
+        let length = self.packet_identifier.encode_length()? + self.properties_length()? + self.payload.encode_length()?; 
+        SubAckFixedHeader::get_remaining_length_bytes(length)?;
         self.fixed_header.remaining_length = length;
         Ok(())
     }
 }
 
+#[derive(Debug)]
+pub struct SubAckBuilder {
+    packet_identifier: Option<u16>,
+    protocol_version: ProtocolVersion,
+    properties: Option<Properties>,
+    subscribes: Vec<SubscribeReturnCode>,
+    reason_codes: Vec<SubAckReasonCode>,
+}
+
+impl SubAckBuilder {
+    fn new() -> SubAckBuilder {
+        SubAckBuilder {
+            packet_identifier: None,
+            protocol_version: ProtocolVersion::V311,
+            properties: None,
+            subscribes: Vec::new(),
+            reason_codes: Vec::new(),
+        }
+    }
+
+    pub fn packet_identifier(mut self, packet_identifier: u16) -> SubAckBuilder {
+        self.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> SubAckBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with `ProtocolVersion::V5`.
+    pub fn properties(mut self, properties: Properties) -> SubAckBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    /// Adds the return code for the next subscription; only valid before
+    /// `ProtocolVersion::V5`.
+    pub fn return_code(mut self, return_code: SubscribeReturnCode) -> SubAckBuilder {
+        self.subscribes.push(return_code);
+        self
+    }
+
+    /// Adds the MQTT 5 reason code for the next subscription; only valid with
+    /// `ProtocolVersion::V5`.
+    pub fn reason_code(mut self, reason_code: SubAckReasonCode) -> SubAckBuilder {
+        self.reason_codes.push(reason_code);
+        self
+    }
+
+    pub fn build(self) -> Result<SubAck, SubAckError> {
+        let packet_identifier = match self.packet_identifier {
+            Some(packet_identifier) => PacketIdentifier::new(packet_identifier)?,
+            None => bail!(ErrorKind::MissingPacketIdentifier),
+        };
+        let properties = match (self.protocol_version, self.properties) {
+            (ProtocolVersion::V5, properties) => Some(properties.unwrap_or_default()),
+            (_, None) => None,
+            (_, Some(_)) => bail!(ErrorKind::PropertiesRequireV5),
+        };
+        match properties {
+            Some(_) if !self.subscribes.is_empty() => bail!(ErrorKind::ReturnCodeWithV5),
+            None if !self.reason_codes.is_empty() => bail!(ErrorKind::ReasonCodeRequiresV5),
+            _ => {},
+        }
+        if self.subscribes.is_empty() && self.reason_codes.is_empty() {
+            bail!(ErrorKind::EmptyReturnCodes);
+        }
+        if self.protocol_version == ProtocolVersion::V31 && self.subscribes.contains(&SubscribeReturnCode::Failure) {
+            bail!(ErrorKind::FailureRequiresV311);
+        }
+
+        let mut suback = SubAck {
+            fixed_header: SubAckFixedHeader::new(),
+            packet_identifier,
+            properties,
+            payload: SubAckPayload {
+                subscribes: self.subscribes,
+                reason_codes: self.reason_codes,
+            },
+        };
+        suback.calculate_remaining_length()?;
+        Ok(suback)
+    }
+}
+
 impl<'a> Decodable<'a> for SubAck {
     type Error = SubAckError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header: SubAckFixedHeader = Decodable::decode(byte)?;
-        let packet_identifier: PacketIdentifier = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header: SubAckFixedHeader = Decodable::decode_with(byte, ctx)?;
+        let packet_identifier: PacketIdentifier = Decodable::decode_with(byte, ctx)?;
+        let properties: Option<Properties> = match ctx.protocol_version {
+            ProtocolVersion::V5 => Some(Decodable::decode_with(byte, ctx)?),
+            _ => None,
+        };
+        let variable_header_length = packet_identifier.encode_length()? + match properties {
+            Some(ref properties) => properties.encode_length()?,
+            None => 0,
+        };
 
-        let payload_length = fixed_header.remaining_length - packet_identifier.encode_length()?;
-        let payload = Decodable::decode_with(byte, Some(payload_length))?;
+        let payload_length = match fixed_header.remaining_length.checked_sub(variable_header_length) {
+            Some(payload_length) => payload_length,
+            None => bail!("remaining length shorter than sub ack variable header"),
+        };
+        let payload = SubAckPayload::decode(byte, payload_length, ctx)?;
 
         Ok(SubAck{
             fixed_header: fixed_header,
             packet_identifier: packet_identifier,
+            properties,
             payload: payload,
         })
     }
@@ -169,23 +293,20 @@ impl<'a> Decodable<'a> for SubAck {
 
 impl Encodable for SubAck {
     type Error = SubAckError;
-    type Cond =();
-
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let fixed_header = self.fixed_header.encode()?;
-        let packet_identifier = self.packet_identifier.encode()?;
-        let payload = self.payload.encode()?;
 
-        let mut vec = Vec::new();
-        vec.extend(fixed_header);
-        vec.extend(packet_identifier);
-        vec.extend(payload);
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        self.packet_identifier.encode_to_with(dst, ctx)?;
+        if let Some(ref properties) = self.properties {
+            properties.encode_to_with(dst, ctx)?;
+        }
+        self.payload.encode_to_with(dst, ctx)?;
 
-        Ok(vec)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
-        let length = self.fixed_header.encode_length()? + self.packet_identifier.encode_length()? + self.payload.encode_length()?;
+        let length = self.fixed_header.encode_length()? + self.packet_identifier.encode_length()? + self.properties_length()? + self.payload.encode_length()?;
         Ok(length)
     }
 }
@@ -197,18 +318,76 @@ mod test{
 
     #[test]
     fn test_suback_encode_decode(){
-        let suback = SubAck::new();
-        let mut vecbyte = suback.encode().unwrap();
+        let suback = SubAck::builder()
+                        .packet_identifier(81)
+                        .return_code(SubscribeReturnCode::MaximumQos2)
+                        .return_code(SubscribeReturnCode::MaximumQos0)
+                        .build()
+                        .unwrap();
+        let vecbyte = suback.encode().unwrap();
         //println!("{:?}", vecbyte);
+        assert_eq!(vecbyte, vec![0x90, 0x04, 0x00, 81, 0x02, 0x00]);
 
-        let mut byte = BytesMut::from(vecbyte);
-        let result = SubAck::decode(&mut byte);
+        let mut byte = BytesMut::from(&vecbyte[..]);
+        let result = SubAck::decode(&mut byte).unwrap();
         //println!("{:?}", result);
+        assert_eq!(result.encode().unwrap(), vecbyte);
+        assert_eq!(result.packet_identifier(), 81);
+        assert_eq!(result.return_codes(), &[SubscribeReturnCode::MaximumQos2, SubscribeReturnCode::MaximumQos0][..]);
+        assert!(SubAck::builder().packet_identifier(81).build().is_err());
 
 
     }
-}
-
-
 
+    #[test]
+    fn test_suback_encode_decode_v5(){
+        let properties = Properties::new().with(::packet::Property::ReasonString("ok".into())).unwrap();
+        let suback = SubAck::builder()
+                        .protocol_version(ProtocolVersion::V5)
+                        .packet_identifier(81)
+                        .properties(properties.clone())
+                        .reason_code(SubAckReasonCode::GrantedQos1)
+                        .reason_code(SubAckReasonCode::SharedSubscriptionsNotSupported)
+                        .build()
+                        .unwrap();
+        let vecbyte = suback.encode().unwrap();
+        assert_eq!(vecbyte, vec![0x90, 0x0A, 0x00, 81, 0x05, 0x1F, 0x00, 0x02, b'o', b'k', 0x01, 0x9E]);
+
+        let result = SubAck::decode_with(&mut BytesMut::from(&vecbyte[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(result.encode().unwrap(), vecbyte);
+        assert_eq!(result.properties(), Some(&properties));
+        assert_eq!(result.reason_codes(), &[SubAckReasonCode::GrantedQos1, SubAckReasonCode::SharedSubscriptionsNotSupported][..]);
+        assert!(result.return_codes().is_empty());
+
+        // 0x9E is a reason code but not a 3.1.1 return code
+        assert!(SubAck::decode(&mut BytesMut::from(&vecbyte[..])).is_err());
+        match SubAck::builder().packet_identifier(1).reason_code(SubAckReasonCode::GrantedQos0).build() {
+            Err(SubAckError(ErrorKind::ReasonCodeRequiresV5, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match SubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).return_code(SubscribeReturnCode::MaximumQos0).build() {
+            Err(SubAckError(ErrorKind::ReturnCodeWithV5, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(SubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).build().is_err());
+    }
 
+    #[test]
+    fn test_suback_v31(){
+        let vecbyte = vec![0x90, 0x04, 0x00, 0x07, 0x01, 0x80];
+        assert!(SubAck::decode(&mut BytesMut::from(&vecbyte[..])).is_ok());
+        assert!(SubAck::decode_with(&mut BytesMut::from(&vecbyte[..]), &DecodeContext::new(ProtocolVersion::V31)).is_err());
+
+        let suback = SubAck::builder()
+                        .protocol_version(ProtocolVersion::V31)
+                        .packet_identifier(7)
+                        .return_code(SubscribeReturnCode::MaximumQos1)
+                        .build()
+                        .unwrap();
+        assert_eq!(suback.encode().unwrap(), vec![0x90, 0x03, 0x00, 0x07, 0x01]);
+        match SubAck::builder().protocol_version(ProtocolVersion::V31).packet_identifier(7).return_code(SubscribeReturnCode::Failure).build() {
+            Err(SubAckError(ErrorKind::FailureRequiresV311, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -1,9 +1,10 @@
-use {Encodable, Decodable};
-use packet::FixedHeader;
+use {Encodable, Decodable, ProtocolVersion, DecodeContext, EncodeContext};
+use packet::{FixedHeader, Properties};
 use control::variable_header::{PacketIdentifier, PacketIdentifierError, PacketIdentifierErrorKind};
-use bytes::BytesMut;
+use std::slice;
+use bytes::{BytesMut, Buf, BufMut};
 
-use topic_name::TopicName;
+use topic_filter::TopicFilter;
 use qos::QualityOfService;
 
 error_chain!{
@@ -11,13 +12,21 @@ error_chain!{
         SubscribeError, ErrorKind, ResultExt, SubscribeResult;
     }
 
-    
+    errors {
+        MissingPacketIdentifier
+        EmptySubscribes
+        RequestedQosReservedBits(requested_qos: u8)
+        InvalidRetainHandling(retain_handling: u8)
+        PropertiesRequireV5
+        OptionsRequireV5
+    }
 
     links {
         FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
-        TopicNameError(::topic_name::TopicNameError, ::topic_name::ErrorKind);
+        TopicFilterError(::topic_filter::TopicFilterError, ::topic_filter::ErrorKind);
         QualityOfServiceError(::qos::QualityOfServiceError, ::qos::ErrorKind);
         PacketIdentifierError(::control::variable_header::PacketIdentifierError, ::control::variable_header::PacketIdentifierErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
     }
     
 }
@@ -46,12 +55,11 @@ impl FixedHeader for SubscribeFixedHeader {
 
 impl<'a> Decodable<'a> for SubscribeFixedHeader {
     type Error = SubscribeError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error>{
         match Self::get_fixheader(byte) {
             Ok((packet_type, _, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(SubscribeFixedHeader{
                     packet_type: packet_type,
                     remaining_length: remaining_length,
@@ -66,10 +74,9 @@ impl<'a> Decodable<'a> for SubscribeFixedHeader {
 
 impl Encodable for SubscribeFixedHeader {
     type Error = SubscribeError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        Self::encode_fixedheader(self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, 0x02, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -77,52 +84,218 @@ impl Encodable for SubscribeFixedHeader {
     }
 }
 
+/// When the server sends the retained messages matching a new subscription
+/// (MQTT 5.0 §3.8.3.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetainHandling {
+    #[default]
+    SendOnSubscribe = 0,
+    SendOnNewSubscribe = 1,
+    DoNotSend = 2,
+}
+
+/// MQTT 5 options of a subscription besides its maximum QoS. The defaults
+/// are what a 3.1.1 subscription implies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubscriptionOptions {
+    pub no_local: bool,
+    pub retain_as_published: bool,
+    pub retain_handling: RetainHandling,
+}
+
+impl SubscriptionOptions {
+    /// Options held in bits 2 to 5 of a subscription options byte.
+    fn from_bits(byte: u8) -> Result<SubscriptionOptions, SubscribeError> {
+        let retain_handling = match byte >> 4 & 0x03 {
+            0 => RetainHandling::SendOnSubscribe,
+            1 => RetainHandling::SendOnNewSubscribe,
+            2 => RetainHandling::DoNotSend,
+            retain_handling => bail!(ErrorKind::InvalidRetainHandling(retain_handling)),
+        };
+        Ok(SubscriptionOptions {
+            no_local: byte & 0x04 == 0x04,
+            retain_as_published: byte & 0x08 == 0x08,
+            retain_handling,
+        })
+    }
+
+    fn bits(&self) -> u8 {
+        let mut byte = (self.retain_handling as u8) << 4;
+        if self.no_local {
+            byte |= 0x04;
+        }
+        if self.retain_as_published {
+            byte |= 0x08;
+        }
+        byte
+    }
+}
+
 #[derive(Debug)]
 pub struct Subscribe {
     fixed_header: SubscribeFixedHeader,
     packet_identifier: PacketIdentifier,
+    properties: Option<Properties>,
     payload: SubscribePayload,
 }
 
 impl Subscribe {
-    fn new() -> Subscribe{
-        let mut subscribe = Subscribe{
-            fixed_header: SubscribeFixedHeader::new(),
-            packet_identifier: PacketIdentifier(82u16),
-            payload: {
-                let mut vec = Vec::new();
-                for i in 0..5 {
-                    let topic_name = TopicName("enjie".into());
-                    let qos = QualityOfService::Level0;
-                    vec.push((topic_name, qos));
-                }
-                SubscribePayload{
-                    subscribes: vec,
-                }
-            }
-        };
-        subscribe.calculate_remaining_length();
-        subscribe
+    pub fn builder() -> SubscribeBuilder {
+        SubscribeBuilder::new()
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
+    pub fn subscribes(&self) -> &[(TopicFilter, QualityOfService)] {
+        &self.payload.subscribes
+    }
+
+    pub fn subscriptions(&self) -> Subscriptions<'_> {
+        Subscriptions(self.payload.subscribes.iter())
+    }
+
+    /// MQTT 5 subscription options, one per entry of `subscribes`; all default
+    /// for a 3.1.1 packet.
+    pub fn subscription_options(&self) -> &[SubscriptionOptions] {
+        &self.payload.options
+    }
+
+    fn properties_length(&self) -> Result<u32, SubscribeError> {
+        match self.properties {
+            Some(ref properties) => Ok(properties.encode_length()?),
+            None => Ok(0),
+        }
     }
 
     fn calculate_remaining_length(&mut self) -> Result<(), SubscribeError>{
-        self.fixed_header.remaining_length = self.packet_identifier.encode_length()? + self.payload.encode_length()?;
+        let remaining_length = self.packet_identifier.encode_length()? + self.properties_length()? + self.payload.encode_length()?;
+        SubscribeFixedHeader::get_remaining_length_bytes(remaining_length)?;
+        self.fixed_header.remaining_length = remaining_length;
         Ok(())
     }
 }
 
+#[derive(Debug)]
+pub struct SubscribeBuilder {
+    packet_identifier: Option<u16>,
+    protocol_version: ProtocolVersion,
+    properties: Option<Properties>,
+    subscribes: Vec<(String, QualityOfService, SubscriptionOptions)>,
+}
+
+impl SubscribeBuilder {
+    fn new() -> SubscribeBuilder {
+        SubscribeBuilder {
+            packet_identifier: None,
+            protocol_version: ProtocolVersion::V311,
+            properties: None,
+            subscribes: Vec::new(),
+        }
+    }
+
+    pub fn packet_identifier(mut self, packet_identifier: u16) -> SubscribeBuilder {
+        self.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> SubscribeBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with `ProtocolVersion::V5`.
+    pub fn properties(mut self, properties: Properties) -> SubscribeBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn subscribe<T: Into<String>>(mut self, topic_filter: T, qos: QualityOfService) -> SubscribeBuilder {
+        self.subscribes.push((topic_filter.into(), qos, SubscriptionOptions::default()));
+        self
+    }
+
+    /// Adds a subscription with MQTT 5 options; options other than the
+    /// defaults are only valid with `ProtocolVersion::V5`.
+    pub fn subscribe_with_options<T: Into<String>>(mut self, topic_filter: T, qos: QualityOfService, options: SubscriptionOptions) -> SubscribeBuilder {
+        self.subscribes.push((topic_filter.into(), qos, options));
+        self
+    }
+
+    pub fn build(self) -> Result<Subscribe, SubscribeError> {
+        let packet_identifier = match self.packet_identifier {
+            Some(packet_identifier) => PacketIdentifier::new(packet_identifier)?,
+            None => bail!(ErrorKind::MissingPacketIdentifier),
+        };
+        let properties = match (self.protocol_version, self.properties) {
+            (ProtocolVersion::V5, properties) => Some(properties.unwrap_or_default()),
+            (_, None) => None,
+            (_, Some(_)) => bail!(ErrorKind::PropertiesRequireV5),
+        };
+        if let Some(ref properties) = properties {
+            properties.validate(8)?;
+        }
+        if self.subscribes.is_empty() {
+            bail!(ErrorKind::EmptySubscribes);
+        }
+        let mut subscribes = Vec::with_capacity(self.subscribes.len());
+        let mut options = Vec::with_capacity(self.subscribes.len());
+        for (topic_filter, qos, subscription_options) in self.subscribes {
+            if properties.is_none() && subscription_options != SubscriptionOptions::default() {
+                bail!(ErrorKind::OptionsRequireV5);
+            }
+            subscribes.push((TopicFilter::new(topic_filter)?, qos));
+            options.push(subscription_options);
+        }
+
+        let mut subscribe = Subscribe {
+            fixed_header: SubscribeFixedHeader::new(),
+            packet_identifier,
+            properties,
+            payload: SubscribePayload {
+                subscribes,
+                options,
+            },
+        };
+        subscribe.calculate_remaining_length()?;
+        Ok(subscribe)
+    }
+}
+
 impl<'a> Decodable<'a> for Subscribe {
     type Error = SubscribeError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header = Decodable::decode(byte)?; 
-        let packet_identifier = Decodable::decode(byte)?;
-        let payload = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header: SubscribeFixedHeader = Decodable::decode_with(byte, ctx)?;
+        let packet_identifier: PacketIdentifier = Decodable::decode_with(byte, ctx)?;
+        let properties: Option<Properties> = match ctx.protocol_version {
+            ProtocolVersion::V5 => Some(Decodable::decode_with(byte, ctx)?),
+            _ => None,
+        };
+        if let Some(ref properties) = properties {
+            properties.validate(8)?;
+        }
+        let variable_header_length = packet_identifier.encode_length()? + match properties {
+            Some(ref properties) => properties.encode_length()?,
+            None => 0,
+        };
+
+        let payload_length = match fixed_header.remaining_length.checked_sub(variable_header_length) {
+            Some(payload_length) => payload_length,
+            None => bail!("remaining length shorter than subscribe variable header"),
+        };
+        let payload = SubscribePayload::decode(byte, payload_length, ctx)?;
 
         Ok(Subscribe{
             fixed_header: fixed_header,
             packet_identifier: packet_identifier,
+            properties,
             payload: payload,
         })
     }
@@ -130,24 +303,22 @@ impl<'a> Decodable<'a> for Subscribe {
 
 impl Encodable for Subscribe {
     type Error = SubscribeError;
-    type Cond = ();
-
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let mut vec = Vec::new();
-        let fixed_header = self.fixed_header.encode()?;
-        let packet_identifier = self.packet_identifier.encode()?;
-        let payload = self.payload.encode()?;
 
-        vec.extend(fixed_header);
-        vec.extend(packet_identifier);
-        vec.extend(payload);
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error>{
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        self.packet_identifier.encode_to_with(dst, ctx)?;
+        if let Some(ref properties) = self.properties {
+            properties.encode_to_with(dst, ctx)?;
+        }
+        self.payload.encode_to_with(dst, ctx)?;
 
-        Ok(vec)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
         let length = self.fixed_header.encode_length()?
                     + self.packet_identifier.encode_length()?
+                    + self.properties_length()?
                     + self.payload.encode_length()?;
         Ok(length)
     }
@@ -155,37 +326,58 @@ impl Encodable for Subscribe {
 
 #[derive(Debug)]
 struct SubscribePayload {
-    subscribes: Vec<(TopicName, QualityOfService)>,
+    subscribes: Vec<(TopicFilter, QualityOfService)>,
+    options: Vec<SubscriptionOptions>,
 }
 
-impl<'a> Decodable<'a> for SubscribePayload {
-    type Error = SubscribeError;
-    type Cond = ();
+impl SubscribePayload {
+    /// Decodes the `length` bytes of payload left after the variable header.
+    fn decode(byte: &mut BytesMut, length: u32, ctx: &DecodeContext) -> Result<SubscribePayload, SubscribeError> {
+        let mut size = length;
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error> {
+        // MQTT 5 turns the bits above the QoS into subscription options and
+        // reserves only the top two
+        let reserved = match ctx.protocol_version {
+            ProtocolVersion::V5 => 0xC0,
+            _ => 0xFC,
+        };
         let mut vec = Vec::new();
-        while byte.len() > 0{
-           let topic_name = Decodable::decode(byte)?;
-           let qos = Decodable::decode(byte)?;
-           vec.push((topic_name, qos));
+        let mut options = Vec::new();
+        while size > 0 {
+           let topic_filter: TopicFilter = Decodable::decode_with(byte, ctx)?;
+           let requested_qos: u8 = Decodable::decode_with(byte, ctx).chain_err(|| "decode requested qos fail")?;
+           if requested_qos & reserved != 0 {
+               bail!(ErrorKind::RequestedQosReservedBits(requested_qos));
+           }
+           let subscription_options = SubscriptionOptions::from_bits(requested_qos)?;
+           let qos = QualityOfService::from_u8(requested_qos & 0x03)?;
+           size = match size.checked_sub(topic_filter.encode_length()? + qos.encode_length()?) {
+               Some(size) => size,
+               None => bail!("subscription overruns subscribe payload"),
+           };
+           vec.push((topic_filter, qos));
+           options.push(subscription_options);
         } 
+        // MQTT-3.8.3-3: a SUBSCRIBE carries at least one subscription
+        if vec.is_empty() {
+            bail!(ErrorKind::EmptySubscribes);
+        }
         Ok(SubscribePayload{
             subscribes: vec,
+            options,
         })
     }
 }
 
 impl Encodable for SubscribePayload {
     type Error = SubscribeError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let mut vec = Vec::new();
-        for &(ref topic_name, ref qos) in &self.subscribes {
-            vec.extend(topic_name.encode()?);
-            vec.extend(qos.encode()?);
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        for (&(ref topic_name, qos), options) in self.subscribes.iter().zip(&self.options) {
+            topic_name.encode_to_with(dst, ctx)?;
+            dst.put_u8(qos as u8 | options.bits());
         }
-        Ok(vec)
+        Ok(())
 
     }
 
@@ -215,6 +407,24 @@ impl Encodable for SubscribePayload {
 }
 
 
+/// Iterator over the topic filters and requested QoS of a `Subscribe`.
+#[derive(Debug, Clone)]
+pub struct Subscriptions<'a>(slice::Iter<'a, (TopicFilter, QualityOfService)>);
+
+impl<'a> Iterator for Subscriptions<'a> {
+    type Item = (&'a TopicFilter, QualityOfService);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|&(ref topic_filter, qos)| (topic_filter, qos))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Subscriptions<'a> {}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -224,12 +434,13 @@ mod test {
     fn test_subscribe_payload(){
         let mut vec = vec![];
         for i in 0..5 {
-            let topic_name = TopicName("enjie".into());
+            let topic_filter = TopicFilter::new("enjie").unwrap();
             let qos = QualityOfService::Level0;
-            vec.push((topic_name, qos));
+            vec.push((topic_filter, qos));
         }
         let payload = SubscribePayload{
-            subscribes: vec  
+            subscribes: vec,
+            options: vec![SubscriptionOptions::default(); 5],
         };
 
         //println!("{:?}", payload.encode_length());  
@@ -237,13 +448,130 @@ mod test {
 
     #[test]
     fn test_subscribe_encode_decode(){
-        let subscribe = Subscribe::new();
-        let vecbytes = subscribe.encode();
+        let subscribe = Subscribe::builder()
+                            .packet_identifier(82)
+                            .subscribe("enjie", QualityOfService::Level0)
+                            .subscribe("a/#", QualityOfService::Level2)
+                            .build()
+                            .unwrap();
+        let vecbytes = subscribe.encode().unwrap();
         //println!("{:?}", vecbytes);
+        assert_eq!(vecbytes[..4].to_vec(), vec![0x82, 0x10, 0x00, 82]);
         
-        let mut bytes = BytesMut::from(vecbytes.unwrap());
-        let result = Subscribe::decode(&mut bytes);
+        let mut bytes = BytesMut::from(&vecbytes[..]);
+        let result = Subscribe::decode(&mut bytes).unwrap();
         //println!("{:?}", result) ;
-        
+        assert_eq!(result.encode().unwrap(), vecbytes);
+        assert_eq!(result.packet_identifier(), 82);
+        assert_eq!(result.subscribes().len(), 2);
+        assert_eq!(result.subscribes()[1].0.as_str(), "a/#");
+        assert_eq!(result.subscribes()[1].1, QualityOfService::Level2);
+
+        let subscriptions: Vec<_> = result.subscriptions().map(|(topic_filter, qos)| (topic_filter.as_str(), qos)).collect();
+        assert_eq!(subscriptions, vec![("enjie", QualityOfService::Level0), ("a/#", QualityOfService::Level2)]);
+        assert_eq!(result.subscriptions().len(), 2);
+    }
+
+    #[test]
+    fn test_subscribe_decode_invalid(){
+        let decode = |vec: &[u8]| Subscribe::decode(&mut BytesMut::from(vec));
+        assert!(decode(&[0x82, 0x06, 0x00, 0x01, 0x00, 0x01, b'a', 0x01]).is_ok());
+        match decode(&[0x82, 0x06, 0x00, 0x01, 0x00, 0x01, b'a', 0x41]) {
+            Err(SubscribeError(ErrorKind::RequestedQosReservedBits(0x41), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(decode(&[0x82, 0x06, 0x00, 0x01, 0x00, 0x01, b'a', 0x03]).is_err());
+        match decode(&[0x82, 0x02, 0x00, 0x01]) {
+            Err(SubscribeError(ErrorKind::EmptySubscribes, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(decode(&[0x82, 0x07, 0x00, 0x01, 0x00, 0x02, b'#', b'a', 0x01]).is_err());
+        assert!(Subscribe::builder().packet_identifier(1).subscribe("a+", QualityOfService::Level0).build().is_err());
+    }
+
+    #[test]
+    fn test_subscribe_builder_invalid(){
+        assert!(Subscribe::builder().subscribe("a", QualityOfService::Level0).build().is_err());
+        assert!(Subscribe::builder().packet_identifier(1).build().is_err());
+    }
+
+    #[test]
+    fn test_subscribe_encode_decode_v5(){
+        let properties = Properties::new().with(::packet::Property::SubscriptionIdentifier(5)).unwrap();
+        let subscribe = Subscribe::builder()
+                            .protocol_version(ProtocolVersion::V5)
+                            .packet_identifier(82)
+                            .properties(properties.clone())
+                            .subscribe("a", QualityOfService::Level1)
+                            .build()
+                            .unwrap();
+        let vecbytes = subscribe.encode().unwrap();
+        assert_eq!(vecbytes, vec![0x82, 0x09, 0x00, 82, 0x02, 0x0B, 0x05, 0x00, 0x01, b'a', 0x01]);
+
+        let result = Subscribe::decode_with(&mut BytesMut::from(&vecbytes[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(result.encode().unwrap(), vecbytes);
+        assert_eq!(result.properties(), Some(&properties));
+        assert_eq!(result.subscribes()[0].0.as_str(), "a");
+        assert!(Subscribe::builder().packet_identifier(1).subscribe("a", QualityOfService::Level0)
+                                    .properties(properties.clone()).build().is_err());
+
+        let repeated = properties.with(::packet::Property::SubscriptionIdentifier(6)).unwrap();
+        assert!(Subscribe::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).subscribe("a", QualityOfService::Level0)
+                                    .properties(repeated).build().is_err());
+        let vecbytes = [0x82, 0x0B, 0x00, 82, 0x04, 0x0B, 0x05, 0x0B, 0x06, 0x00, 0x01, b'a', 0x01];
+        match Subscribe::decode_with(&mut BytesMut::from(&vecbytes[..]), &DecodeContext::new(ProtocolVersion::V5)) {
+            Err(SubscribeError(ErrorKind::Properties(::packet::PropertiesErrorKind::DuplicateProperty(0x0B)), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_subscribe_options(){
+        let options = SubscriptionOptions {
+            no_local: false,
+            retain_as_published: true,
+            retain_handling: RetainHandling::DoNotSend,
+        };
+        let subscribe = Subscribe::builder()
+                            .protocol_version(ProtocolVersion::V5)
+                            .packet_identifier(1)
+                            .subscribe_with_options("a", QualityOfService::Level2, options)
+                            .subscribe("b", QualityOfService::Level0)
+                            .build()
+                            .unwrap();
+        let vecbytes = subscribe.encode().unwrap();
+        assert_eq!(vecbytes, vec![0x82, 0x0B, 0x00, 0x01, 0x00, 0x00, 0x01, b'a', 0x2A, 0x00, 0x01, b'b', 0x00]);
+
+        let result = Subscribe::decode_with(&mut BytesMut::from(&vecbytes[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(result.subscription_options(), &[options, SubscriptionOptions::default()][..]);
+
+        match Subscribe::builder().packet_identifier(1).subscribe_with_options("a", QualityOfService::Level0, options).build() {
+            Err(SubscribeError(ErrorKind::OptionsRequireV5, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(Subscribe::builder().packet_identifier(1).subscribe_with_options("a", QualityOfService::Level0, SubscriptionOptions::default())
+                                    .build().is_ok());
+    }
+
+    #[test]
+    fn test_subscribe_decode_v5_options(){
+        let ctx = DecodeContext::new(ProtocolVersion::V5);
+        let decode = |vec: &[u8]| Subscribe::decode_with(&mut BytesMut::from(vec), &ctx);
+        // No Local with QoS 1
+        let vec = [0x82, 0x07, 0x00, 0x01, 0x00, 0x00, 0x01, b'a', 0x05];
+        let result = decode(&vec).unwrap();
+        assert_eq!(result.subscribes()[0].1, QualityOfService::Level1);
+        assert_eq!(result.subscription_options(), &[SubscriptionOptions { no_local: true, ..SubscriptionOptions::default() }][..]);
+        assert_eq!(result.encode().unwrap(), vec.to_vec());
+        assert!(Subscribe::decode(&mut BytesMut::from(&vec[..])).is_err());
+
+        match decode(&[0x82, 0x07, 0x00, 0x01, 0x00, 0x00, 0x01, b'a', 0x41]) {
+            Err(SubscribeError(ErrorKind::RequestedQosReservedBits(0x41), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match decode(&[0x82, 0x07, 0x00, 0x01, 0x00, 0x00, 0x01, b'a', 0x31]) {
+            Err(SubscribeError(ErrorKind::InvalidRetainHandling(3), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
     }
 }
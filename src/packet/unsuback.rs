@@ -1,16 +1,25 @@
-use {Encodable, Decodable};
-use bytes::BytesMut;
+use {Encodable, Decodable, ProtocolVersion, UnSubAckReasonCode, DecodeContext, EncodeContext};
+use bytes::{BytesMut, Buf, BufMut};
 use control::variable_header::PacketIdentifier;
-use packet::FixedHeader;
+use packet::{FixedHeader, Properties};
 
 error_chain!{
     types{
         UnSubAckError, ErrorKind, ResultExt, UnSubAckResult;
     }
 
+    errors{
+        MissingPacketIdentifier
+        PropertiesRequireV5
+        ReasonCodeRequiresV5
+        EmptyReasonCodes
+    }
+
     links{
         FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
         PacketIdentifierError(::control::variable_header::PacketIdentifierError, ::control::variable_header::PacketIdentifierErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
+        ReasonCode(::reason_code::ReasonCodeError, ::reason_code::ErrorKind);
     }
 }
 #[derive(Debug)]
@@ -36,12 +45,11 @@ impl FixedHeader for UnSubAckFixedHeader{
 
 impl<'a> Decodable<'a> for UnSubAckFixedHeader{
     type Error = UnSubAckError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error> {
         match Self::get_fixheader(byte) {
             Ok((packet_type, _, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(UnSubAckFixedHeader {
                     packet_type: packet_type,
                     remaining_length: remaining_length,
@@ -54,10 +62,9 @@ impl<'a> Decodable<'a> for UnSubAckFixedHeader{
 
 impl Encodable for UnSubAckFixedHeader{
     type Error = UnSubAckError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        Self::encode_fixedheader(self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, 0u8, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -70,51 +77,166 @@ impl Encodable for UnSubAckFixedHeader{
 pub struct UnSubAck{
     fixed_header: UnSubAckFixedHeader,
     packet_identifier: PacketIdentifier,
+    properties: Option<Properties>,
+    reason_codes: Vec<UnSubAckReasonCode>,
 }
 
 impl UnSubAck{
-    fn new() -> UnSubAck {
-        UnSubAck{
-            fixed_header: UnSubAckFixedHeader::new(),
-            packet_identifier: PacketIdentifier(323),
+    pub fn builder() -> UnSubAckBuilder {
+        UnSubAckBuilder::new()
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
+    /// MQTT 5 reason codes, one per topic filter of the UNSUBSCRIBE; empty for
+    /// a 3.1.1 packet.
+    pub fn reason_codes(&self) -> &[UnSubAckReasonCode] {
+        &self.reason_codes
+    }
+
+    fn properties_length(&self) -> Result<u32, UnSubAckError> {
+        match self.properties {
+            Some(ref properties) => Ok(properties.encode_length()?),
+            None => Ok(0),
+        }
+    }
+
+    fn calculate_remaining_length(&mut self) -> Result<(), UnSubAckError> {
+        let length = self.packet_identifier.encode_length()? + self.properties_length()? + self.reason_codes.len() as u32;
+        UnSubAckFixedHeader::get_remaining_length_bytes(length)?;
+        self.fixed_header.remaining_length = length;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnSubAckBuilder {
+    packet_identifier: Option<u16>,
+    protocol_version: ProtocolVersion,
+    properties: Option<Properties>,
+    reason_codes: Vec<UnSubAckReasonCode>,
+}
+
+impl UnSubAckBuilder {
+    fn new() -> UnSubAckBuilder {
+        UnSubAckBuilder {
+            packet_identifier: None,
+            protocol_version: ProtocolVersion::V311,
+            properties: None,
+            reason_codes: Vec::new(),
+        }
+    }
+
+    pub fn packet_identifier(mut self, packet_identifier: u16) -> UnSubAckBuilder {
+        self.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> UnSubAckBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with `ProtocolVersion::V5`.
+    pub fn properties(mut self, properties: Properties) -> UnSubAckBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    /// Adds the MQTT 5 reason code for the next topic filter; only valid with
+    /// `ProtocolVersion::V5`, which needs at least one.
+    pub fn reason_code(mut self, reason_code: UnSubAckReasonCode) -> UnSubAckBuilder {
+        self.reason_codes.push(reason_code);
+        self
+    }
+
+    pub fn build(self) -> Result<UnSubAck, UnSubAckError> {
+        let packet_identifier = match self.packet_identifier {
+            Some(packet_identifier) => PacketIdentifier::new(packet_identifier)?,
+            None => bail!(ErrorKind::MissingPacketIdentifier),
+        };
+        let properties = match (self.protocol_version, self.properties) {
+            (ProtocolVersion::V5, properties) => Some(properties.unwrap_or_default()),
+            (_, None) => None,
+            (_, Some(_)) => bail!(ErrorKind::PropertiesRequireV5),
+        };
+        match properties {
+            Some(_) if self.reason_codes.is_empty() => bail!(ErrorKind::EmptyReasonCodes),
+            None if !self.reason_codes.is_empty() => bail!(ErrorKind::ReasonCodeRequiresV5),
+            _ => {},
         }
+
+        let mut unsuback = UnSubAck {
+            fixed_header: UnSubAckFixedHeader::new(),
+            packet_identifier,
+            properties,
+            reason_codes: self.reason_codes,
+        };
+        unsuback.calculate_remaining_length()?;
+        Ok(unsuback)
     }
 }
 
 
 impl<'a> Decodable<'a> for UnSubAck {
     type Error = UnSubAckError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let fixed_header = Decodable::decode(byte)?;
-        let packet_identifier = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let fixed_header: UnSubAckFixedHeader = Decodable::decode_with(byte, ctx)?;
+        let packet_identifier: PacketIdentifier = Decodable::decode_with(byte, ctx)?;
+        let mut reason_codes = Vec::new();
+        let properties = match ctx.protocol_version {
+            ProtocolVersion::V5 => {
+                let properties: Properties = Decodable::decode_with(byte, ctx)?;
+                let payload_length = match fixed_header.remaining_length.checked_sub(packet_identifier.encode_length()? + properties.encode_length()?) {
+                    Some(payload_length) => payload_length,
+                    None => bail!("remaining length shorter than unsub ack variable header"),
+                };
+                for _ in 0..payload_length {
+                    reason_codes.push(Decodable::decode_with(byte, ctx)?);
+                }
+                Some(properties)
+            },
+            _ => None,
+        };
 
         Ok(UnSubAck{
             fixed_header: fixed_header,
             packet_identifier: packet_identifier,
+            properties,
+            reason_codes,
         })
     }
 }
 
 impl Encodable for UnSubAck {
     type Error = UnSubAckError;
-    type Cond = ();
-
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let mut vec = vec![];
-        let fixed_header = self.fixed_header.encode()?;
-        let packet_identifier = self.packet_identifier.encode()?;
 
-        vec.extend(fixed_header);
-        vec.extend(packet_identifier);
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error>{
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        self.packet_identifier.encode_to_with(dst, ctx)?;
+        if let Some(ref properties) = self.properties {
+            properties.encode_to_with(dst, ctx)?;
+        }
+        for reason_code in &self.reason_codes {
+            reason_code.encode_to_with(dst, ctx)?;
+        }
 
-        Ok(vec)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
         let length = self.fixed_header.encode_length()?
-                    + self.packet_identifier.encode_length()?;
+                    + self.packet_identifier.encode_length()?
+                    + self.properties_length()?
+                    + self.reason_codes.len() as u32;
         Ok(length)
     }
 }
@@ -125,13 +247,41 @@ mod test{
 
     #[test]
     fn test_unsuback_encode_decode(){
-        let packet = UnSubAck::new();
+        let packet = UnSubAck::builder().packet_identifier(323).build().unwrap();
         let vecbyte = packet.encode().unwrap();
         //println!("{:?}", vecbyte);
+        assert_eq!(vecbyte, vec![0xB0, 0x02, 0x01, 0x43]);
 
-        let mut bytes = BytesMut::from(vecbyte);
-        let result = UnSubAck::decode(&mut bytes);
+        let mut bytes = BytesMut::from(&vecbyte[..]);
+        let result = UnSubAck::decode(&mut bytes).unwrap();
         //println!("{:?}", result);
+        assert_eq!(result.encode().unwrap(), vecbyte);
+        assert_eq!(result.packet_identifier(), 323);
     }
-}
 
+    #[test]
+    fn test_unsuback_encode_decode_v5(){
+        let packet = UnSubAck::builder()
+                        .protocol_version(ProtocolVersion::V5)
+                        .packet_identifier(1)
+                        .reason_code(UnSubAckReasonCode::Success)
+                        .reason_code(UnSubAckReasonCode::NoSubscriptionExisted)
+                        .build()
+                        .unwrap();
+        let vecbyte = packet.encode().unwrap();
+        assert_eq!(vecbyte, vec![0xB0, 0x05, 0x00, 0x01, 0x00, 0x00, 0x11]);
+
+        let mut bytes = BytesMut::from(&vecbyte[..]);
+        let result = UnSubAck::decode_with(&mut bytes, &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert!(bytes.is_empty());
+        assert_eq!(result.encode().unwrap(), vecbyte);
+        assert_eq!(result.properties(), Some(&Properties::new()));
+        assert_eq!(result.reason_codes(), &[UnSubAckReasonCode::Success, UnSubAckReasonCode::NoSubscriptionExisted][..]);
+
+        let mut bytes = BytesMut::from(&[0xB0, 0x04, 0x00, 0x01, 0x00, 0x42][..]);
+        assert!(UnSubAck::decode_with(&mut bytes, &DecodeContext::new(ProtocolVersion::V5)).is_err());
+        assert!(UnSubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).build().is_err());
+        assert!(UnSubAck::builder().packet_identifier(1).reason_code(UnSubAckReasonCode::Success).build().is_err());
+        assert!(UnSubAck::builder().packet_identifier(1).properties(Properties::new()).build().is_err());
+    }
+}
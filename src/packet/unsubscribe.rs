@@ -1,8 +1,8 @@
-use {Encodable, Decodable};
-use bytes::BytesMut;
+use {Encodable, Decodable, ProtocolVersion, DecodeContext, EncodeContext};
+use bytes::{BytesMut, Buf, BufMut};
 use control::variable_header::PacketIdentifier;
 use topic_filter::TopicFilter;
-use packet::FixedHeader;
+use packet::{FixedHeader, Properties};
 
 error_chain!{
 
@@ -10,10 +10,17 @@ error_chain!{
         UnsubscribeError, ErrorKind, ResultExt, UnsubscribeResult;
     }
 
+    errors{
+        MissingPacketIdentifier
+        EmptyTopicFilters
+        PropertiesRequireV5
+    }
+
     links{
         FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
         PacketIdentifierError(::control::variable_header::PacketIdentifierError, ::control::variable_header::PacketIdentifierErrorKind);
         TopicFilterError(::topic_filter::TopicFilterError, ::topic_filter::ErrorKind);
+        Properties(::packet::PropertiesError, ::packet::PropertiesErrorKind);
     }
 }
 
@@ -41,12 +48,11 @@ impl FixedHeader for UnsubscribeFixedHeader {
 
 impl<'a> Decodable<'a> for UnsubscribeFixedHeader{
     type Error = UnsubscribeError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, Self::Error>{
         match Self::get_fixheader(byte) {
             Ok((packet_type, _, remaining_length, n)) => {
-                byte.split_to(1 + n);
+                byte.advance(1 + n);
                 Ok(UnsubscribeFixedHeader {
                     packet_type: packet_type,
                     remaining_length: remaining_length,
@@ -59,10 +65,9 @@ impl<'a> Decodable<'a> for UnsubscribeFixedHeader{
 
 impl Encodable for UnsubscribeFixedHeader{
     type Error = UnsubscribeError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        Self::encode_fixedheader(self.packet_type, 0u8, self.remaining_length).map_err(From::from)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
+        Self::encode_fixedheader(dst, self.packet_type, 0x02, self.remaining_length).map_err(From::from)
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -76,29 +81,17 @@ struct UnsubscribePayload {
 }
 
 impl UnsubscribePayload {
-    fn new() -> UnsubscribePayload {
-        let vec = vec![TopicFilter("fuck".into())];
-        UnsubscribePayload {
-            filters: vec,
-        }
-    }
-}
-
-impl<'a> Decodable<'a> for UnsubscribePayload{
-
-    type Error = UnsubscribeError;
-    type Cond = u32;
-
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        let mut size = match decode_size {
-            Some(n) => n,
-            None => bail!("unavaiable no param to decode Unsubscribe payload.")
-        };
+    /// Decodes the `length` bytes of payload left after the variable header.
+    fn decode(byte: &mut BytesMut, length: u32, ctx: &DecodeContext) -> Result<UnsubscribePayload, UnsubscribeError> {
+        let mut size = length;
 
         let mut vec = Vec::new();
         while size > 0 {
-            let topic_filter: TopicFilter = Decodable::decode(byte)?;
-            size -= topic_filter.encode_length()?;
+            let topic_filter: TopicFilter = Decodable::decode_with(byte, ctx)?;
+            size = match size.checked_sub(topic_filter.encode_length()?) {
+                Some(size) => size,
+                None => bail!("topic filter overruns unsubscribe payload"),
+            };
             vec.push(topic_filter);
         }
 
@@ -110,15 +103,13 @@ impl<'a> Decodable<'a> for UnsubscribePayload{
 
 impl Encodable for UnsubscribePayload{
     type Error = UnsubscribeError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let mut vec = Vec::new();
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error>{
         for topic_filter in &self.filters {
-            vec.extend(topic_filter.encode()?);
+            topic_filter.encode_to_with(dst, ctx)?;
         }
 
-        Ok(vec)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -135,43 +126,137 @@ impl Encodable for UnsubscribePayload{
 pub struct Unsubscribe {
     fixed_header: UnsubscribeFixedHeader,
     packet_identifier: PacketIdentifier,
+    properties: Option<Properties>,
     payload: UnsubscribePayload,
 }
 
 impl Unsubscribe {
-    fn new() -> Unsubscribe {
-        let mut result = Unsubscribe{
-            fixed_header: UnsubscribeFixedHeader::new(),
-            packet_identifier: PacketIdentifier(32),
-            payload: UnsubscribePayload::new(),
-        };
-        result.calculate_remaining_length();
-        result
+    pub fn builder() -> UnsubscribeBuilder {
+        UnsubscribeBuilder::new()
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    /// MQTT 5 properties; `None` for a 3.1.1 packet.
+    pub fn properties(&self) -> Option<&Properties> {
+        self.properties.as_ref()
+    }
+
+    pub fn topic_filters(&self) -> &[TopicFilter] {
+        &self.payload.filters
+    }
+
+    fn properties_length(&self) -> Result<u32, UnsubscribeError> {
+        match self.properties {
+            Some(ref properties) => Ok(properties.encode_length()?),
+            None => Ok(0),
+        }
     }
 
     fn calculate_remaining_length(&mut self) -> Result<(), UnsubscribeError>{
-        let length = self.packet_identifier.encode_length()? + self.payload.encode_length()?; 
+        let length = self.packet_identifier.encode_length()? + self.properties_length()? + self.payload.encode_length()?; 
+        UnsubscribeFixedHeader::get_remaining_length_bytes(length)?;
         self.fixed_header.remaining_length = length;
         Ok(())
     }
 }
 
+#[derive(Debug)]
+pub struct UnsubscribeBuilder {
+    packet_identifier: Option<u16>,
+    protocol_version: ProtocolVersion,
+    properties: Option<Properties>,
+    filters: Vec<String>,
+}
+
+impl UnsubscribeBuilder {
+    fn new() -> UnsubscribeBuilder {
+        UnsubscribeBuilder {
+            packet_identifier: None,
+            protocol_version: ProtocolVersion::V311,
+            properties: None,
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn packet_identifier(mut self, packet_identifier: u16) -> UnsubscribeBuilder {
+        self.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> UnsubscribeBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the MQTT 5 properties; only valid with `ProtocolVersion::V5`.
+    pub fn properties(mut self, properties: Properties) -> UnsubscribeBuilder {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn topic_filter<T: Into<String>>(mut self, topic_filter: T) -> UnsubscribeBuilder {
+        self.filters.push(topic_filter.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Unsubscribe, UnsubscribeError> {
+        let packet_identifier = match self.packet_identifier {
+            Some(packet_identifier) => PacketIdentifier::new(packet_identifier)?,
+            None => bail!(ErrorKind::MissingPacketIdentifier),
+        };
+        let properties = match (self.protocol_version, self.properties) {
+            (ProtocolVersion::V5, properties) => Some(properties.unwrap_or_default()),
+            (_, None) => None,
+            (_, Some(_)) => bail!(ErrorKind::PropertiesRequireV5),
+        };
+        if self.filters.is_empty() {
+            bail!(ErrorKind::EmptyTopicFilters);
+        }
+        let filters = self.filters.into_iter().map(TopicFilter::new).collect::<Result<Vec<_>, _>>()?;
+
+        let mut unsubscribe = Unsubscribe {
+            fixed_header: UnsubscribeFixedHeader::new(),
+            packet_identifier,
+            properties,
+            payload: UnsubscribePayload {
+                filters,
+            },
+        };
+        unsubscribe.calculate_remaining_length()?;
+        Ok(unsubscribe)
+    }
+}
+
 impl<'a> Decodable<'a> for Unsubscribe{
 
     type Error = UnsubscribeError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>{
-        let fixed_header: UnsubscribeFixedHeader = Decodable::decode(byte)?;
-        let packet_identifier: PacketIdentifier = Decodable::decode(byte)?;
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error>{
+        let fixed_header: UnsubscribeFixedHeader = Decodable::decode_with(byte, ctx)?;
+        let packet_identifier: PacketIdentifier = Decodable::decode_with(byte, ctx)?;
+        let properties: Option<Properties> = match ctx.protocol_version {
+            ProtocolVersion::V5 => Some(Decodable::decode_with(byte, ctx)?),
+            _ => None,
+        };
+        let variable_header_length = packet_identifier.encode_length()? + match properties {
+            Some(ref properties) => properties.encode_length()?,
+            None => 0,
+        };
 
-        let payload_length = fixed_header.remaining_length - packet_identifier.encode_length()?;
+        let payload_length = match fixed_header.remaining_length.checked_sub(variable_header_length) {
+            Some(payload_length) => payload_length,
+            None => bail!("remaining length shorter than unsubscribe variable header"),
+        };
         
-        let payload = Decodable::decode_with(byte, Some(payload_length))?;
+        let payload = UnsubscribePayload::decode(byte, payload_length, ctx)?;
 
         Ok(Unsubscribe{
             fixed_header: fixed_header,
             packet_identifier: packet_identifier,
+            properties,
             payload: payload,
         })
 
@@ -180,24 +265,22 @@ impl<'a> Decodable<'a> for Unsubscribe{
 
 impl Encodable for Unsubscribe{
     type Error = UnsubscribeError;
-    type Cond = ();
-
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        let mut vec = vec![];
-        let fixed_header = self.fixed_header.encode()?;
-        let packet_identifier = self.packet_identifier.encode()?;
-        let payload = self.payload.encode()?;
 
-        vec.extend(fixed_header);
-        vec.extend(packet_identifier);
-        vec.extend(payload);
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error>{
+        self.fixed_header.encode_to_with(dst, ctx)?;
+        self.packet_identifier.encode_to_with(dst, ctx)?;
+        if let Some(ref properties) = self.properties {
+            properties.encode_to_with(dst, ctx)?;
+        }
+        self.payload.encode_to_with(dst, ctx)?;
 
-        Ok(vec)
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
         let length = self.fixed_header.encode_length()? 
                     + self.packet_identifier.encode_length()?
+                    + self.properties_length()?
                     + self.payload.encode_length()?;
         Ok(length)
     }
@@ -211,12 +294,31 @@ mod test{
 
     #[test]
     fn test_unsubscribe_encode_decode(){
-        let fuck = Unsubscribe::new();
+        let fuck = Unsubscribe::builder().packet_identifier(32).topic_filter("fuck").build().unwrap();
         let vecbytes = fuck.encode().unwrap();
         //println!("{:?}", vecbytes);
+        assert_eq!(vecbytes, vec![0xA2, 0x08, 0x00, 32, 0x00, 0x04, b'f', b'u', b'c', b'k']);
 
-        let mut bytes = BytesMut::from(vecbytes);
-        let result = Unsubscribe::decode(&mut bytes);
+        let mut bytes = BytesMut::from(&vecbytes[..]);
+        let result = Unsubscribe::decode(&mut bytes).unwrap();
         //println!("{:?}", result);
+        assert_eq!(result.encode().unwrap(), vecbytes);
+        assert_eq!(result.packet_identifier(), 32);
+        assert_eq!(result.topic_filters()[0].as_str(), "fuck");
+        assert!(Unsubscribe::builder().packet_identifier(32).build().is_err());
+        assert!(Unsubscribe::builder().packet_identifier(32).topic_filter("a/#/b").build().is_err());
+    }
+
+    #[test]
+    fn test_unsubscribe_encode_decode_v5(){
+        let packet = Unsubscribe::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).topic_filter("a").build().unwrap();
+        let vecbytes = packet.encode().unwrap();
+        assert_eq!(vecbytes, vec![0xA2, 0x06, 0x00, 0x01, 0x00, 0x00, 0x01, b'a']);
+
+        let result = Unsubscribe::decode_with(&mut BytesMut::from(&vecbytes[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(result.encode().unwrap(), vecbytes);
+        assert_eq!(result.properties(), Some(&Properties::new()));
+        assert_eq!(result.topic_filters().len(), 1);
+        assert!(Unsubscribe::builder().packet_identifier(1).topic_filter("a").properties(Properties::new()).build().is_err());
     }
 }
@@ -12,9 +12,10 @@ use packet::unsuback::UnSubAck;
 use packet::pingreq::PingReq;
 use packet::pingresp::PingResp;
 use packet::disconnect::Disconnect;
-use {Decodable, Encodable};
-use packet::FixedHeader;
-use bytes::BytesMut;
+use packet::auth::Auth;
+use {Decodable, Encodable, ProtocolVersion, DecodeContext, EncodeContext};
+use packet::{FixedHeader, VectoredPacket, valid_fixed_header_flags};
+use bytes::{Bytes, BytesMut, BufMut};
 
 #[derive(Debug)]
 pub enum ValuePacket{
@@ -32,6 +33,7 @@ pub enum ValuePacket{
     PingReqPacket(PingReq),
     PingRespPacket(PingResp),
     DisconnecPacket(Disconnect),
+    AuthPacket(Auth),
 }
 
 
@@ -40,6 +42,12 @@ error_chain!{
         ValuePacketError, ErrorKind, ResultExt, ValuePacketResult;
     }
 
+    errors{
+        ProtocolVersionMismatch(packet: ProtocolVersion, expected: ProtocolVersion)
+        PacketTooLarge(size: usize, max: usize)
+        MalformedPacket(r: String)
+    }
+
     links{
         FixedHeaderError(::packet::FixedHeaderError, ::packet::ErrorKind);
         ConnackError(::packet::ConnackError, ::packet::ConnackErrorKind);
@@ -56,6 +64,7 @@ error_chain!{
         SubscribeError(::packet::SubscribeError, ::packet::SubscribeErrorKind);
         UnSubAckError(::packet::UnSubAckError, ::packet::UnSubAckErrorKind);
         UnsubscribeError(::packet::UnsubscribeError, ::packet::UnsubscribeErrorKind);
+        AuthError(::packet::AuthError, ::packet::AuthErrorKind);
     }
 }
 
@@ -67,10 +76,68 @@ impl FixedHeader for ValuePacket{
 
 impl ValuePacket {
     pub fn get_packet_length(bytes: &mut BytesMut) -> Result<u32, ValuePacketError> {
-        if let Ok((_, _, n, _,)) = Self::get_fixheader(bytes) {
-            Ok(n + 2)
-        }else{
-            bail!("get packet length error");
+        let (_, _, remaining_length, n) = Self::get_fixheader(bytes)?;
+        Ok(1 + n as u32 + remaining_length)
+    }
+
+    /// Encodes the packet as a `VectoredPacket` for the protocol version it
+    /// was built or decoded for. Only PUBLISH has a separate payload; every
+    /// other packet is encoded whole into the header.
+    pub fn encode_vectored(&self) -> Result<VectoredPacket, ValuePacketError> {
+        self.encode_vectored_with(&EncodeContext::new(self.protocol_version().unwrap_or_default()))
+    }
+
+    /// Like `encode_vectored`, refusing the packet if it does not fit `ctx`
+    /// as `encode_to_with` does.
+    pub fn encode_vectored_with(&self, ctx: &EncodeContext) -> Result<VectoredPacket, ValuePacketError> {
+        match *self {
+            ValuePacket::PublishPacket(ref packet) => {
+                self.check_context(ctx)?;
+                packet.encode_vectored().map_err(From::from)
+            },
+            ref packet => Ok(VectoredPacket::new(packet.encode_with(ctx)?.into(), Bytes::new())),
+        }
+    }
+
+    /// Checks the packet was built for the layout of `ctx.protocol_version`
+    /// and encodes to at most `ctx.max_packet_size` bytes.
+    fn check_context(&self, ctx: &EncodeContext) -> Result<(), ValuePacketError> {
+        match self.protocol_version() {
+            Some(version) if version.has_properties() != ctx.protocol_version.has_properties() => bail!(ErrorKind::ProtocolVersionMismatch(version, ctx.protocol_version)),
+            _ => {},
+        }
+        let size = self.encode_length()? as usize;
+        if size > ctx.max_packet_size {
+            bail!(ErrorKind::PacketTooLarge(size, ctx.max_packet_size));
+        }
+        Ok(())
+    }
+
+    /// Protocol version the packet was built or decoded for; `None` for the
+    /// packets whose layout is the same in every version. CONNECT is one of
+    /// them as it announces its version itself.
+    ///
+    /// 3.1 and 3.1.1 share a layout, so a packet without properties reports
+    /// 3.1.1 and encodes under either.
+    fn protocol_version(&self) -> Option<ProtocolVersion> {
+        let properties = match *self {
+            ValuePacket::ConnackPacket(ref packet) => packet.properties(),
+            ValuePacket::PublishPacket(ref packet) => packet.properties(),
+            ValuePacket::PubAckPacket(ref packet) => packet.properties(),
+            ValuePacket::PubRecPacket(ref packet) => packet.properties(),
+            ValuePacket::PubRelPacket(ref packet) => packet.properties(),
+            ValuePacket::PubCompPacket(ref packet) => packet.properties(),
+            ValuePacket::SubscribePacket(ref packet) => packet.properties(),
+            ValuePacket::SubAckPacket(ref packet) => packet.properties(),
+            ValuePacket::UnsubscribePacket(ref packet) => packet.properties(),
+            ValuePacket::UnSubAckPacket(ref packet) => packet.properties(),
+            ValuePacket::DisconnecPacket(ref packet) => packet.properties(),
+            ValuePacket::AuthPacket(_) => return Some(ProtocolVersion::V5),
+            _ => return None,
+        };
+        match properties {
+            Some(_) => Some(ProtocolVersion::V5),
+            None => Some(ProtocolVersion::V311),
         }
     }
 }
@@ -78,26 +145,29 @@ impl ValuePacket {
 
 impl<'a> Decodable<'a> for ValuePacket {
     type Error = ValuePacketError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>{
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error>{
         let result = match Self::get_fixheader(byte) {
-            Ok((packet_type, _, _, _)) => {
+            Ok((packet_type, flags, _, _)) => {
+                if !valid_fixed_header_flags(packet_type, flags) {
+                    bail!(ErrorKind::MalformedPacket(format!("invalid flags {:#06b} for packet type {}", flags, packet_type)));
+                }
                 match packet_type {
-                    1 => ValuePacket::ConnectPacket(Decodable::decode(byte)?),
-                    2 => ValuePacket::ConnackPacket(Decodable::decode(byte)?),
-                    3 => ValuePacket::PublishPacket(Decodable::decode(byte)?),
-                    4 => ValuePacket::PubAckPacket(Decodable::decode(byte)?),
-                    5 => ValuePacket::PubRecPacket(Decodable::decode(byte)?),
-                    6 => ValuePacket::PubRelPacket(Decodable::decode(byte)?),
-                    7 => ValuePacket::PubCompPacket(Decodable::decode(byte)?),
-                    8 => ValuePacket::SubscribePacket(Decodable::decode(byte)?),
-                    9 => ValuePacket::SubAckPacket(Decodable::decode(byte)?),
-                    10 => ValuePacket::UnsubscribePacket(Decodable::decode(byte)?),
-                    11 => ValuePacket::UnSubAckPacket(Decodable::decode(byte)?),
-                    12 => ValuePacket::PingReqPacket(Decodable::decode(byte)?),
-                    13 => ValuePacket::PingRespPacket(Decodable::decode(byte)?),
-                    14 => ValuePacket::DisconnecPacket(Decodable::decode(byte)?),
+                    1 => ValuePacket::ConnectPacket(Decodable::decode_with(byte, ctx)?),
+                    2 => ValuePacket::ConnackPacket(Decodable::decode_with(byte, ctx)?),
+                    3 => ValuePacket::PublishPacket(Decodable::decode_with(byte, ctx)?),
+                    4 => ValuePacket::PubAckPacket(Decodable::decode_with(byte, ctx)?),
+                    5 => ValuePacket::PubRecPacket(Decodable::decode_with(byte, ctx)?),
+                    6 => ValuePacket::PubRelPacket(Decodable::decode_with(byte, ctx)?),
+                    7 => ValuePacket::PubCompPacket(Decodable::decode_with(byte, ctx)?),
+                    8 => ValuePacket::SubscribePacket(Decodable::decode_with(byte, ctx)?),
+                    9 => ValuePacket::SubAckPacket(Decodable::decode_with(byte, ctx)?),
+                    10 => ValuePacket::UnsubscribePacket(Decodable::decode_with(byte, ctx)?),
+                    11 => ValuePacket::UnSubAckPacket(Decodable::decode_with(byte, ctx)?),
+                    12 => ValuePacket::PingReqPacket(Decodable::decode_with(byte, ctx)?),
+                    13 => ValuePacket::PingRespPacket(Decodable::decode_with(byte, ctx)?),
+                    14 => ValuePacket::DisconnecPacket(Decodable::decode_with(byte, ctx)?),
+                    15 if ctx.protocol_version == ProtocolVersion::V5 => ValuePacket::AuthPacket(Decodable::decode_with(byte, ctx)?),
                     _ => bail!("error packet type , no specify packet to decode"),
                 }
             },
@@ -109,45 +179,235 @@ impl<'a> Decodable<'a> for ValuePacket {
 
 impl Encodable for ValuePacket {
     type Error = ValuePacketError;
-    type Cond = ();
-
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
-        match self {
-            &ValuePacket::ConnectPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::ConnackPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::PublishPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::PubAckPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::PubRecPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::PubRelPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::PubCompPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::SubscribePacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::SubAckPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::UnsubscribePacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::UnSubAckPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::PingReqPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::PingRespPacket(ref packet) => packet.encode().map_err(From::from),
-            &ValuePacket::DisconnecPacket(ref packet) => packet.encode().map_err(From::from),
+
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error>{
+        self.check_context(ctx)?;
+
+        match *self {
+            ValuePacket::ConnectPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::ConnackPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::PublishPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::PubAckPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::PubRecPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::PubRelPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::PubCompPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::SubscribePacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::SubAckPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::UnsubscribePacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::UnSubAckPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::PingReqPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::PingRespPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::DisconnecPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
+            ValuePacket::AuthPacket(ref packet) => packet.encode_to_with(dst, ctx).map_err(From::from),
             _ => bail!("not found value packet error"),
         }
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
-        match self {
-            &ValuePacket::ConnectPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::ConnackPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::PublishPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::PubAckPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::PubRecPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::PubRelPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::PubCompPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::SubscribePacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::SubAckPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::UnsubscribePacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::UnSubAckPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::PingReqPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::PingRespPacket(ref packet) => packet.encode_length().map_err(From::from),
-            &ValuePacket::DisconnecPacket(ref packet) => packet.encode_length().map_err(From::from),
+        match *self {
+            ValuePacket::ConnectPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::ConnackPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::PublishPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::PubAckPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::PubRecPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::PubRelPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::PubCompPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::SubscribePacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::SubAckPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::UnsubscribePacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::UnSubAckPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::PingReqPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::PingRespPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::DisconnecPacket(ref packet) => packet.encode_length().map_err(From::from),
+            ValuePacket::AuthPacket(ref packet) => packet.encode_length().map_err(From::from),
             _ => bail!("not found value packet error"),
         }
     }
 }
+
+#[cfg(test)]
+mod test{
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use packet::{PacketDecoder, StreamingDecoder, DecoderConfig, DecodeError, DecodeErrorKind, ValuePacketRef, ValuePacketRefError,
+                 ValuePacketRefErrorKind, Properties, Property};
+    use blocking::read_packet_with_config;
+    use qos::QualityOfService;
+    use return_code::SubscribeReturnCode;
+
+    fn decode_all(data: &[u8]) {
+        for protocol_version in &[ProtocolVersion::V31, ProtocolVersion::V311, ProtocolVersion::V5] {
+            let config = DecoderConfig { protocol_version: *protocol_version, ..DecoderConfig::default() };
+            let _ = ValuePacket::decode_with(&mut BytesMut::from(data), &config.context());
+
+            let mut bytes = BytesMut::from(data);
+            let mut decoder = PacketDecoder::with_config(config.clone());
+            while let Ok(Some(_)) = decoder.decode(&mut bytes) {}
+
+            let mut bytes = BytesMut::from(data);
+            let mut decoder = StreamingDecoder::with_config(config.clone());
+            while let Ok(Some(_)) = decoder.decode(&mut bytes) {}
+
+            let mut offset = 0;
+            while let Ok(Some((_, length))) = ValuePacketRef::decode_with(&data[offset..], &config.context()) {
+                offset += length;
+            }
+
+            let mut reader = data;
+            while read_packet_with_config(&mut reader, config.clone()).is_ok() {}
+        }
+    }
+
+    fn sample_packets() -> Vec<Vec<u8>> {
+        vec![
+            Connect::builder().client_id("enjie").will("a/b", vec![1, 2]).user_name("user").password("pass").build().unwrap().encode().unwrap(),
+            Connack::builder().session_present(true).build().unwrap().encode().unwrap(),
+            Publish::builder().topic_name("a/b").qos(QualityOfService::Level1).packet_identifier(1).payload(vec![1, 2, 3]).build().unwrap().encode().unwrap(),
+            PubAck::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            PubRec::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            PubRel::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            PubComp::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            Subscribe::builder().packet_identifier(1).subscribe("a/+", QualityOfService::Level1).subscribe("b/#", QualityOfService::Level2).build().unwrap().encode().unwrap(),
+            SubAck::builder().packet_identifier(1).return_code(SubscribeReturnCode::MaximumQos1).return_code(SubscribeReturnCode::Failure).build().unwrap().encode().unwrap(),
+            Unsubscribe::builder().packet_identifier(1).topic_filter("a/+").topic_filter("b/#").build().unwrap().encode().unwrap(),
+            UnSubAck::builder().packet_identifier(1).build().unwrap().encode().unwrap(),
+            PingReq::builder().build().unwrap().encode().unwrap(),
+            PingResp::builder().build().unwrap().encode().unwrap(),
+            Disconnect::builder().build().unwrap().encode().unwrap(),
+        ]
+    }
+
+    fn sample_packets_v5() -> Vec<Vec<u8>> {
+        let properties = Properties::new().with(Property::ReasonString("x".into())).unwrap();
+        vec![
+            Connect::builder().protocol_version(ProtocolVersion::V5).client_id("enjie").will("a/b", vec![1, 2])
+                              .properties(Properties::new().with(Property::SessionExpiryInterval(10)).unwrap())
+                              .will_properties(Properties::new().with(Property::ContentType("text/plain".into())).unwrap())
+                              .build().unwrap().encode().unwrap(),
+            Connack::builder().protocol_version(ProtocolVersion::V5).properties(properties.clone()).build().unwrap().encode().unwrap(),
+            Publish::builder().protocol_version(ProtocolVersion::V5).topic_name("a/b").qos(QualityOfService::Level1).packet_identifier(1)
+                              .properties(Properties::new().with(Property::ContentType("text/plain".into())).unwrap())
+                              .payload(vec![1, 2, 3]).build().unwrap().encode().unwrap(),
+            PubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).reason_code(::PubAckReasonCode::NoMatchingSubscribers)
+                             .properties(properties.clone()).build().unwrap().encode().unwrap(),
+            Subscribe::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1)
+                                .properties(Properties::new().with(Property::SubscriptionIdentifier(5)).unwrap())
+                                .subscribe("a/+", QualityOfService::Level1).build().unwrap().encode().unwrap(),
+            SubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).reason_code(::SubAckReasonCode::GrantedQos1)
+                             .properties(properties.clone()).build().unwrap().encode().unwrap(),
+            Disconnect::builder().protocol_version(ProtocolVersion::V5).reason_code(::DisconnectReasonCode::DisconnectWithWillMessage)
+                                 .properties(properties.clone()).build().unwrap().encode().unwrap(),
+            Auth::builder().properties(properties).build().unwrap().encode().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_encode_to_exact_length(){
+        for vec in sample_packets() {
+            let packet = ValuePacket::decode(&mut BytesMut::from(&vec[..])).unwrap();
+            assert_eq!(packet.encode_length().unwrap() as usize, vec.len());
+
+            let mut slice = vec![0u8; vec.len()];
+            packet.encode_to(&mut &mut slice[..]).unwrap();
+            assert_eq!(slice, vec);
+
+            let mut bytes = BytesMut::from(&b"head"[..]);
+            packet.encode_to(&mut bytes).unwrap();
+            assert_eq!(&bytes[..4], b"head");
+            assert_eq!(&bytes[4..], &vec[..]);
+        }
+    }
+
+    #[test]
+    fn test_decode_regression_corpus(){
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/decode_packet");
+        for entry in fs::read_dir(dir).unwrap() {
+            let data = fs::read(entry.unwrap().path()).unwrap();
+            decode_all(&data);
+        }
+    }
+
+    #[test]
+    fn test_decode_mutated_packets(){
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for packet in sample_packets_v5() {
+            assert!(ValuePacket::decode_with(&mut BytesMut::from(&packet[..]), &DecodeContext::new(ProtocolVersion::V5)).is_ok());
+        }
+        for packet in sample_packets().into_iter().chain(sample_packets_v5()) {
+            for len in 0..packet.len() {
+                decode_all(&packet[..len]);
+            }
+            for _ in 0..1000 {
+                let mut mutated = packet.clone();
+                for _ in 0..next() % 4 + 1 {
+                    let i = (next() % mutated.len() as u64) as usize;
+                    mutated[i] = next() as u8;
+                }
+                decode_all(&mutated);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_reserved_flags(){
+        let malformed: &[&[u8]] = &[
+            &[0x80, 0x06, 0x00, 0x01, 0x00, 0x01, b'a', 0x01],
+            &[0xA0, 0x05, 0x00, 0x01, 0x00, 0x01, b'a'],
+            &[0x60, 0x02, 0x00, 0x01],
+            &[0x42, 0x02, 0x00, 0x01],
+            &[0xC1, 0x00],
+            &[0xE8, 0x00],
+        ];
+        for vec in malformed {
+            match ValuePacket::decode(&mut BytesMut::from(&vec[..])) {
+                Err(ValuePacketError(ErrorKind::MalformedPacket(_), _)) => {},
+                other => panic!("unexpected {:?} for {:?}", other, vec),
+            }
+            match PacketDecoder::new().decode(&mut BytesMut::from(&vec[..2])) {
+                Err(DecodeError(DecodeErrorKind::MalformedPacket(_), _)) => {},
+                other => panic!("unexpected {:?} for {:?}", other, vec),
+            }
+            match StreamingDecoder::new().decode(&mut BytesMut::from(&vec[..])) {
+                Err(DecodeError(DecodeErrorKind::MalformedPacket(_), _)) => {},
+                other => panic!("unexpected {:?} for {:?}", other, vec),
+            }
+            match ValuePacketRef::decode(vec) {
+                Err(ValuePacketRefError(ValuePacketRefErrorKind::MalformedPacket(_), _)) => {},
+                other => panic!("unexpected {:?} for {:?}", other, vec),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_auth_requires_v5(){
+        let vec = Auth::builder().build().unwrap().encode().unwrap();
+        assert!(ValuePacket::decode(&mut BytesMut::from(&vec[..])).is_err());
+        match ValuePacket::decode_with(&mut BytesMut::from(&vec[..]), &DecodeContext::new(ProtocolVersion::V5)).unwrap() {
+            ValuePacket::AuthPacket(ref auth) => assert!(auth.reason_code().is_success()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_encode_with_context(){
+        let packet = ValuePacket::PubAckPacket(PubAck::builder().protocol_version(ProtocolVersion::V5).packet_identifier(1).build().unwrap());
+        assert!(packet.encode().is_err());
+        let vec = packet.encode_with(&EncodeContext::new(ProtocolVersion::V5)).unwrap();
+        assert_eq!(vec, vec![0x40, 0x02, 0x00, 0x01]);
+
+        let ctx = EncodeContext { max_packet_size: 3, ..EncodeContext::new(ProtocolVersion::V5) };
+        match packet.encode_with(&ctx) {
+            Err(ValuePacketError(ErrorKind::PacketTooLarge(4, 3), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(ValuePacket::PingReqPacket(PingReq::builder().build().unwrap()).encode_with(&ctx).is_ok());
+    }
+}
@@ -0,0 +1,52 @@
+use std::io::IoSlice;
+use bytes::{Buf, Bytes};
+use bytes::buf::Chain;
+
+/// Encoded packet split into its headers and its payload.
+///
+/// The fixed and variable headers are encoded into one small buffer while the
+/// PUBLISH payload is shared with the packet instead of being copied after
+/// them. The packet is consumed as a `Buf`, so `chunks_vectored` yields both
+/// parts ready for `write_vectored`.
+#[derive(Debug)]
+pub struct VectoredPacket {
+    inner: Chain<Bytes, Bytes>,
+}
+
+impl VectoredPacket {
+    pub fn new(header: Bytes, payload: Bytes) -> VectoredPacket {
+        VectoredPacket {
+            inner: header.chain(payload),
+        }
+    }
+
+    pub fn header(&self) -> &Bytes {
+        self.inner.first_ref()
+    }
+
+    pub fn payload(&self) -> &Bytes {
+        self.inner.last_ref()
+    }
+
+    pub fn into_inner(self) -> (Bytes, Bytes) {
+        self.inner.into_inner()
+    }
+}
+
+impl Buf for VectoredPacket {
+    fn remaining(&self) -> usize {
+        self.inner.remaining()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.inner.chunk()
+    }
+
+    fn chunks_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        self.inner.chunks_vectored(dst)
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        self.inner.advance(cnt)
+    }
+}
@@ -0,0 +1,61 @@
+/// Protocol revision spoken on a connection, identified by the CONNECT
+/// protocol level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProtocolVersion {
+    V31 = 3,
+    #[default]
+    V311 = 4,
+    V5 = 5,
+}
+
+impl ProtocolVersion {
+    pub fn from_level(level: u8) -> Option<ProtocolVersion> {
+        match level {
+            3 => Some(ProtocolVersion::V31),
+            4 => Some(ProtocolVersion::V311),
+            5 => Some(ProtocolVersion::V5),
+            _ => None,
+        }
+    }
+
+    /// Version named by a CONNECT protocol name and level, if the pair is a
+    /// valid one: `"MQIsdp"` with level 3, or `"MQTT"` with level 4 or 5.
+    pub fn from_protocol(name: &str, level: u8) -> Option<ProtocolVersion> {
+        match ProtocolVersion::from_level(level) {
+            Some(version) if version.protocol_name() == name => Some(version),
+            _ => None,
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Protocol name a CONNECT at this version carries.
+    pub fn protocol_name(&self) -> &'static str {
+        match *self {
+            ProtocolVersion::V31 => "MQIsdp",
+            ProtocolVersion::V311 | ProtocolVersion::V5 => "MQTT",
+        }
+    }
+
+    /// Whether packets carry MQTT 5 properties; 3.1 and 3.1.1 share a layout.
+    pub fn has_properties(&self) -> bool {
+        *self == ProtocolVersion::V5
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_protocol(){
+        assert_eq!(ProtocolVersion::from_protocol("MQIsdp", 3), Some(ProtocolVersion::V31));
+        assert_eq!(ProtocolVersion::from_protocol("MQTT", 4), Some(ProtocolVersion::V311));
+        assert_eq!(ProtocolVersion::from_protocol("MQTT", 5), Some(ProtocolVersion::V5));
+        assert_eq!(ProtocolVersion::from_protocol("MQTT", 3), None);
+        assert_eq!(ProtocolVersion::from_protocol("MQIsdp", 4), None);
+        assert_eq!(ProtocolVersion::from_protocol("MQTT", 6), None);
+    }
+}
@@ -1,5 +1,5 @@
-use bytes::BytesMut;
-use {Encodable, Decodable};
+use bytes::{BytesMut, BufMut};
+use {Encodable, Decodable, DecodeContext, EncodeContext};
 
 error_chain!{
     types {
@@ -7,7 +7,7 @@ error_chain!{
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum QualityOfService{
     Level0 = 0,
     Level1 = 1,
@@ -15,32 +15,36 @@ pub enum QualityOfService{
 }
 
 
-impl<'a> Decodable<'a> for QualityOfService{
-    type Error = QualityOfServiceError;
-    type Cond = ();
-
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>{
-        let flag: u8 = Decodable::decode(byte).chain_err(||"decode quality of service byte fail")?;
-        let mut result = match flag {
+impl QualityOfService {
+    pub fn from_u8(level: u8) -> Result<QualityOfService, QualityOfServiceError> {
+        match level {
             0 => Ok(QualityOfService::Level0),
             1 => Ok(QualityOfService::Level1),
             2 => Ok(QualityOfService::Level2),
             _ => bail!("QualityOfService unavaiable u8"),
-        };
-        result
+        }
+    }
+}
+
+impl<'a> Decodable<'a> for QualityOfService{
+    type Error = QualityOfServiceError;
+
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error>{
+        let flag: u8 = Decodable::decode_with(byte, ctx).chain_err(||"decode quality of service byte fail")?;
+        QualityOfService::from_u8(flag)
     }
 }
 
 impl Encodable for QualityOfService{
     type Error = QualityOfServiceError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error>{
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error>{
         match *self {
-            QualityOfService::Level0 => Ok(vec![0]),
-            QualityOfService::Level1 => Ok(vec![1]),
-            QualityOfService::Level2 => Ok(vec![2]),
+            QualityOfService::Level0 => dst.put_u8(0),
+            QualityOfService::Level1 => dst.put_u8(1),
+            QualityOfService::Level2 => dst.put_u8(2),
         }
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -0,0 +1,234 @@
+use bytes::{BytesMut, BufMut};
+use {Encodable, Decodable, DecodeContext, EncodeContext};
+use qos::QualityOfService;
+
+error_chain!{
+    types{
+        ReasonCodeError, ErrorKind, ResultExt, ReasonCodeResult;
+    }
+
+    errors{
+        UnknownReasonCode(code: u8)
+    }
+
+    links{
+        PacketError(::PacketError, ::ErrorKind);
+    }
+}
+
+/// Declares the MQTT 5 reason codes one packet may carry (MQTT 5.0 §2.4).
+/// The first variant is the default, which packets leave out when they can.
+macro_rules! reason_code {
+    ($(#[$attr:meta])* pub enum $name:ident { $default:ident = $default_code:expr, $($variant:ident = $code:expr,)* }) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $default = $default_code,
+            $($variant = $code,)*
+        }
+
+        impl $name {
+            pub fn from_u8(code: u8) -> Result<$name, ReasonCodeError> {
+                match code {
+                    $default_code => Ok($name::$default),
+                    $($code => Ok($name::$variant),)*
+                    _ => bail!(ErrorKind::UnknownReasonCode(code)),
+                }
+            }
+
+            /// Codes below 0x80 report success, the others failure.
+            pub fn is_success(&self) -> bool {
+                (*self as u8) < 0x80
+            }
+
+            pub fn is_default(&self) -> bool {
+                *self == $name::$default
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> $name {
+                $name::$default
+            }
+        }
+
+        impl<'a> Decodable<'a> for $name {
+            type Error = ReasonCodeError;
+
+            fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+                let code: u8 = Decodable::decode_with(byte, ctx)?;
+                $name::from_u8(code)
+            }
+        }
+
+        impl Encodable for $name {
+            type Error = ReasonCodeError;
+
+            fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+                dst.put_u8(*self as u8);
+                Ok(())
+            }
+
+            fn encode_length(&self) -> Result<u32, Self::Error> {
+                Ok(1u32)
+            }
+        }
+    }
+}
+
+reason_code!{
+    /// Reason code of an MQTT 5 PUBACK.
+    pub enum PubAckReasonCode {
+        Success = 0x00,
+        NoMatchingSubscribers = 0x10,
+        UnspecifiedError = 0x80,
+        ImplementationSpecificError = 0x83,
+        NotAuthorized = 0x87,
+        TopicNameInvalid = 0x90,
+        PacketIdentifierInUse = 0x91,
+        QuotaExceeded = 0x97,
+        PayloadFormatInvalid = 0x99,
+    }
+}
+
+reason_code!{
+    /// Reason code of an MQTT 5 PUBREC.
+    pub enum PubRecReasonCode {
+        Success = 0x00,
+        NoMatchingSubscribers = 0x10,
+        UnspecifiedError = 0x80,
+        ImplementationSpecificError = 0x83,
+        NotAuthorized = 0x87,
+        TopicNameInvalid = 0x90,
+        PacketIdentifierInUse = 0x91,
+        QuotaExceeded = 0x97,
+        PayloadFormatInvalid = 0x99,
+    }
+}
+
+reason_code!{
+    /// Reason code of an MQTT 5 PUBREL.
+    pub enum PubRelReasonCode {
+        Success = 0x00,
+        PacketIdentifierNotFound = 0x92,
+    }
+}
+
+reason_code!{
+    /// Reason code of an MQTT 5 PUBCOMP.
+    pub enum PubCompReasonCode {
+        Success = 0x00,
+        PacketIdentifierNotFound = 0x92,
+    }
+}
+
+reason_code!{
+    /// Reason code for one subscription of an MQTT 5 SUBACK.
+    pub enum SubAckReasonCode {
+        GrantedQos0 = 0x00,
+        GrantedQos1 = 0x01,
+        GrantedQos2 = 0x02,
+        UnspecifiedError = 0x80,
+        ImplementationSpecificError = 0x83,
+        NotAuthorized = 0x87,
+        TopicFilterInvalid = 0x8F,
+        PacketIdentifierInUse = 0x91,
+        QuotaExceeded = 0x97,
+        SharedSubscriptionsNotSupported = 0x9E,
+        SubscriptionIdentifiersNotSupported = 0xA1,
+        WildcardSubscriptionsNotSupported = 0xA2,
+    }
+}
+
+impl SubAckReasonCode {
+    /// The maximum QoS granted, or `None` if the subscription was refused.
+    pub fn granted_qos(&self) -> Option<QualityOfService> {
+        match *self {
+            SubAckReasonCode::GrantedQos0 => Some(QualityOfService::Level0),
+            SubAckReasonCode::GrantedQos1 => Some(QualityOfService::Level1),
+            SubAckReasonCode::GrantedQos2 => Some(QualityOfService::Level2),
+            _ => None,
+        }
+    }
+}
+
+reason_code!{
+    /// Reason code for one topic filter of an MQTT 5 UNSUBACK.
+    pub enum UnSubAckReasonCode {
+        Success = 0x00,
+        NoSubscriptionExisted = 0x11,
+        UnspecifiedError = 0x80,
+        ImplementationSpecificError = 0x83,
+        NotAuthorized = 0x87,
+        TopicFilterInvalid = 0x8F,
+        PacketIdentifierInUse = 0x91,
+    }
+}
+
+reason_code!{
+    /// Reason code of an MQTT 5 DISCONNECT.
+    pub enum DisconnectReasonCode {
+        NormalDisconnection = 0x00,
+        DisconnectWithWillMessage = 0x04,
+        UnspecifiedError = 0x80,
+        MalformedPacket = 0x81,
+        ProtocolError = 0x82,
+        ImplementationSpecificError = 0x83,
+        NotAuthorized = 0x87,
+        ServerBusy = 0x89,
+        ServerShuttingDown = 0x8B,
+        KeepAliveTimeout = 0x8D,
+        SessionTakenOver = 0x8E,
+        TopicFilterInvalid = 0x8F,
+        TopicNameInvalid = 0x90,
+        ReceiveMaximumExceeded = 0x93,
+        TopicAliasInvalid = 0x94,
+        PacketTooLarge = 0x95,
+        MessageRateTooHigh = 0x96,
+        QuotaExceeded = 0x97,
+        AdministrativeAction = 0x98,
+        PayloadFormatInvalid = 0x99,
+        RetainNotSupported = 0x9A,
+        QosNotSupported = 0x9B,
+        UseAnotherServer = 0x9C,
+        ServerMoved = 0x9D,
+        SharedSubscriptionsNotSupported = 0x9E,
+        ConnectionRateExceeded = 0x9F,
+        MaximumConnectTime = 0xA0,
+        SubscriptionIdentifiersNotSupported = 0xA1,
+        WildcardSubscriptionsNotSupported = 0xA2,
+    }
+}
+
+reason_code!{
+    /// Reason code of an MQTT 5 AUTH.
+    pub enum AuthReasonCode {
+        Success = 0x00,
+        ContinueAuthentication = 0x18,
+        ReAuthenticate = 0x19,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reason_code_decode_encode(){
+        let mut bytes = BytesMut::from(&[0x10, 0x92, 0x8E][..]);
+        assert_eq!(PubAckReasonCode::decode(&mut bytes).unwrap(), PubAckReasonCode::NoMatchingSubscribers);
+        assert_eq!(PubRelReasonCode::decode(&mut bytes).unwrap(), PubRelReasonCode::PacketIdentifierNotFound);
+        assert_eq!(DisconnectReasonCode::decode(&mut bytes).unwrap(), DisconnectReasonCode::SessionTakenOver);
+        assert_eq!(UnSubAckReasonCode::NoSubscriptionExisted.encode().unwrap(), vec![0x11]);
+
+        assert!(PubCompReasonCode::from_u8(0x10).is_err());
+        assert!(DisconnectReasonCode::default().is_success());
+        assert_eq!(AuthReasonCode::from_u8(0x19).unwrap(), AuthReasonCode::ReAuthenticate);
+        assert_eq!(SubAckReasonCode::from_u8(0x9E).unwrap(), SubAckReasonCode::SharedSubscriptionsNotSupported);
+        assert_eq!(SubAckReasonCode::GrantedQos1.granted_qos(), Some(QualityOfService::Level1));
+        assert_eq!(SubAckReasonCode::NotAuthorized.granted_qos(), None);
+        assert!(SubAckReasonCode::GrantedQos2.is_success() && !SubAckReasonCode::GrantedQos2.is_default());
+        assert!(!SubAckReasonCode::NotAuthorized.is_success());
+        assert!(PubAckReasonCode::NoMatchingSubscribers.is_success());
+    }
+}
@@ -1,5 +1,5 @@
-use {Encodable, Decodable};
-use bytes::BytesMut;
+use {Encodable, Decodable, DecodeContext, EncodeContext, ProtocolVersion};
+use bytes::{BytesMut, BufMut};
 
 error_chain!{
     types{
@@ -7,8 +7,9 @@ error_chain!{
     }
 }
 
+/// `Failure` was added in 3.1.1; a 3.1 SUBACK only grants QoS levels.
 //#[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SubscribeReturnCode{
     MaximumQos0 = 0x00,
     MaximumQos1 = 0x01,
@@ -18,14 +19,13 @@ pub enum SubscribeReturnCode{
 
 impl<'a> Decodable<'a> for SubscribeReturnCode {
     type Error = SubscribeReturnCodeError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error>{
-        let code = match Decodable::decode(byte).chain_err(||"decode subscribe reture code u8 error")? {
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error>{
+        let code = match Decodable::decode_with(byte, ctx).chain_err(||"decode subscribe reture code u8 error")? {
             0u8 => SubscribeReturnCode::MaximumQos0, 
             1 => SubscribeReturnCode::MaximumQos1, 
             2 => SubscribeReturnCode::MaximumQos2, 
-            128 => SubscribeReturnCode::MaximumQos0, 
+            128 if ctx.protocol_version != ProtocolVersion::V31 => SubscribeReturnCode::Failure, 
             _ => bail!("unavaiable subscribe return code"),
         };
         Ok(code)
@@ -34,11 +34,10 @@ impl<'a> Decodable<'a> for SubscribeReturnCode {
 
 impl Encodable for SubscribeReturnCode {
     type Error = SubscribeReturnCodeError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        let vec = vec![*self as u8];
-        Ok(vec)
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, _ctx: &EncodeContext) -> Result<(), Self::Error> {
+        dst.put_u8(*self as u8);
+        Ok(())
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
@@ -56,5 +55,9 @@ mod test {
         let vec = return_code.encode();
 
         //println!("{:?}", vec);
+        assert_eq!(vec.unwrap(), vec![0x01]);
+
+        let mut bytes = BytesMut::from(&[0x80][..]);
+        assert_eq!(SubscribeReturnCode::decode(&mut bytes).unwrap(), SubscribeReturnCode::Failure);
     }
 }
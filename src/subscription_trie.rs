@@ -0,0 +1,217 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+
+use qos::QualityOfService;
+use topic_filter::TopicFilter;
+use topic_name::TopicName;
+
+/// Subscriptions indexed by topic filter level, for routing a PUBLISH to its
+/// subscribers without testing every filter.
+///
+/// Each filter level is a child of the level before it, with `+` and `#`
+/// stored as ordinary children: topic names cannot contain them, so only a
+/// wildcard filter ever reaches those branches.
+#[derive(Debug, Clone)]
+pub struct SubscriptionTrie<S> {
+    root: Node<S>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node<S> {
+    children: HashMap<String, Node<S>>,
+    subscribers: HashMap<S, QualityOfService>,
+}
+
+impl<S: Eq + Hash> Node<S> {
+    fn new() -> Node<S> {
+        Node {
+            children: HashMap::new(),
+            subscribers: HashMap::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.subscribers.is_empty()
+    }
+
+    /// Removes `subscriber` from the node at the end of `levels`, dropping
+    /// the nodes left empty on the way back up.
+    fn remove<'a, I>(&mut self, mut levels: I, subscriber: &S) -> Option<QualityOfService>
+        where I: Iterator<Item = &'a str>
+    {
+        let level = match levels.next() {
+            Some(level) => level,
+            None => return self.subscribers.remove(subscriber),
+        };
+        let (qos, empty) = match self.children.get_mut(level) {
+            Some(child) => (child.remove(levels, subscriber), child.is_empty()),
+            None => return None,
+        };
+        if empty {
+            self.children.remove(level);
+        }
+        qos
+    }
+}
+
+impl<S: Eq + Hash + Clone> Node<S> {
+    fn collect(&self, levels: &[&str], system: bool, matches: &mut HashMap<S, QualityOfService>) {
+        // a first level wildcard never matches a topic name starting with `$`
+        if !system {
+            if let Some(child) = self.children.get("#") {
+                add(matches, &child.subscribers);
+            }
+        }
+
+        let (level, rest) = match levels.split_first() {
+            Some((level, rest)) => (level, rest),
+            None => return add(matches, &self.subscribers),
+        };
+        if let Some(child) = self.children.get(*level) {
+            child.collect(rest, false, matches);
+        }
+        if !system {
+            if let Some(child) = self.children.get("+") {
+                child.collect(rest, false, matches);
+            }
+        }
+    }
+}
+
+/// Merges `subscribers` into `matches`, keeping the higher QoS of a
+/// subscriber matched by several filters.
+fn add<S: Eq + Hash + Clone>(matches: &mut HashMap<S, QualityOfService>, subscribers: &HashMap<S, QualityOfService>) {
+    for (subscriber, &qos) in subscribers {
+        let granted = matches.entry(subscriber.clone()).or_insert(qos);
+        *granted = cmp::max(*granted, qos);
+    }
+}
+
+impl<S: Eq + Hash + Clone> SubscriptionTrie<S> {
+    pub fn new() -> SubscriptionTrie<S> {
+        SubscriptionTrie {
+            root: Node::new(),
+            len: 0,
+        }
+    }
+
+    /// Subscribes `subscriber` to `topic_filter`, returning the QoS of the
+    /// subscription it replaces.
+    pub fn insert(&mut self, topic_filter: &TopicFilter, subscriber: S, qos: QualityOfService) -> Option<QualityOfService> {
+        let mut node = &mut self.root;
+        for level in topic_filter.levels() {
+            node = node.children.entry(level.into()).or_insert_with(Node::new);
+        }
+        match node.subscribers.entry(subscriber) {
+            Entry::Occupied(mut entry) => Some(entry.insert(qos)),
+            Entry::Vacant(entry) => {
+                entry.insert(qos);
+                self.len += 1;
+                None
+            },
+        }
+    }
+
+    /// Unsubscribes `subscriber` from `topic_filter`, returning the QoS it was
+    /// subscribed with.
+    pub fn remove(&mut self, topic_filter: &TopicFilter, subscriber: &S) -> Option<QualityOfService> {
+        let qos = self.root.remove(topic_filter.levels(), subscriber);
+        if qos.is_some() {
+            self.len -= 1;
+        }
+        qos
+    }
+
+    /// Every subscriber with a filter matching `topic_name`, once each, with
+    /// the highest QoS among its matching subscriptions.
+    pub fn matches(&self, topic_name: &TopicName) -> HashMap<S, QualityOfService> {
+        let levels: Vec<&str> = topic_name.levels().collect();
+        let mut matches = HashMap::new();
+        self.root.collect(&levels, topic_name.is_system(), &mut matches);
+        matches
+    }
+
+    /// Number of `(topic filter, subscriber)` subscriptions.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<S: Eq + Hash + Clone> Default for SubscriptionTrie<S> {
+    fn default() -> SubscriptionTrie<S> {
+        SubscriptionTrie::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(topic_filter: &str) -> TopicFilter {
+        TopicFilter::new(topic_filter).unwrap()
+    }
+
+    fn matches(trie: &SubscriptionTrie<&'static str>, topic_name: &str) -> Vec<(&'static str, QualityOfService)> {
+        let mut matches: Vec<_> = trie.matches(&TopicName::new(topic_name).unwrap()).into_iter().collect();
+        matches.sort();
+        matches
+    }
+
+    #[test]
+    fn test_subscription_trie_matches(){
+        let mut trie = SubscriptionTrie::new();
+        trie.insert(&filter("sport/tennis/player1"), "a", QualityOfService::Level0);
+        trie.insert(&filter("sport/tennis/+"), "b", QualityOfService::Level1);
+        trie.insert(&filter("sport/#"), "c", QualityOfService::Level2);
+        trie.insert(&filter("#"), "d", QualityOfService::Level0);
+        trie.insert(&filter("+/+"), "e", QualityOfService::Level1);
+        trie.insert(&filter("$SYS/#"), "f", QualityOfService::Level0);
+        assert_eq!(trie.len(), 6);
+
+        assert_eq!(matches(&trie, "sport/tennis/player1"), vec![("a", QualityOfService::Level0), ("b", QualityOfService::Level1),
+                                                                ("c", QualityOfService::Level2), ("d", QualityOfService::Level0)]);
+        assert_eq!(matches(&trie, "sport"), vec![("c", QualityOfService::Level2), ("d", QualityOfService::Level0)]);
+        assert_eq!(matches(&trie, "sport/tennis"), vec![("c", QualityOfService::Level2), ("d", QualityOfService::Level0),
+                                                        ("e", QualityOfService::Level1)]);
+        assert_eq!(matches(&trie, "/finance"), vec![("d", QualityOfService::Level0), ("e", QualityOfService::Level1)]);
+        assert_eq!(matches(&trie, "$SYS/broker"), vec![("f", QualityOfService::Level0)]);
+        assert_eq!(matches(&trie, "news/a/b"), vec![("d", QualityOfService::Level0)]);
+    }
+
+    #[test]
+    fn test_subscription_trie_overlapping_filters(){
+        let mut trie = SubscriptionTrie::new();
+        trie.insert(&filter("a/+"), "x", QualityOfService::Level0);
+        trie.insert(&filter("a/#"), "x", QualityOfService::Level2);
+        trie.insert(&filter("a/b"), "x", QualityOfService::Level1);
+        assert_eq!(matches(&trie, "a/b"), vec![("x", QualityOfService::Level2)]);
+
+        assert_eq!(trie.insert(&filter("a/#"), "x", QualityOfService::Level0), Some(QualityOfService::Level2));
+        assert_eq!(trie.len(), 3);
+        assert_eq!(matches(&trie, "a/b"), vec![("x", QualityOfService::Level1)]);
+    }
+
+    #[test]
+    fn test_subscription_trie_remove(){
+        let mut trie = SubscriptionTrie::new();
+        trie.insert(&filter("a/b/c"), "x", QualityOfService::Level1);
+        trie.insert(&filter("a/+/c"), "y", QualityOfService::Level1);
+        assert_eq!(trie.remove(&filter("a/b"), &"x"), None);
+        assert_eq!(trie.remove(&filter("a/b/c"), &"y"), None);
+        assert_eq!(trie.remove(&filter("a/b/c"), &"x"), Some(QualityOfService::Level1));
+        assert_eq!(trie.remove(&filter("a/b/c"), &"x"), None);
+        assert_eq!(matches(&trie, "a/b/c"), vec![("y", QualityOfService::Level1)]);
+        assert!(!trie.root.children["a"].children.contains_key("b"));
+
+        assert_eq!(trie.remove(&filter("a/+/c"), &"y"), Some(QualityOfService::Level1));
+        assert!(trie.is_empty());
+        assert!(trie.root.is_empty());
+    }
+}
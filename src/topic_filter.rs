@@ -1,34 +1,294 @@
-use bytes::BytesMut;
-use {Encodable, Decodable};
+use std::str;
+use bytes::{BytesMut, BufMut};
+use {Encodable, Decodable, DecodeContext, EncodeContext};
+use topic_name::TopicName;
 
 error_chain!{
     types{
         TopicFilterError, ErrorKind, ResultExt, TopicFilterResult;
     }
+
+    errors{
+        EmptyTopicFilter
+        InvalidMultiLevelWildcard(filter: String)
+        InvalidSingleLevelWildcard(filter: String)
+    }
 }
 
-#[derive(Debug)]
-pub struct TopicFilter(pub String);
+/// A topic filter checked against MQTT 3.1.1 §4.7: at least one character,
+/// `#` only as the whole last level and `+` only as a whole level.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicFilter(String);
+
+impl TopicFilter {
+    pub fn new<T: Into<String>>(topic_filter: T) -> Result<TopicFilter, TopicFilterError> {
+        let topic_filter = topic_filter.into();
+        TopicFilter::validate(&topic_filter)?;
+        Ok(TopicFilter(topic_filter))
+    }
+
+    /// Checks `topic_filter` without taking ownership of it.
+    pub fn validate(topic_filter: &str) -> Result<(), TopicFilterError> {
+        if topic_filter.is_empty() {
+            bail!(ErrorKind::EmptyTopicFilter);
+        }
+
+        let mut levels = topic_filter.split('/').peekable();
+        while let Some(level) = levels.next() {
+            if level.contains('#') && (level != "#" || levels.peek().is_some()) {
+                bail!(ErrorKind::InvalidMultiLevelWildcard(topic_filter.into()));
+            }
+            if level.contains('+') && level != "+" {
+                bail!(ErrorKind::InvalidSingleLevelWildcard(topic_filter.into()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The levels between `/` separators, wildcards included.
+    pub fn levels(&self) -> str::Split<'_, char> {
+        self.0.split('/')
+    }
+
+    /// The canonical form of the filter, equal for filters that match the
+    /// same topic names. A topic name is never empty, so `#` cannot match
+    /// the parent level of `+/#` or `/#`: the first is written `#` and the
+    /// second `/+/#`. No other two valid filters are equivalent.
+    pub fn canonical(&self) -> TopicFilter {
+        match self.0.as_str() {
+            "+/#" => TopicFilter("#".into()),
+            "/#" => TopicFilter("/+/#".into()),
+            _ => self.clone(),
+        }
+    }
+
+    /// Whether every topic name this filter matches is also matched by
+    /// `other`.
+    pub fn is_subset_of(&self, other: &TopicFilter) -> bool {
+        let (filter, other) = (self.canonical(), other.canonical());
+        let levels: Vec<&str> = filter.levels().collect();
+        let other_levels: Vec<&str> = other.levels().collect();
+
+        for i in 0.. {
+            match (levels.get(i), other_levels.get(i)) {
+                (Some(level), Some(&"#")) => return i > 0 || !is_system_level(level),
+                (None, Some(&"#")) => return true,
+                (Some(&"#"), _) => return false,
+                (None, None) => return true,
+                (None, Some(_)) | (Some(_), None) => return false,
+                (Some(&"+"), Some(&"+")) => {},
+                (Some(&"+"), Some(_)) => return false,
+                (Some(level), Some(&"+")) => if i == 0 && is_system_level(level) {
+                    return false;
+                },
+                (Some(level), Some(other_level)) => if level != other_level {
+                    return false;
+                },
+            }
+        }
+        unreachable!()
+    }
+
+    /// Whether some topic name is matched by both this filter and `other`.
+    pub fn intersects(&self, other: &TopicFilter) -> bool {
+        let (filter, other) = (self.canonical(), other.canonical());
+        let levels: Vec<&str> = filter.levels().collect();
+        let other_levels: Vec<&str> = other.levels().collect();
+
+        for i in 0.. {
+            match (levels.get(i), other_levels.get(i)) {
+                (Some(&"#"), Some(level)) | (Some(level), Some(&"#")) => return i > 0 || !is_system_level(level),
+                (Some(&"#"), None) | (None, Some(&"#")) => return true,
+                (None, None) => return true,
+                (None, Some(_)) | (Some(_), None) => return false,
+                (Some(&"+"), Some(level)) | (Some(level), Some(&"+")) => if i == 0 && is_system_level(level) {
+                    return false;
+                },
+                (Some(level), Some(other_level)) => if level != other_level {
+                    return false;
+                },
+            }
+        }
+        unreachable!()
+    }
+
+    /// Whether `topic_name` matches this filter. A `#` also matches the parent
+    /// level, and a wildcard in the first level never matches a topic name
+    /// starting with `$`.
+    pub fn matches(&self, topic_name: &TopicName) -> bool {
+        if topic_name.is_system() && (self.0.starts_with('+') || self.0.starts_with('#')) {
+            return false;
+        }
+
+        let mut topic_levels = topic_name.levels();
+        for level in self.levels() {
+            match (level, topic_levels.next()) {
+                ("#", _) => return true,
+                (_, None) => return false,
+                ("+", Some(_)) => {},
+                (level, Some(topic_level)) => if level != topic_level {
+                    return false;
+                },
+            }
+        }
+        topic_levels.next().is_none()
+    }
+}
+
+/// Whether a first filter level only matches topic names starting with `$`,
+/// which first level wildcards do not match.
+fn is_system_level(level: &str) -> bool {
+    level.starts_with('$')
+}
 
 impl<'a> Decodable<'a> for TopicFilter {
     type Error = TopicFilterError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, _: Option<Self::Cond>) -> Result<Self, Self::Error> {
-        Ok(TopicFilter(Decodable::decode(byte).chain_err(||"decode topic filter fail")?))
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error> {
+        let topic_filter: String = Decodable::decode_with(byte, ctx).chain_err(||"decode topic filter fail")?;
+        TopicFilter::new(topic_filter)
     }
 }
 
 
 impl Encodable for TopicFilter {
     type Error = TopicFilterError;
-    type Cond = ();
 
-    fn encode_with(&self, _: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        self.0.encode().chain_err(||"encode topic filter fail")
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.0.encode_to_with(dst, ctx).chain_err(||"encode topic filter fail")
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error>{
         self.0.encode_length().chain_err(||"encode topic filter length fail")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(topic_filter: &str, topic_name: &str) -> bool {
+        TopicFilter::new(topic_filter).unwrap().matches(&TopicName::new(topic_name).unwrap())
+    }
+
+    #[test]
+    fn test_topic_filter_validation(){
+        for topic_filter in &["#", "+", "/", "sport/#", "sport/+/player1", "+/+", "/+", "+/tennis/#", "$SYS/#"] {
+            assert!(TopicFilter::new(*topic_filter).is_ok(), "{}", topic_filter);
+        }
+        match TopicFilter::new("") {
+            Err(TopicFilterError(ErrorKind::EmptyTopicFilter, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        for topic_filter in &["sport/tennis#", "sport/tennis/#/ranking", "#/", "##"] {
+            match TopicFilter::new(*topic_filter) {
+                Err(TopicFilterError(ErrorKind::InvalidMultiLevelWildcard(_), _)) => {},
+                other => panic!("unexpected {:?} for {}", other, topic_filter),
+            }
+        }
+        for topic_filter in &["sport+", "sport/+tennis", "++"] {
+            match TopicFilter::new(*topic_filter) {
+                Err(TopicFilterError(ErrorKind::InvalidSingleLevelWildcard(_), _)) => {},
+                other => panic!("unexpected {:?} for {}", other, topic_filter),
+            }
+        }
+
+        let mut bytes = BytesMut::from(&[0x00, 0x02, b'a', b'#'][..]);
+        assert!(TopicFilter::decode(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn test_topic_filter_matches(){
+        assert!(matches("sport/tennis/player1/#", "sport/tennis/player1"));
+        assert!(matches("sport/tennis/player1/#", "sport/tennis/player1/ranking"));
+        assert!(matches("sport/tennis/player1/#", "sport/tennis/player1/score/wimbledon"));
+        assert!(matches("sport/#", "sport"));
+        assert!(matches("#", "sport/tennis"));
+        assert!(matches("#", "/"));
+
+        assert!(matches("sport/tennis/+", "sport/tennis/player1"));
+        assert!(!matches("sport/tennis/+", "sport/tennis/player1/ranking"));
+        assert!(!matches("sport/+", "sport"));
+        assert!(matches("sport/+", "sport/"));
+        assert!(matches("+/+", "/finance"));
+        assert!(matches("/+", "/finance"));
+        assert!(!matches("+", "/finance"));
+        assert!(matches("+/tennis/#", "sport/tennis"));
+
+        assert!(matches("sport/tennis", "sport/tennis"));
+        assert!(!matches("sport/tennis", "sport/Tennis"));
+        assert!(!matches("sport/tennis", "sport/tennis/"));
+        assert!(!matches("sport", "sport/tennis"));
+
+        assert!(!matches("#", "$SYS/monitor"));
+        assert!(!matches("+/monitor/Clients", "$SYS/monitor/Clients"));
+        assert!(matches("$SYS/#", "$SYS/monitor/Clients"));
+        assert!(matches("$SYS/monitor/+", "$SYS/monitor/Clients"));
+    }
+
+    #[test]
+    fn test_topic_filter_canonical(){
+        let filter = |topic_filter| TopicFilter::new(topic_filter).unwrap();
+        assert_eq!(filter("+/#").canonical(), filter("#"));
+        assert_eq!(filter("/#").canonical(), filter("/+/#"));
+        assert_eq!(filter("a/+/#").canonical(), filter("a/+/#"));
+        assert_eq!(filter("+/+/#").canonical(), filter("+/+/#"));
+    }
+
+    #[test]
+    fn test_topic_filter_is_subset_of(){
+        let subset = |topic_filter, other| TopicFilter::new(topic_filter).unwrap().is_subset_of(&TopicFilter::new(other).unwrap());
+        assert!(subset("a/+/c", "a/#"));
+        assert!(subset("a/+/c", "a/+/+"));
+        assert!(subset("a/b/c", "a/+/c"));
+        assert!(subset("a", "a/#"));
+        assert!(subset("a/#", "a/#"));
+        assert!(subset("a/b/#", "a/#"));
+        assert!(subset("#", "+/#"));
+        assert!(subset("+/#", "#"));
+        assert!(subset("/", "+/+"));
+        assert!(subset("/#", "+/+/#"));
+
+        assert!(!subset("a/#", "a/+/c"));
+        assert!(!subset("a/+", "a/b"));
+        assert!(!subset("a/#", "a/+/#"));
+        assert!(!subset("a/b", "a/b/c"));
+        assert!(!subset("a/b/c", "a/b"));
+        assert!(!subset("#", "+"));
+
+        assert!(!subset("$SYS/a", "#"));
+        assert!(!subset("$SYS/a", "+/a"));
+        assert!(subset("$SYS/a", "$SYS/#"));
+        assert!(subset("a/$b", "+/+"));
+        assert!(subset("#", "#"));
+    }
+
+    #[test]
+    fn test_topic_filter_intersects(){
+        let intersects = |topic_filter, other| {
+            let (topic_filter, other) = (TopicFilter::new(topic_filter).unwrap(), TopicFilter::new(other).unwrap());
+            assert_eq!(topic_filter.intersects(&other), other.intersects(&topic_filter));
+            topic_filter.intersects(&other)
+        };
+        assert!(intersects("a/+/c", "a/b/+"));
+        assert!(intersects("a/#", "+/b"));
+        assert!(intersects("a/#", "a"));
+        assert!(intersects("#", "+/+/+"));
+        assert!(intersects("+", "a"));
+
+        assert!(!intersects("a/+", "b/+"));
+        assert!(!intersects("a/+", "a/+/c"));
+        assert!(!intersects("a/b/#", "a/c"));
+        assert!(!intersects("+", "a/b"));
+        assert!(!intersects("+", "/#"));
+
+        assert!(!intersects("#", "$SYS/a"));
+        assert!(!intersects("+/a", "$SYS/a"));
+        assert!(intersects("$SYS/#", "$SYS/+"));
+        assert!(intersects("+/$b", "a/+"));
+    }
+}
@@ -1,30 +1,86 @@
-use bytes::BytesMut;
-use {Encodable, Decodable};
+use std::str;
+use bytes::{BytesMut, BufMut};
+use {Encodable, Decodable, ByteStr, DecodeContext, EncodeContext};
 
-#[derive(Debug, Clone)]
-pub struct TopicName(pub String);
+/// A topic name checked against MQTT 3.1.1 §4.7: at least one character, at
+/// most 65535 bytes, and neither wildcards nor U+0000.
+///
+/// Stored as a `ByteStr`, so a decoded topic name shares the packet buffer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicName(ByteStr);
+
+impl TopicName {
+    pub fn new<T: Into<ByteStr>>(topic_name: T) -> Result<TopicName, TopicNameError> {
+        let topic_name = topic_name.into();
+        TopicName::validate(&topic_name)?;
+        Ok(TopicName(topic_name))
+    }
+
+    /// Checks `topic_name` without taking ownership of it.
+    pub fn validate(topic_name: &str) -> Result<(), TopicNameError> {
+        if topic_name.is_empty() {
+            bail!(ErrorKind::EmptyTopicName);
+        }
+        if topic_name.len() > u16::MAX as usize {
+            bail!(ErrorKind::TopicNameTooLong(topic_name.len()));
+        }
+        if topic_name.contains(['+', '#']) {
+            bail!(ErrorKind::WildcardInTopicName(topic_name.into()));
+        }
+        if topic_name.contains('\u{0}') {
+            bail!(ErrorKind::NullCharacter);
+        }
+        Ok(())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn as_byte_str(&self) -> &ByteStr {
+        &self.0
+    }
+
+    /// The levels between `/` separators; `"/a/"` has three, the outer two
+    /// empty.
+    pub fn levels(&self) -> str::Split<'_, char> {
+        self.0.split('/')
+    }
+
+    /// Whether the topic name starts with `$`, which first level wildcards do
+    /// not match.
+    pub fn is_system(&self) -> bool {
+        self.0.starts_with('$')
+    }
+}
 
 error_chain!{
     types{
         TopicNameError, ErrorKind, ResultExt, TopicNameResult;
     }
+
+    errors{
+        EmptyTopicName
+        TopicNameTooLong(len: usize)
+        WildcardInTopicName(topic_name: String)
+        NullCharacter
+    }
 }
 
 impl<'a> Decodable<'a> for TopicName {
     type Error = TopicNameError;
-    type Cond = ();
 
-    fn decode_with(byte: &mut BytesMut, decode_size: Option<Self::Cond>) -> Result<Self, Self::Error>{
-        Ok(TopicName(Decodable::decode(byte).chain_err(||"decode topic name string fail")?))
+    fn decode_with(byte: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, Self::Error>{
+        let topic_name: ByteStr = Decodable::decode_with(byte, ctx).chain_err(||"decode topic name string fail")?;
+        TopicName::new(topic_name)
     }
 }
 
 impl Encodable for TopicName {
     type Error = TopicNameError;
-    type Cond = ();
 
-    fn encode_with(&self, cond: Option<Self::Cond>) -> Result<Vec<u8>, Self::Error> {
-        self.0.encode().chain_err(||"encode topic name string fail")
+    fn encode_to_with<B: BufMut>(&self, dst: &mut B, ctx: &EncodeContext) -> Result<(), Self::Error> {
+        self.0.encode_to_with(dst, ctx).chain_err(||"encode topic name string fail")
     }
 
     fn encode_length(&self) -> Result<u32, Self::Error> {
@@ -32,4 +88,46 @@ impl Encodable for TopicName {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
 
+    #[test]
+    fn test_topic_name_validation(){
+        for topic_name in &["a", "/", "sport/tennis/player1", "$SYS/monitor", "sport/ tennis"] {
+            assert!(TopicName::new(*topic_name).is_ok(), "{}", topic_name);
+        }
+        match TopicName::new("") {
+            Err(TopicNameError(ErrorKind::EmptyTopicName, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        for topic_name in &["a/#", "+", "sport/+/player1", "a#b"] {
+            match TopicName::new(*topic_name) {
+                Err(TopicNameError(ErrorKind::WildcardInTopicName(_), _)) => {},
+                other => panic!("unexpected {:?} for {}", other, topic_name),
+            }
+        }
+        match TopicName::new("a\u{0}b") {
+            Err(TopicNameError(ErrorKind::NullCharacter, _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match TopicName::new(String::from_utf8(vec![b'a'; 65536]).unwrap()) {
+            Err(TopicNameError(ErrorKind::TopicNameTooLong(65536), _)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut bytes = BytesMut::from(&[0x00, 0x03, b'a', b'/', b'#'][..]);
+        assert!(TopicName::decode(&mut bytes).is_err());
+        let mut bytes = BytesMut::from(&[0x00, 0x00][..]);
+        assert!(TopicName::decode(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn test_topic_name_levels(){
+        let topic_name = TopicName::new("/finance/").unwrap();
+        assert_eq!(topic_name.levels().collect::<Vec<_>>(), vec!["", "finance", ""]);
+        assert_eq!(TopicName::new("a").unwrap().levels().count(), 1);
+        assert!(!topic_name.is_system());
+        assert!(TopicName::new("$SYS/broker").unwrap().is_system());
+    }
+}